@@ -3,14 +3,17 @@ mod build_move;
 mod color_graph;
 mod uncover_live;
 
-use crate::linearize::Test;
+use crate::linearize::{Cmp, Component, Context, Fragment, Function, Handler, Test};
+use crate::runtime::{STACK_BASE, STACK_SIZE};
 use crate::select_instructions::{self as prev, Index, Op};
 use crate::var::Var;
+use anyhow::{anyhow, Result};
+use petgraph::visit::Dfs;
 use build_interference::build_interference;
 use build_move::build_move;
 use std::collections::{HashMap, HashSet};
 use std::fmt::Display;
-use uncover_live::uncover_live;
+use uncover_live::{live_across_calls, uncover_live};
 
 use self::color_graph::color_graph;
 
@@ -20,6 +23,13 @@ pub type Graph = petgraph::Graph<Block, Jmp, petgraph::Directed, u32>;
 pub struct Program {
     pub blocks: Graph,
     pub tests: Vec<Test>,
+    pub functions: Vec<Function>,
+    pub handlers: Vec<Handler>,
+    pub teardown: Option<usize>,
+    /// The frame of each body that is not a function, by its entry block: tests, handlers and
+    /// the bodies of `after`. Functions enter their own frame, but these are run from outside,
+    /// which reserves it for them.
+    pub frames: HashMap<Index, u32>,
 }
 
 #[derive(Debug, Clone)]
@@ -39,7 +49,7 @@ pub enum Instruction {
         destination: Location,
     },
     Tellraw {
-        text: String,
+        components: Vec<Component>,
     },
     Command {
         text: String,
@@ -56,18 +66,85 @@ pub enum Instruction {
         set_location: Location,
         set_value: i64,
     },
-    ExecuteIfScoreEqualsSet {
+    ExecuteIfScoreCompareSet {
+        cmp: Cmp,
         a: Location,
         b: Location,
         set_location: Location,
         set_value: i64,
     },
-    ExecuteUnlessScoreEqualsSet {
+    ExecuteUnlessScoreCompareSet {
+        cmp: Cmp,
         a: Location,
         b: Location,
         set_location: Location,
         set_value: i64,
     },
+    ExecuteStoreResultScore {
+        location: Location,
+        command: String,
+    },
+    ExecuteStoreResultGetScore {
+        location: Location,
+        target: String,
+    },
+    Enter {
+        frame_size: u32,
+    },
+    LoadArg {
+        location: Location,
+        index: usize,
+    },
+    Call {
+        id: usize,
+        args: Vec<Location>,
+        result: Location,
+    },
+    CallIndirect {
+        function: Location,
+        args: Vec<Location>,
+        result: Location,
+    },
+    Return {
+        location: Location,
+        frame_size: u32,
+    },
+    TailCall {
+        id: usize,
+        args: Vec<Location>,
+        frame_size: u32,
+    },
+    TailCallIndirect {
+        function: Location,
+        args: Vec<Location>,
+        frame_size: u32,
+    },
+    Disarm {
+        location: Location,
+        frame_size: u32,
+    },
+    GetScore {
+        location: Location,
+        target: String,
+        objective: String,
+    },
+    SetScore {
+        target: String,
+        objective: String,
+        location: Location,
+    },
+    Schedule {
+        block: Index,
+        ticks: i64,
+    },
+    EarlyReturn {
+        location: Option<Location>,
+        frame_size: u32,
+    },
+    Asm {
+        template: Vec<Fragment>,
+        locations: Vec<Location>,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -82,19 +159,29 @@ pub enum Jmp {
         value: i64,
         block: Index,
     },
-    ExecuteIfScoreEqualsFunction {
+    ExecuteIfScoreCompareFunction {
+        cmp: Cmp,
         a: Location,
         b: Location,
         block: Index,
     },
-    ExecuteUnlessScoreEqualsFunction {
+    ExecuteUnlessScoreCompareFunction {
+        cmp: Cmp,
         a: Location,
         b: Location,
         block: Index,
     },
     Function {
         block: Index,
-    }
+    },
+    Join {
+        block: Index,
+    },
+    Resume,
+    ExecuteContextFunction {
+        context: Vec<Context>,
+        block: Index,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -197,11 +284,12 @@ impl Display for Register {
     }
 }
 
-pub fn assign_homes(program: prev::Program) -> Program {
+pub fn assign_homes(program: prev::Program) -> Result<Program> {
     let move_graph = build_move(&program);
     let annotated_program = uncover_live(&program);
     let interference_graph = build_interference(&annotated_program);
-    let color_map = color_graph(interference_graph, &move_graph);
+    let spilled = live_across_calls(&annotated_program);
+    let color_map = color_graph(interference_graph, &move_graph, &spilled);
     let location_map: HashMap<Var, Location> = color_map
         .iter()
         .map(|(var, color)| (var.clone(), Location::from_color(*color)))
@@ -210,23 +298,109 @@ pub fn assign_homes(program: prev::Program) -> Program {
     assign_homes_program(program, &location_map)
 }
 
-fn assign_homes_program(program: prev::Program, location_map: &HashMap<Var, Location>) -> Program {
+fn assign_homes_program(
+    program: prev::Program,
+    location_map: &HashMap<Var, Location>,
+) -> Result<Program> {
+    let mut bodies: Vec<(Index, String)> = Vec::new();
+    for test in &program.tests {
+        bodies.push((test.block, format!("Test \"{}\"", test.name)));
+    }
+    for handler in &program.handlers {
+        bodies.push((handler.block, format!("The on-{} handler", handler.event)));
+    }
+    for block in program.blocks.node_weights() {
+        for instr in &block.instrs {
+            if let prev::Instruction::Schedule { block, .. } = instr {
+                bodies.push((*block, "An after body".to_owned()));
+            }
+        }
+    }
+    let top_level = bodies.len();
+    for function in &program.functions {
+        bodies.push((function.block, format!("Function {}", function.id)));
+    }
+
+    // Each body reserves only as much of the stack as the variables in its own blocks need.
+    let mut block_frames = HashMap::new();
+    let mut frames = HashMap::new();
+    for (i, (entry, description)) in bodies.into_iter().enumerate() {
+        let mut body = Vec::new();
+        let mut dfs = Dfs::new(&program.blocks, entry);
+        while let Some(idx) = dfs.next(&program.blocks) {
+            body.push(idx);
+        }
+        let frame_size = body
+            .iter()
+            .flat_map(|idx| block_vars(&program.blocks, *idx))
+            .filter_map(|var| match location_map.get(&var) {
+                Some(Location::Stack { offset }) => Some(*offset),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        let room = STACK_SIZE - STACK_BASE;
+        if i64::from(frame_size) > room {
+            return Err(anyhow!(
+                "{description} needs {frame_size} stack slots for its variables, but only {room} fit on the stack"
+            ));
+        }
+        for idx in body {
+            block_frames.insert(idx, frame_size);
+        }
+        if i < top_level {
+            frames.insert(entry, frame_size);
+        }
+    }
+
     let blocks = program.blocks.map(
-        |_, n| assign_homes_block(n.clone(), location_map),
+        |idx, n| assign_homes_block(n.clone(), location_map, block_frames.get(&idx).copied().unwrap_or(0)),
         |_, e| assign_homes_jmp(e.clone(), location_map),
     );
-    Program {
+    Ok(Program {
         blocks,
         tests: program.tests,
+        functions: program.functions,
+        handlers: program.handlers,
+        teardown: program.teardown,
+        frames,
+    })
+}
+
+/// The variables a block uses, in its instructions and in the jumps out of it.
+fn block_vars(graph: &prev::Graph, idx: Index) -> HashSet<Var> {
+    let mut vars = HashSet::new();
+    for instr in &graph[idx].instrs {
+        vars.extend(read_set(instr));
+        vars.extend(write_set(instr));
     }
+    for jmp in graph.edges_directed(idx, petgraph::Direction::Outgoing).map(|e| e.weight()) {
+        match jmp {
+            prev::Jmp::ExecuteIfScoreMatchesFunction { var, .. }
+            | prev::Jmp::ExecuteUnlessScoreMatchesFunction { var, .. } => {
+                vars.insert(var.clone());
+            }
+            prev::Jmp::ExecuteIfScoreCompareFunction { a, b, .. }
+            | prev::Jmp::ExecuteUnlessScoreCompareFunction { a, b, .. } => {
+                vars.insert(a.clone());
+                vars.insert(b.clone());
+            }
+            _ => {}
+        }
+    }
+    vars
 }
 
-fn assign_homes_block(block: prev::Block, location_map: &HashMap<Var, Location>) -> Block {
+fn assign_homes_block(
+    block: prev::Block,
+    location_map: &HashMap<Var, Location>,
+    frame_size: u32,
+) -> Block {
     Block {
         instrs: block
             .instrs
             .iter()
-            .map(|instr| assign_homes_instr(instr.clone(), location_map))
+            .map(|instr| assign_homes_instr(instr.clone(), location_map, frame_size))
             .collect(),
     }
 }
@@ -234,6 +408,7 @@ fn assign_homes_block(block: prev::Block, location_map: &HashMap<Var, Location>)
 fn assign_homes_instr(
     instr: prev::Instruction,
     location_map: &HashMap<Var, Location>,
+    frame_size: u32,
 ) -> Instruction {
     match instr {
         prev::Instruction::Set { var, value } => Instruction::Set {
@@ -249,7 +424,7 @@ fn assign_homes_instr(
             source: location_map[&source].clone(),
             destination: location_map[&destination].clone(),
         },
-        prev::Instruction::Tellraw { text } => Instruction::Tellraw { text },
+        prev::Instruction::Tellraw { components } => Instruction::Tellraw { components },
         prev::Instruction::Command { text } => Instruction::Command { text },
         prev::Instruction::ExecuteIfScoreMatchesSet { var, value, set_var, set_value } => {
             Instruction::ExecuteIfScoreMatchesSet {
@@ -267,22 +442,92 @@ fn assign_homes_instr(
                 set_value,
             }
         }
-        prev::Instruction::ExecuteIfScoreEqualsSet { a, b, set_var, set_value } => {
-            Instruction::ExecuteIfScoreEqualsSet {
+        prev::Instruction::ExecuteIfScoreCompareSet { cmp, a, b, set_var, set_value } => {
+            Instruction::ExecuteIfScoreCompareSet {
+                cmp,
                 a: location_map[&a].clone(),
                 b: location_map[&b].clone(),
                 set_location: location_map[&set_var].clone(),
                 set_value
             }
         }
-        prev::Instruction::ExecuteUnlessScoreEqualsSet { a, b, set_var, set_value } => {
-            Instruction::ExecuteUnlessScoreEqualsSet {
+        prev::Instruction::ExecuteUnlessScoreCompareSet { cmp, a, b, set_var, set_value } => {
+            Instruction::ExecuteUnlessScoreCompareSet {
+                cmp,
                 a: location_map[&a].clone(),
                 b: location_map[&b].clone(),
                 set_location: location_map[&set_var].clone(),
                 set_value
             }
         }
+        prev::Instruction::ExecuteStoreResultScore { var, command } => {
+            Instruction::ExecuteStoreResultScore {
+                location: location_map[&var].clone(),
+                command,
+            }
+        }
+        prev::Instruction::ExecuteStoreResultGetScore { var, target } => {
+            Instruction::ExecuteStoreResultGetScore {
+                location: location_map[&var].clone(),
+                target,
+            }
+        }
+        prev::Instruction::Enter => Instruction::Enter { frame_size },
+        prev::Instruction::LoadArg { var, index } => Instruction::LoadArg {
+            location: location_map[&var].clone(),
+            index,
+        },
+        prev::Instruction::Call { id, args, result } => Instruction::Call {
+            id,
+            args: args.iter().map(|arg| location_map[arg].clone()).collect(),
+            result: location_map[&result].clone(),
+        },
+        prev::Instruction::CallIndirect {
+            function,
+            args,
+            result,
+        } => Instruction::CallIndirect {
+            function: location_map[&function].clone(),
+            args: args.iter().map(|arg| location_map[arg].clone()).collect(),
+            result: location_map[&result].clone(),
+        },
+        prev::Instruction::Return { var } => Instruction::Return {
+            location: location_map[&var].clone(),
+            frame_size,
+        },
+        prev::Instruction::TailCall { id, args } => Instruction::TailCall {
+            id,
+            args: args.iter().map(|arg| location_map[arg].clone()).collect(),
+            frame_size,
+        },
+        prev::Instruction::TailCallIndirect { function, args } => Instruction::TailCallIndirect {
+            function: location_map[&function].clone(),
+            args: args.iter().map(|arg| location_map[arg].clone()).collect(),
+            frame_size,
+        },
+        prev::Instruction::Disarm { var } => Instruction::Disarm {
+            location: location_map[&var].clone(),
+            frame_size,
+        },
+        prev::Instruction::GetScore { var, target, objective } => Instruction::GetScore {
+            location: location_map[&var].clone(),
+            target,
+            objective,
+        },
+        prev::Instruction::SetScore { target, objective, var } => Instruction::SetScore {
+            target,
+            objective,
+            location: location_map[&var].clone(),
+        },
+        prev::Instruction::Schedule { block, ticks } => Instruction::Schedule { block, ticks },
+        prev::Instruction::Asm { template, vars } => Instruction::Asm {
+            template,
+            locations: vars.iter().map(|var| location_map[var].clone()).collect(),
+        },
+        prev::Instruction::EarlyReturn { var } => Instruction::EarlyReturn {
+            location: var.map(|var| location_map[&var].clone()),
+            frame_size,
+        },
     }
 }
 
@@ -293,9 +538,12 @@ fn assign_homes_jmp(
     match jmp {
         prev::Jmp::ExecuteIfScoreMatchesFunction { var, value, block } => Jmp::ExecuteIfScoreMatchesFunction { location: location_map[&var].clone(), value, block },
         prev::Jmp::ExecuteUnlessScoreMatchesFunction { var, value, block } => Jmp::ExecuteUnlessScoreMatchesFunction { location: location_map[&var].clone(), value, block },
-        prev::Jmp::ExecuteIfScoreEqualsFunction { a, b, block } => Jmp::ExecuteIfScoreEqualsFunction { a: location_map[&a].clone(), b: location_map[&b].clone(), block },
-        prev::Jmp::ExecuteUnlessScoreEqualsFunction { a, b, block } => Jmp::ExecuteUnlessScoreEqualsFunction { a: location_map[&a].clone(), b: location_map[&b].clone(), block },
+        prev::Jmp::ExecuteIfScoreCompareFunction { cmp, a, b, block } => Jmp::ExecuteIfScoreCompareFunction { cmp, a: location_map[&a].clone(), b: location_map[&b].clone(), block },
+        prev::Jmp::ExecuteUnlessScoreCompareFunction { cmp, a, b, block } => Jmp::ExecuteUnlessScoreCompareFunction { cmp, a: location_map[&a].clone(), b: location_map[&b].clone(), block },
         prev::Jmp::Function { block } => Jmp::Function { block },
+        prev::Jmp::Join { block, .. } => Jmp::Join { block },
+        prev::Jmp::Resume { .. } => Jmp::Resume,
+        prev::Jmp::ExecuteContextFunction { context, block } => Jmp::ExecuteContextFunction { context, block },
     }
 }
 
@@ -307,16 +555,32 @@ fn write_set(instr: &prev::Instruction) -> HashSet<Var> {
             source,
             destination,
         } => HashSet::from([destination.clone()]),
-        prev::Instruction::Tellraw { text } => HashSet::new(),
+        prev::Instruction::Tellraw { .. } => HashSet::new(),
         prev::Instruction::Command { text } => HashSet::new(),
         prev::Instruction::ExecuteIfScoreMatchesSet { var, value, set_var, set_value } =>
             HashSet::from([set_var.clone()]),
         prev::Instruction::ExecuteUnlessScoreMatchesSet { var, value, set_var, set_value } =>
             HashSet::from([set_var.clone()]),
-        prev::Instruction::ExecuteIfScoreEqualsSet { a, b, set_var, set_value } =>
+        prev::Instruction::ExecuteIfScoreCompareSet { a, b, set_var, set_value, .. } =>
             HashSet::from([set_var.clone()]),
-        prev::Instruction::ExecuteUnlessScoreEqualsSet { a, b, set_var, set_value } =>
+        prev::Instruction::ExecuteUnlessScoreCompareSet { a, b, set_var, set_value, .. } =>
             HashSet::from([set_var.clone()]),
+        prev::Instruction::ExecuteStoreResultScore { var, .. } => HashSet::from([var.clone()]),
+        prev::Instruction::ExecuteStoreResultGetScore { .. } => HashSet::new(),
+        prev::Instruction::Enter => HashSet::new(),
+        prev::Instruction::LoadArg { var, .. } => HashSet::from([var.clone()]),
+        prev::Instruction::Call { result, .. } => HashSet::from([result.clone()]),
+        prev::Instruction::CallIndirect { result, .. } => HashSet::from([result.clone()]),
+        prev::Instruction::Return { .. } => HashSet::new(),
+        prev::Instruction::TailCall { .. } => HashSet::new(),
+        prev::Instruction::TailCallIndirect { .. } => HashSet::new(),
+        prev::Instruction::Disarm { var } => HashSet::from([var.clone()]),
+        prev::Instruction::GetScore { var, .. } => HashSet::from([var.clone()]),
+        prev::Instruction::SetScore { .. } => HashSet::new(),
+        prev::Instruction::Schedule { .. } => HashSet::new(),
+        prev::Instruction::EarlyReturn { .. } => HashSet::new(),
+        // The command may change any of the variables it is given.
+        prev::Instruction::Asm { vars, .. } => vars.iter().cloned().collect(),
     }
 }
 
@@ -333,11 +597,34 @@ fn read_set(instr: &prev::Instruction) -> HashSet<Var> {
             source,
             destination,
         } => HashSet::from([source.clone(), destination.clone()]),
-        prev::Instruction::Tellraw { text } => HashSet::new(),
+        prev::Instruction::Tellraw { .. } => HashSet::new(),
         prev::Instruction::Command { text } => HashSet::new(),
         prev::Instruction::ExecuteIfScoreMatchesSet { var, value, set_var, set_value } => HashSet::from([var.clone()]),
         prev::Instruction::ExecuteUnlessScoreMatchesSet { var, value, set_var, set_value } => HashSet::from([var.clone()]),
-        prev::Instruction::ExecuteIfScoreEqualsSet { a, b, set_var, set_value } => HashSet::from([a.clone(), b.clone()]),
-        prev::Instruction::ExecuteUnlessScoreEqualsSet { a, b, set_var, set_value } => HashSet::from([a.clone(), b.clone()]),
+        prev::Instruction::ExecuteIfScoreCompareSet { a, b, set_var, set_value, .. } => HashSet::from([a.clone(), b.clone()]),
+        prev::Instruction::ExecuteUnlessScoreCompareSet { a, b, set_var, set_value, .. } => HashSet::from([a.clone(), b.clone()]),
+        prev::Instruction::ExecuteStoreResultScore { .. } => HashSet::new(),
+        prev::Instruction::ExecuteStoreResultGetScore { var, .. } => HashSet::from([var.clone()]),
+        prev::Instruction::Enter => HashSet::new(),
+        prev::Instruction::LoadArg { .. } => HashSet::new(),
+        prev::Instruction::Call { args, .. } => args.iter().cloned().collect(),
+        prev::Instruction::CallIndirect { function, args, .. } => {
+            let mut vars: HashSet<Var> = args.iter().cloned().collect();
+            vars.insert(function.clone());
+            vars
+        }
+        prev::Instruction::Return { var } => HashSet::from([var.clone()]),
+        prev::Instruction::TailCall { args, .. } => args.iter().cloned().collect(),
+        prev::Instruction::TailCallIndirect { function, args } => {
+            let mut vars: HashSet<Var> = args.iter().cloned().collect();
+            vars.insert(function.clone());
+            vars
+        }
+        prev::Instruction::Disarm { .. } => HashSet::new(),
+        prev::Instruction::GetScore { .. } => HashSet::new(),
+        prev::Instruction::SetScore { var, .. } => HashSet::from([var.clone()]),
+        prev::Instruction::Schedule { .. } => HashSet::new(),
+        prev::Instruction::EarlyReturn { var } => var.iter().cloned().collect(),
+        prev::Instruction::Asm { vars, .. } => vars.iter().cloned().collect(),
     }
 }
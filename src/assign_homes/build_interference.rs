@@ -58,7 +58,7 @@ fn collect_vars_instr(vs: &mut HashSet<Var>, instr: select_instructions::Instruc
             vs.insert(source);
             vs.insert(destination);
         }
-        Instruction::Tellraw { text: _ } => {}
+        Instruction::Tellraw { .. } => {}
         Instruction::Command { text: _ } => {}
         Instruction::ExecuteIfScoreMatchesSet { var, value, set_var, set_value } => {
             vs.insert(var);
@@ -68,16 +68,65 @@ fn collect_vars_instr(vs: &mut HashSet<Var>, instr: select_instructions::Instruc
             vs.insert(var);
             vs.insert(set_var);
         }
-        Instruction::ExecuteIfScoreEqualsSet { a, b, set_var, set_value } => {
+        Instruction::ExecuteIfScoreCompareSet { a, b, set_var, set_value, .. } => {
             vs.insert(a);
             vs.insert(b);
             vs.insert(set_var);
         }
-        Instruction::ExecuteUnlessScoreEqualsSet { a, b, set_var, set_value } => {
+        Instruction::ExecuteUnlessScoreCompareSet { a, b, set_var, set_value, .. } => {
             vs.insert(a);
             vs.insert(b);
             vs.insert(set_var);
         }
+        Instruction::ExecuteStoreResultScore { var, .. } => {
+            vs.insert(var);
+        }
+        Instruction::ExecuteStoreResultGetScore { var, .. } => {
+            vs.insert(var);
+        }
+        Instruction::Enter => {}
+        Instruction::LoadArg { var, .. } => {
+            vs.insert(var);
+        }
+        Instruction::Call { args, result, .. } => {
+            vs.extend(args);
+            vs.insert(result);
+        }
+        Instruction::CallIndirect {
+            function,
+            args,
+            result,
+        } => {
+            vs.insert(function);
+            vs.extend(args);
+            vs.insert(result);
+        }
+        Instruction::Return { var } => {
+            vs.insert(var);
+        }
+        Instruction::TailCall { args, .. } => {
+            vs.extend(args);
+        }
+        Instruction::TailCallIndirect { function, args } => {
+            vs.insert(function);
+            vs.extend(args);
+        }
+        Instruction::Disarm { var } => {
+            vs.insert(var);
+        }
+        Instruction::GetScore { var, .. } => {
+            vs.insert(var);
+        }
+        Instruction::SetScore { var, .. } => {
+            vs.insert(var);
+        }
+        Instruction::Schedule { .. } => {}
+        Instruction::EarlyReturn { var } => {
+            vs.extend(var);
+        }
+        Instruction::Asm { vars, .. } => {
+            vs.extend(vars);
+        }
     }
 }
 
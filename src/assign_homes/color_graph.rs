@@ -10,6 +10,9 @@ use petgraph::graph::NodeIndex;
 
 type Color = u32;
 
+/// Colors below this are registers, see `Location::from_color`.
+const FIRST_STACK_COLOR: Color = 16;
+
 #[derive(Clone, Eq, PartialEq, Debug)]
 struct Priority {
     saturation: usize,
@@ -43,7 +46,12 @@ impl PartialOrd for Priority {
     }
 }
 
-pub fn color_graph(interference_graph: prev::Graph, move_graph: &MoveGraph) -> HashMap<Var, Color> {
+/// Colors the interference graph, keeping every variable in `spilled` on the stack.
+pub fn color_graph(
+    interference_graph: prev::Graph,
+    move_graph: &MoveGraph,
+    spilled: &HashSet<Var>,
+) -> HashMap<Var, Color> {
     let mut pqueue = KeyedPriorityQueue::new();
 
     for index in interference_graph.node_indices() {
@@ -54,7 +62,7 @@ pub fn color_graph(interference_graph: prev::Graph, move_graph: &MoveGraph) -> H
 
     while let Some((index, _)) = pqueue.pop() {
         if !color_map.contains_key(&interference_graph[index]) {
-            let color = find_least_color(&interference_graph, move_graph, &color_map, index);
+            let color = find_least_color(&interference_graph, move_graph, &color_map, spilled, index);
             color_map.insert(interference_graph[index].clone(), color);
             update_saturation(&mut pqueue, &interference_graph, &color_map, index);
             update_move_saturation(
@@ -74,13 +82,14 @@ fn find_least_color(
     graph: &prev::Graph,
     move_graph: &MoveGraph,
     color_map: &HashMap<Var, Color>,
+    spilled: &HashSet<Var>,
     index: NodeIndex,
 ) -> Color {
-    let mut color = 0;
+    let mut color = if spilled.contains(&graph[index]) { FIRST_STACK_COLOR } else { 0 };
     let conflicts = find_conflicting_colors(graph, color_map, index);
 
     for relative in find_move_related_colors(move_graph, color_map, &graph[index]) {
-        if !conflicts.contains(&relative) {
+        if relative >= color && !conflicts.contains(&relative) {
             return relative;
         }
     }
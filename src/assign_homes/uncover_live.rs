@@ -83,18 +83,41 @@ fn uncover_live_before_jmp(jmp: &Jmp, annotated_blocks: &HashMap<prev::Index, Bl
             live_before.insert(var.clone());
             live_before
         },
-        Jmp::ExecuteIfScoreEqualsFunction { a, b, block } => {
+        Jmp::ExecuteIfScoreCompareFunction { a, b, block, .. } => {
             let mut live_before = annotated_blocks[&block].live_before.clone();
             live_before.insert(a.clone());
             live_before.insert(b.clone());
             live_before
         },
-        Jmp::ExecuteUnlessScoreEqualsFunction { a, b, block } => {
+        Jmp::ExecuteUnlessScoreCompareFunction { a, b, block, .. } => {
             let mut live_before = annotated_blocks[&block].live_before.clone();
             live_before.insert(a.clone());
             live_before.insert(b.clone());
             live_before
         },
         Jmp::Function { block } => annotated_blocks[&block].live_before.clone(),
+        Jmp::ExecuteContextFunction { block, .. } => annotated_blocks[&block].live_before.clone(),
+        // The branches already resume at the join target, so whatever it needs is live in them,
+        // unless they write it and may not run at all.
+        Jmp::Join { live, .. } => live.iter().cloned().collect(),
+        Jmp::Resume { live, block } => {
+            let mut live_before = annotated_blocks[&block].live_before.clone();
+            live_before.extend(live.iter().cloned());
+            live_before
+        }
+    }
+}
+
+/// The variables that are live across a call. Functions are free to use every register, so
+/// these have to be kept on the stack.
+pub fn live_across_calls(program: &Program) -> HashSet<Var> {
+    let mut vars = HashSet::new();
+    for block in program.blocks.node_weights() {
+        for AnnotatedInstruction { instr, live_after } in &block.instrs {
+            if let Instruction::Call { result, .. } | Instruction::CallIndirect { result, .. } = instr {
+                vars.extend(live_after.iter().filter(|var| *var != result).cloned());
+            }
+        }
     }
+    vars
 }
\ No newline at end of file
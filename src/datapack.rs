@@ -7,6 +7,7 @@ pub struct Datapack {
     pub description: String,
     pub pack_format: usize,
     pub functions: Vec<Function>,
+    pub tags: Vec<Tag>,
 }
 
 pub struct Function {
@@ -15,6 +16,13 @@ pub struct Function {
     pub content: String,
 }
 
+/// A function tag, listing the functions to run together by their namespaced ids.
+pub struct Tag {
+    pub namespace: String,
+    pub name: String,
+    pub values: Vec<String>,
+}
+
 impl Datapack {
     pub fn bytes(&self) -> Result<Vec<u8>> {
         let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
@@ -42,6 +50,15 @@ impl Datapack {
             write!(zip, "{}", function.content)?;
         }
 
+        for tag in &self.tags {
+            zip.start_file(
+                format!("data/{}/tags/functions/{}.json", tag.namespace, tag.name),
+                options,
+            )?;
+            let values = object! { "values": tag.values.clone() };
+            write!(zip, "{}", json::stringify(values))?;
+        }
+
         Ok(zip.finish()?.into_inner())
     }
 }
@@ -1,14 +1,37 @@
-use crate::uniquify as prev;
+use crate::type_check as prev;
+pub use crate::type_check::{Context, Directive, Event, Fragment, NbtSource, Origin};
+use crate::type_check::Relation;
 use crate::var::{Var, VarFactory};
 
 pub struct Program {
     pub defs: Vec<Definition>,
+    /// The id of the function the teardown is kept in, when there is one. Every way out of a
+    /// test calls it, rather than repeating it.
+    pub teardown: Option<usize>,
     pub var_factory: VarFactory,
 }
 
 #[derive(PartialEq, Eq, Debug)]
 pub enum Definition {
-    Test { name: String, stmts: Vec<Statement> },
+    Test {
+        name: String,
+        directive: Option<Directive>,
+        budget: Option<i64>,
+        stmts: Vec<Statement>,
+    },
+    Function {
+        id: usize,
+        params: Vec<Var>,
+        stmts: Vec<Statement>,
+        result: Expr,
+    },
+    Handler { event: Event, stmts: Vec<Statement> },
+    /// Numbered so that its tests can count their failures against it.
+    Describe {
+        id: usize,
+        name: String,
+        defs: Vec<Definition>,
+    },
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -16,8 +39,64 @@ pub enum Statement {
     Expr(Expr),
     Command { text: String },
     Let { var: Var, expr: Expr },
-    TellOk { test_name: String },
-    TellNotOk { test_name: String },
+    /// `groups` are the describes the test is in, outermost first.
+    TellOk {
+        test_name: String,
+        groups: Vec<usize>,
+        directive: Option<Directive>,
+    },
+    TellNotOk {
+        test_name: String,
+        groups: Vec<usize>,
+        directive: Option<Directive>,
+        diagnostic: Diagnostic,
+    },
+    ArraySet { array: Expr, index: Expr, field: Option<String>, value: Expr },
+    ArrayPush { array: Expr, value: Element },
+    Execute { context: Context, stmts: Vec<Statement> },
+    SetScore { target: String, objective: String, value: Expr },
+    NbtSet { source: NbtSource, path: String, ty: String, scale: String, value: Expr },
+    After { ticks: i64, stmts: Vec<Statement> },
+    When { cond: Expr, stmts: Vec<Statement> },
+    Return { value: Option<Expr> },
+    Asm { template: Vec<Fragment>, vars: Vec<Var> },
+}
+
+/// What a failed assertion reports under its `not ok` line.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    /// The assertion that failed, if there is one.
+    pub origin: Option<Origin>,
+    /// What the assertion was given and what it wanted, as they are when it is reported. Left
+    /// out of the report when empty.
+    pub found: Vec<Component>,
+    pub wanted: Vec<Component>,
+}
+
+impl Diagnostic {
+    /// Reports `origin` with the message written there, or `message` when there is none.
+    fn new(message: &str, origin: Origin, found: Vec<Component>, wanted: Vec<Component>) -> Self {
+        Diagnostic {
+            message: origin.message.clone().unwrap_or_else(|| message.to_owned()),
+            origin: Some(origin),
+            found,
+            wanted,
+        }
+    }
+}
+
+/// A piece of text to report, either written as is or read from a score as it is shown.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Component {
+    Text(String),
+    Score { target: String, objective: String },
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Element {
+    Scalar(Expr),
+    Compound(Vec<(String, Expr)>),
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -51,85 +130,402 @@ pub enum Expr {
         left: Box<Expr>,
         right: Box<Expr>,
     },
+    /// Only made for the assertions that compare.
+    Less {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    LessEq {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    ArrayEq {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Array {
+        elements: Vec<Element>,
+    },
+    MakeArray {
+        length: i64,
+        fill: Box<Element>,
+    },
+    ArrayGet {
+        array: Box<Expr>,
+        index: Box<Expr>,
+        field: Option<String>,
+    },
+    Length {
+        array: Box<Expr>,
+    },
     Bundle {
         stmts: Vec<Statement>,
         expr: Box<Expr>,
     },
+    Call {
+        id: usize,
+        args: Vec<Expr>,
+    },
+    CallIndirect {
+        function: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    Score {
+        target: String,
+        objective: String,
+    },
+    NbtGet {
+        source: NbtSource,
+        path: String,
+        scale: String,
+    },
+    Random {
+        lo: Box<Expr>,
+        hi: Box<Expr>,
+    },
+}
+
+/// How a test ends, on whichever path it takes: the file's teardown runs, then the result is
+/// reported. Outside of tests there is nothing to report, and nothing to tear down.
+#[derive(Default)]
+struct Ending<'a> {
+    test_name: &'a str,
+    groups: &'a [usize],
+    directive: Option<&'a Directive>,
+    /// Whether the test passes only if one of its assertions fails.
+    fails: bool,
+    /// The function the teardown is kept in.
+    teardown: Option<usize>,
+}
+
+impl Ending<'_> {
+    /// Reports a test that got to its end, or returned early, without a failed assertion.
+    fn pass(&self) -> Vec<Statement> {
+        if self.fails {
+            self.tell_not_ok(Diagnostic {
+                message: "should fail an assertion".to_owned(),
+                origin: None,
+                found: vec![],
+                wanted: vec![],
+            })
+        } else {
+            self.tell_ok()
+        }
+    }
+
+    /// Reports a failed assertion, which is what a test that fails is waiting for.
+    fn fail(&self, diagnostic: Diagnostic) -> Vec<Statement> {
+        if self.fails {
+            self.tell_ok()
+        } else {
+            self.tell_not_ok(diagnostic)
+        }
+    }
+
+    fn tell_ok(&self) -> Vec<Statement> {
+        let mut stmts = self.tear_down();
+        stmts.push(Statement::TellOk {
+            test_name: self.test_name.to_owned(),
+            groups: self.groups.to_vec(),
+            directive: self.directive.cloned(),
+        });
+        stmts
+    }
+
+    fn tell_not_ok(&self, diagnostic: Diagnostic) -> Vec<Statement> {
+        let mut stmts = self.tear_down();
+        stmts.push(Statement::TellNotOk {
+            test_name: self.test_name.to_owned(),
+            groups: self.groups.to_vec(),
+            directive: self.directive.cloned(),
+            diagnostic,
+        });
+        stmts
+    }
+
+    fn tear_down(&self) -> Vec<Statement> {
+        self.teardown
+            .map(|id| Statement::Expr(Expr::Call { id, args: vec![] }))
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Makes one side of an `asserteq` something its report can show. Literals are shown as they
+/// are, and anything else is kept in the `target` score of the registry, which is compared in
+/// its place. Each assertion has scores of its own, as the teardown runs between the comparison
+/// and the report and can overwrite the registry.
+fn reported_operand(target: &str, expr: prev::Expr, stmts: &mut Vec<Statement>) -> (Expr, Component) {
+    match expr {
+        prev::Expr::LitInt(i) => (Expr::LitInt(i), Component::Text(i.to_string())),
+        prev::Expr::LitBool(b) => (Expr::LitBool(b), Component::Text(b.to_string())),
+        expr => {
+            stmts.push(Statement::SetScore {
+                target: target.to_owned(),
+                objective: "registry".to_owned(),
+                value: desugar_asserts_expr(expr),
+            });
+            (
+                Expr::Score {
+                    target: target.to_owned(),
+                    objective: "registry".to_owned(),
+                },
+                Component::Score {
+                    target: target.to_owned(),
+                    objective: "registry".to_owned(),
+                },
+            )
+        }
+    }
 }
 
 pub fn desugar_asserts(program: prev::Program) -> Program {
-    let defs = program.defs.into_iter().map(desugar_asserts_def).collect();
+    // The setup is woven into every test, and the teardown becomes a function of its own that
+    // every test calls on its way out.
+    let mut setup = Vec::new();
+    let mut teardown = None;
+    let mut defs = Vec::new();
+    let mut next_assertion = 0;
+    for def in program.defs {
+        match def {
+            prev::Definition::Setup { stmts } => {
+                setup = desugar_asserts_block(&Ending::default(), &mut next_assertion, stmts, Vec::new())
+            }
+            prev::Definition::Teardown { stmts } => {
+                teardown = Some(desugar_asserts_block(&Ending::default(), &mut next_assertion, stmts, Vec::new()))
+            }
+            def => defs.push(def),
+        }
+    }
+
+    // The teardown takes the id after every other function's.
+    let teardown_id = defs
+        .iter()
+        .filter_map(|def| match def {
+            prev::Definition::Function { id, .. } => Some(id + 1),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+    let teardown = teardown.map(|stmts| (teardown_id, stmts));
+
+    let mut next_group = 0;
+    let mut defs: Vec<_> = defs
+        .into_iter()
+        .map(|def| {
+            let teardown = teardown.as_ref().map(|(id, _)| *id);
+            desugar_asserts_def(def, &setup, teardown, &[], &mut next_group, &mut next_assertion)
+        })
+        .collect();
+    let teardown = teardown.map(|(id, stmts)| {
+        defs.push(Definition::Function {
+            id,
+            params: vec![],
+            stmts,
+            // Nothing reads what it returns, but every function returns a value.
+            result: Expr::LitInt(0),
+        });
+        id
+    });
 
     Program {
         defs,
+        teardown,
         var_factory: program.var_factory,
     }
 }
 
-fn desugar_asserts_def(def: prev::Definition) -> Definition {
+fn desugar_asserts_def(
+    def: prev::Definition,
+    setup: &[Statement],
+    teardown: Option<usize>,
+    groups: &[usize],
+    next_group: &mut usize,
+    next_assertion: &mut usize,
+) -> Definition {
     match def {
-        prev::Definition::Test { name, stmts } => {
-            // Probably more elegant as an rfold.
-            let mut new_stmts = vec![Statement::TellOk {
-                test_name: name.clone(),
-            }];
-            for stmt in stmts.into_iter().rev() {
-                new_stmts = desugar_asserts_stmts(&name, stmt, new_stmts);
-            }
+        prev::Definition::Test {
+            name,
+            directive,
+            fails,
+            budget,
+            stmts,
+        } => {
+            let ending = Ending {
+                test_name: &name,
+                groups,
+                directive: directive.as_ref(),
+                fails,
+                teardown,
+            };
+            let new_stmts = if let Some(Directive::Skip { .. }) = directive {
+                // Nothing is run, not even the fixtures, so there is nothing to tear down.
+                Ending {
+                    teardown: None,
+                    ..ending
+                }
+                .tell_ok()
+            } else {
+                // The setup has no assertions, so nothing in it can skip the test body after it.
+                let mut new_stmts = setup.to_vec();
+                new_stmts.extend(desugar_asserts_block(&ending, next_assertion, stmts, ending.pass()));
+                new_stmts
+            };
 
             Definition::Test {
                 name,
+                directive,
+                budget,
                 stmts: new_stmts,
             }
         }
+        prev::Definition::Function {
+            id,
+            params,
+            stmts,
+            result,
+        } => Definition::Function {
+            id,
+            params,
+            stmts: desugar_asserts_block(&Ending::default(), next_assertion, stmts, Vec::new()),
+            result: desugar_asserts_expr(result),
+        },
+        prev::Definition::Handler { event, stmts } => Definition::Handler {
+            event,
+            stmts: desugar_asserts_block(&Ending::default(), next_assertion, stmts, Vec::new()),
+        },
+        prev::Definition::Describe { name, defs } => {
+            let id = *next_group;
+            *next_group += 1;
+            let groups = [groups, &[id]].concat();
+            Definition::Describe {
+                id,
+                name,
+                defs: defs
+                    .into_iter()
+                    .map(|def| {
+                        desugar_asserts_def(def, setup, teardown, &groups, next_group, next_assertion)
+                    })
+                    .collect(),
+            }
+        }
+        prev::Definition::Setup { .. } | prev::Definition::Teardown { .. } => {
+            unreachable!("fixtures are woven into the tests")
+        }
     }
 }
 
+/// Carries on with `thn` if `cond` holds, and with `els` otherwise.
+fn check(cond: Expr, thn: Vec<Statement>, els: Vec<Statement>) -> Statement {
+    Statement::Expr(Expr::If {
+        cond: Box::new(cond),
+        thn: Box::new(Expr::Bundle {
+            stmts: thn,
+            expr: Box::new(Expr::LitUnit),
+        }),
+        els: Box::new(Expr::Bundle {
+            stmts: els,
+            expr: Box::new(Expr::LitUnit),
+        }),
+    })
+}
+
+/// Desugars `stmts` in front of `continuation`, which runs once they have all passed.
+fn desugar_asserts_block(
+    ending: &Ending,
+    next_assertion: &mut usize,
+    stmts: Vec<prev::Statement>,
+    continuation: Vec<Statement>,
+) -> Vec<Statement> {
+    stmts
+        .into_iter()
+        .rev()
+        .fold(continuation, |continuation, stmt| {
+            desugar_asserts_stmts(ending, next_assertion, stmt, continuation)
+        })
+}
+
 fn desugar_asserts_stmts(
-    test_name: &str,
+    ending: &Ending,
+    next_assertion: &mut usize,
     stmt: prev::Statement,
-    mut continuation: Vec<Statement>,
+    continuation: Vec<Statement>,
 ) -> Vec<Statement> {
     let mut stmts = Vec::new();
 
     match stmt {
-        prev::Statement::Assert { expr } => {
-            stmts.push(Statement::Expr(Expr::If {
-                cond: Box::new(desugar_asserts_expr(expr)),
-                thn: Box::new(Expr::Bundle {
-                    stmts: continuation,
-                    expr: Box::new(Expr::LitUnit),
-                }),
-                els: Box::new(Expr::Bundle {
-                    stmts: vec![Statement::TellNotOk {
-                        test_name: test_name.to_string(),
-                    }],
-                    expr: Box::new(Expr::LitUnit),
-                }),
-            }));
+        prev::Statement::Assert { expr, origin } => {
+            let diagnostic = Diagnostic::new("should be true", origin, vec![], vec![]);
+            stmts.push(check(desugar_asserts_expr(expr), continuation, ending.fail(diagnostic)));
+        }
+        prev::Statement::AssertEq {
+            left,
+            right,
+            origin,
+        } => {
+            let id = *next_assertion;
+            *next_assertion += 1;
+            let (left, found) = reported_operand(&format!("found{id}"), left, &mut stmts);
+            let (right, wanted) = reported_operand(&format!("wanted{id}"), right, &mut stmts);
+            let diagnostic = Diagnostic::new("should be equal", origin, vec![found], vec![wanted]);
+            let cond = Expr::Eq {
+                left: Box::new(left),
+                right: Box::new(right),
+            };
+            stmts.push(check(cond, continuation, ending.fail(diagnostic)));
+        }
+        prev::Statement::AssertCmp {
+            relation,
+            left,
+            right,
+            origin,
+        } => {
+            let id = *next_assertion;
+            *next_assertion += 1;
+            let (left, found) = reported_operand(&format!("found{id}"), left, &mut stmts);
+            let (right, wanted) = reported_operand(&format!("wanted{id}"), right, &mut stmts);
+            let (left, right) = (Box::new(left), Box::new(right));
+            let (message, cond) = match relation {
+                Relation::NotEqual => ("should not be equal", Expr::Eq { left, right }),
+                Relation::Less => ("should be less", Expr::Less { left, right }),
+                Relation::LessOrEqual => ("should be less or equal", Expr::LessEq { left, right }),
+            };
+            let failure = ending.fail(Diagnostic::new(message, origin, vec![found], vec![wanted]));
+            stmts.push(match relation {
+                Relation::NotEqual => check(cond, failure, continuation),
+                _ => check(cond, continuation, failure),
+            });
+        }
+        prev::Statement::AssertBetween { expr, lo, hi, origin } => {
+            let id = *next_assertion;
+            *next_assertion += 1;
+            let (expr, found) = reported_operand(&format!("found{id}"), expr, &mut stmts);
+            let (lo, low) = reported_operand(&format!("low{id}"), lo, &mut stmts);
+            let (hi, high) = reported_operand(&format!("high{id}"), hi, &mut stmts);
+            let wanted = vec![low, Component::Text("..".to_owned()), high];
+            let failure = ending.fail(Diagnostic::new("should be between", origin, vec![found], wanted));
+            let above_lo = Expr::LessEq {
+                left: Box::new(lo),
+                right: Box::new(expr.clone()),
+            };
+            let below_hi = Expr::LessEq {
+                left: Box::new(expr),
+                right: Box::new(hi),
+            };
+            stmts.push(check(
+                above_lo,
+                vec![check(below_hi, continuation, failure.clone())],
+                failure,
+            ));
         }
-        prev::Statement::AssertEq { left, right } => {
-            stmts.push(Statement::Expr(Expr::If {
-                cond: Box::new(Expr::Eq {
-                    left: Box::new(desugar_asserts_expr(left)),
-                    right: Box::new(desugar_asserts_expr(right)),
-                }),
-                thn: Box::new(Expr::Bundle {
-                    stmts: continuation,
-                    expr: Box::new(Expr::LitUnit),
-                }),
-                els: Box::new(Expr::Bundle {
-                    stmts: vec![Statement::TellNotOk {
-                        test_name: test_name.to_string(),
-                    }],
-                    expr: Box::new(Expr::LitUnit),
-                }),
-            }));
-        },
         prev::Statement::Command { text } => {
             stmts.push(Statement::Command { text });
             stmts.extend(continuation);
         }
+        prev::Statement::Asm { template, vars } => {
+            stmts.push(Statement::Asm { template, vars });
+            stmts.extend(continuation);
+        }
         prev::Statement::Let { var, expr } => {
             stmts.push(Statement::Let {
                 var,
@@ -137,6 +533,90 @@ fn desugar_asserts_stmts(
             });
             stmts.extend(continuation);
         }
+        prev::Statement::ArraySet {
+            array,
+            index,
+            field,
+            value,
+        } => {
+            stmts.push(Statement::ArraySet {
+                array: desugar_asserts_expr(array),
+                index: desugar_asserts_expr(index),
+                field,
+                value: desugar_asserts_expr(value),
+            });
+            stmts.extend(continuation);
+        }
+        prev::Statement::ArrayPush { array, value } => {
+            stmts.push(Statement::ArrayPush {
+                array: desugar_asserts_expr(array),
+                value: desugar_asserts_element(value),
+            });
+            stmts.extend(continuation);
+        }
+        prev::Statement::SetScore {
+            target,
+            objective,
+            value,
+        } => {
+            stmts.push(Statement::SetScore {
+                target,
+                objective,
+                value: desugar_asserts_expr(value),
+            });
+            stmts.extend(continuation);
+        }
+        prev::Statement::NbtSet {
+            source,
+            path,
+            ty,
+            scale,
+            value,
+        } => {
+            stmts.push(Statement::NbtSet {
+                source,
+                path,
+                ty,
+                scale,
+                value: desugar_asserts_expr(value),
+            });
+            stmts.extend(continuation);
+        }
+        prev::Statement::Execute { context, stmts: body } => {
+            // The body cannot contain assertions, so it never needs the test name.
+            let new_body = desugar_asserts_block(&Ending::default(), next_assertion, body, Vec::new());
+            stmts.push(Statement::Execute {
+                context,
+                stmts: new_body,
+            });
+            stmts.extend(continuation);
+        }
+        prev::Statement::After { ticks, stmts: body } => {
+            let new_body = desugar_asserts_block(&Ending::default(), next_assertion, body, Vec::new());
+            stmts.push(Statement::After {
+                ticks,
+                stmts: new_body,
+            });
+            stmts.extend(continuation);
+        }
+        prev::Statement::When { cond, stmts: body } => {
+            let new_body = desugar_asserts_block(ending, next_assertion, body, Vec::new());
+            stmts.push(Statement::When {
+                cond: desugar_asserts_expr(cond),
+                stmts: new_body,
+            });
+            stmts.extend(continuation);
+        }
+        prev::Statement::Return { value } => {
+            // Nothing after a return runs, so the continuation is dropped. Only tests
+            // return without a value, and one that returns early has passed so far.
+            if value.is_none() {
+                stmts.extend(ending.pass());
+            }
+            stmts.push(Statement::Return {
+                value: value.map(desugar_asserts_expr),
+            });
+        }
     }
 
     stmts
@@ -171,7 +651,63 @@ fn desugar_asserts_expr(expr: prev::Expr) -> Expr {
         prev::Expr::Eq { left, right } => Expr::Eq {
             left: Box::new(desugar_asserts_expr(*left)),
             right: Box::new(desugar_asserts_expr(*right)),
-        }
+        },
+        prev::Expr::ArrayEq { left, right } => Expr::ArrayEq {
+            left: Box::new(desugar_asserts_expr(*left)),
+            right: Box::new(desugar_asserts_expr(*right)),
+        },
+        prev::Expr::Array { elements } => Expr::Array {
+            elements: elements.into_iter().map(desugar_asserts_element).collect(),
+        },
+        prev::Expr::MakeArray { length, fill } => Expr::MakeArray {
+            length,
+            fill: Box::new(desugar_asserts_element(*fill)),
+        },
+        prev::Expr::ArrayGet {
+            array,
+            index,
+            field,
+        } => Expr::ArrayGet {
+            array: Box::new(desugar_asserts_expr(*array)),
+            index: Box::new(desugar_asserts_expr(*index)),
+            field,
+        },
+        prev::Expr::Length { array } => Expr::Length {
+            array: Box::new(desugar_asserts_expr(*array)),
+        },
+        prev::Expr::Score { target, objective } => Expr::Score { target, objective },
+        prev::Expr::NbtGet { source, path, scale } => Expr::NbtGet { source, path, scale },
+        prev::Expr::Random { lo, hi } => Expr::Random {
+            lo: Box::new(desugar_asserts_expr(*lo)),
+            hi: Box::new(desugar_asserts_expr(*hi)),
+        },
+        prev::Expr::Call { id, args } => Expr::Call {
+            id,
+            args: args.into_iter().map(desugar_asserts_expr).collect(),
+        },
+        prev::Expr::CallIndirect { function, args } => Expr::CallIndirect {
+            function: Box::new(desugar_asserts_expr(*function)),
+            args: args.into_iter().map(desugar_asserts_expr).collect(),
+        },
+        prev::Expr::Let { var, value, body } => Expr::Bundle {
+            stmts: vec![Statement::Let {
+                var,
+                expr: desugar_asserts_expr(*value),
+            }],
+            expr: Box::new(desugar_asserts_expr(*body)),
+        },
+    }
+}
+
+fn desugar_asserts_element(element: prev::Element) -> Element {
+    match element {
+        prev::Element::Scalar(expr) => Element::Scalar(desugar_asserts_expr(expr)),
+        prev::Element::Compound(fields) => Element::Compound(
+            fields
+                .into_iter()
+                .map(|(field, expr)| (field, desugar_asserts_expr(expr)))
+                .collect(),
+        ),
     }
 }
 
@@ -187,6 +723,9 @@ mod tests {
         let program = prev::Program {
             defs: vec![prev::Definition::Test {
                 name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
                 stmts: vec![
                     prev::Statement::Let {
                         var: x.clone(),
@@ -201,7 +740,9 @@ mod tests {
         };
 
         let program = desugar_asserts(program);
-        let Definition::Test { stmts, .. } = &program.defs[0];
+        let Definition::Test { stmts, .. } = &program.defs[0] else {
+            panic!("Expected test definition");
+        };
 
         assert_eq!(
             stmts[0],
@@ -219,7 +760,9 @@ mod tests {
         assert_eq!(
             stmts[2],
             Statement::TellOk {
-                test_name: "test".to_owned()
+                test_name: "test".to_owned(),
+                groups: vec![],
+                directive: None,
             }
         );
     }
@@ -234,17 +777,20 @@ mod tests {
         let program = prev::Program {
             defs: vec![prev::Definition::Test {
                 name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
                 stmts: vec![
                     prev::Statement::Let {
                         var: x.clone(),
                         expr: prev::Expr::LitBool(false),
                     },
-                    prev::Statement::Assert { expr: prev::Expr::LitBool(true) },
+                    prev::Statement::Assert { expr: prev::Expr::LitBool(true), origin: Origin::default() },
                     prev::Statement::Let {
                         var: y.clone(),
                         expr: prev::Expr::LitBool(false),
                     },
-                    prev::Statement::Assert { expr: prev::Expr::LitBool(false) },
+                    prev::Statement::Assert { expr: prev::Expr::LitBool(false), origin: Origin::default() },
                     prev::Statement::Let {
                         var: z.clone(),
                         expr: prev::Expr::LitBool(false),
@@ -256,7 +802,20 @@ mod tests {
         };
 
         let program = desugar_asserts(program);
-        let Definition::Test { stmts, .. } = &program.defs[0];
+        let Definition::Test { stmts, .. } = &program.defs[0] else {
+            panic!("Expected test definition");
+        };
+        let not_ok = Statement::TellNotOk {
+            test_name: "test".to_owned(),
+            groups: vec![],
+            directive: None,
+            diagnostic: Diagnostic {
+                message: "should be true".to_owned(),
+                origin: Some(Origin::default()),
+                found: vec![],
+                wanted: vec![],
+            },
+        };
 
         assert_eq!(
             stmts[0],
@@ -268,7 +827,7 @@ mod tests {
 
         let stmts = if let Statement::Expr(Expr::If { cond, thn, els}) = stmts[1].clone() {
             assert_eq!(*cond, Expr::LitBool(true));
-            assert_eq!(*els, Expr::Bundle { stmts: vec![ Statement::TellNotOk { test_name: "test".to_owned() }], expr: Box::new(Expr::LitUnit) });
+            assert_eq!(*els, Expr::Bundle { stmts: vec![not_ok.clone()], expr: Box::new(Expr::LitUnit) });
             if let Expr::Bundle { stmts, expr } = *thn {
                 assert_eq!(*expr, Expr::LitUnit);
                 stmts
@@ -289,7 +848,7 @@ mod tests {
 
         let stmts = if let Statement::Expr(Expr::If { cond, thn, els }) = stmts[1].clone() {
             assert_eq!(*cond, Expr::LitBool(false));
-            assert_eq!(*els, Expr::Bundle { stmts: vec![ Statement::TellNotOk { test_name: "test".to_owned() }], expr: Box::new(Expr::LitUnit) });
+            assert_eq!(*els, Expr::Bundle { stmts: vec![not_ok.clone()], expr: Box::new(Expr::LitUnit) });
             if let Expr::Bundle { stmts, expr } = *thn {
                 assert_eq!(*expr, Expr::LitUnit);
                 stmts
@@ -309,7 +868,432 @@ mod tests {
         );
         assert_eq!(
             stmts[1],
-            Statement::TellOk { test_name: "test".to_owned() }
+            Statement::TellOk { test_name: "test".to_owned(), groups: vec![], directive: None }
+        );
+    }
+
+    #[test]
+    fn fixtures() {
+        let command = |text: &str| Statement::Command {
+            text: text.to_owned(),
+        };
+        let program = prev::Program {
+            defs: vec![
+                prev::Definition::Test {
+                    name: "test".to_owned(),
+                    directive: None,
+                    fails: false,
+                    budget: None,
+                    stmts: vec![prev::Statement::Assert {
+                        origin: Origin::default(),
+                        expr: prev::Expr::LitBool(true),
+                    }],
+                },
+                prev::Definition::Setup {
+                    stmts: vec![prev::Statement::Command {
+                        text: "setup".to_owned(),
+                    }],
+                },
+                prev::Definition::Teardown {
+                    stmts: vec![prev::Statement::Command {
+                        text: "teardown".to_owned(),
+                    }],
+                },
+            ],
+            var_factory: VarFactory::new(),
+        };
+
+        let program = desugar_asserts(program);
+        assert_eq!(program.defs.len(), 2);
+        let Definition::Test { stmts, .. } = &program.defs[0] else {
+            panic!("Expected test definition");
+        };
+        // The teardown is kept once, in a function of its own.
+        assert_eq!(program.teardown, Some(0));
+        assert_eq!(
+            program.defs[1],
+            Definition::Function {
+                id: 0,
+                params: vec![],
+                stmts: vec![command("teardown")],
+                result: Expr::LitInt(0),
+            }
+        );
+
+        assert_eq!(stmts[0], command("setup"));
+        let Statement::Expr(Expr::If { thn, els, .. }) = &stmts[1] else {
+            panic!("Expected the assertion");
+        };
+        let not_ok = Statement::TellNotOk {
+            test_name: "test".to_owned(),
+            groups: vec![],
+            directive: None,
+            diagnostic: Diagnostic {
+                message: "should be true".to_owned(),
+                origin: Some(Origin::default()),
+                found: vec![],
+                wanted: vec![],
+            },
+        };
+        let teardown_then = |tell: Statement| Expr::Bundle {
+            stmts: vec![Statement::Expr(Expr::Call { id: 0, args: vec![] }), tell],
+            expr: Box::new(Expr::LitUnit),
+        };
+        assert_eq!(
+            **thn,
+            teardown_then(Statement::TellOk { test_name: "test".to_owned(), groups: vec![], directive: None })
+        );
+        assert_eq!(
+            **els,
+            teardown_then(not_ok)
+        );
+    }
+
+    #[test]
+    fn asserteq_reports_operands() {
+        let mut var_factory = VarFactory::new();
+        let x = var_factory.named("x".to_owned());
+        let origin = Origin {
+            line: 3,
+            source: "(asserteq x 3)".to_owned(),
+            message: None,
+        };
+        let program = prev::Program {
+            defs: vec![prev::Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![prev::Statement::AssertEq {
+                    left: prev::Expr::Variable(x.clone()),
+                    right: prev::Expr::LitInt(3),
+                    origin: origin.clone(),
+                }],
+            }],
+            var_factory,
+        };
+
+        let program = desugar_asserts(program);
+        let Definition::Test { stmts, .. } = &program.defs[0] else {
+            panic!("Expected test definition");
+        };
+
+        let found = Expr::Score {
+            target: "found0".to_owned(),
+            objective: "registry".to_owned(),
+        };
+        assert_eq!(
+            stmts[0],
+            Statement::SetScore {
+                target: "found0".to_owned(),
+                objective: "registry".to_owned(),
+                value: Expr::Variable(x),
+            }
+        );
+        let Statement::Expr(Expr::If { cond, els, .. }) = &stmts[1] else {
+            panic!("Expected the assertion");
+        };
+        assert_eq!(
+            **cond,
+            Expr::Eq {
+                left: Box::new(found),
+                right: Box::new(Expr::LitInt(3)),
+            }
+        );
+        assert_eq!(
+            **els,
+            Expr::Bundle {
+                stmts: vec![Statement::TellNotOk {
+                    test_name: "test".to_owned(),
+                    groups: vec![],
+                    directive: None,
+                    diagnostic: Diagnostic {
+                        message: "should be equal".to_owned(),
+                        origin: Some(origin),
+                        found: vec![Component::Score {
+                            target: "found0".to_owned(),
+                            objective: "registry".to_owned(),
+                        }],
+                        wanted: vec![Component::Text("3".to_owned())],
+                    },
+                }],
+                expr: Box::new(Expr::LitUnit),
+            }
+        );
+    }
+
+    #[test]
+    fn assertions_keep_their_own_scores() {
+        let mut var_factory = VarFactory::new();
+        let x = var_factory.named("x".to_owned());
+        let assert_x = || prev::Statement::AssertEq {
+            left: prev::Expr::Variable(x.clone()),
+            right: prev::Expr::LitInt(3),
+            origin: Origin::default(),
+        };
+        let program = prev::Program {
+            defs: vec![prev::Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![assert_x(), assert_x()],
+            }],
+            var_factory,
+        };
+
+        let program = desugar_asserts(program);
+        let Definition::Test { stmts, .. } = &program.defs[0] else {
+            panic!("Expected test definition");
+        };
+        let Statement::SetScore { target: first, .. } = &stmts[0] else {
+            panic!("Expected the first operand to be stored");
+        };
+        let Statement::Expr(Expr::If { thn, .. }) = &stmts[1] else {
+            panic!("Expected the first assertion");
+        };
+        let Expr::Bundle { stmts: rest, .. } = &**thn else {
+            panic!("Expected the rest of the test");
+        };
+        let Statement::SetScore { target: second, .. } = &rest[0] else {
+            panic!("Expected the second operand to be stored");
+        };
+        assert_ne!(first, second);
+
+        // The second assertion reports the score it stored, not the first one's.
+        let Statement::Expr(Expr::If { els, .. }) = &rest[1] else {
+            panic!("Expected the second assertion");
+        };
+        let Expr::Bundle { stmts: failure, .. } = &**els else {
+            panic!("Expected the failure");
+        };
+        let Some(Statement::TellNotOk { diagnostic, .. }) = failure.last() else {
+            panic!("Expected the failure to be reported");
+        };
+        assert_eq!(
+            diagnostic.found,
+            vec![Component::Score {
+                target: second.clone(),
+                objective: "registry".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn describes() {
+        let test = |name: &str| prev::Definition::Test {
+            name: name.to_owned(),
+            directive: None,
+            fails: false,
+            budget: None,
+            stmts: vec![],
+        };
+        let program = prev::Program {
+            defs: vec![
+                prev::Definition::Describe {
+                    name: "outer".to_owned(),
+                    defs: vec![
+                        prev::Definition::Describe {
+                            name: "inner".to_owned(),
+                            defs: vec![test("a")],
+                        },
+                        test("b"),
+                    ],
+                },
+                prev::Definition::Describe {
+                    name: "other".to_owned(),
+                    defs: vec![test("c")],
+                },
+            ],
+            var_factory: VarFactory::new(),
+        };
+
+        let program = desugar_asserts(program);
+        let [Definition::Describe { id: 0, defs: outer, .. }, Definition::Describe { id: 2, .. }] =
+            program.defs.as_slice()
+        else {
+            panic!("Expected describes numbered in order");
+        };
+        let [Definition::Describe { id: 1, defs: inner, .. }, Definition::Test { stmts, .. }] =
+            outer.as_slice()
+        else {
+            panic!("Expected a nested describe and a test");
+        };
+        assert_eq!(
+            stmts[0],
+            Statement::TellOk { test_name: "b".to_owned(), groups: vec![0], directive: None }
+        );
+        let [Definition::Test { stmts, .. }] = inner.as_slice() else {
+            panic!("Expected a test");
+        };
+        assert_eq!(
+            stmts[0],
+            Statement::TellOk { test_name: "a".to_owned(), groups: vec![0, 1], directive: None }
+        );
+    }
+
+    #[test]
+    fn skip_only_reports() {
+        let directive = Some(Directive::Skip {
+            reason: "later".to_owned(),
+        });
+        let program = prev::Program {
+            defs: vec![
+                prev::Definition::Setup {
+                    stmts: vec![prev::Statement::Command {
+                        text: "setup".to_owned(),
+                    }],
+                },
+                prev::Definition::Test {
+                    name: "test".to_owned(),
+                    directive: directive.clone(),
+                    fails: false,
+                    budget: None,
+                    stmts: vec![prev::Statement::Assert {
+                        expr: prev::Expr::LitBool(false),
+                        origin: Origin::default(),
+                    }],
+                },
+            ],
+            var_factory: VarFactory::new(),
+        };
+
+        let program = desugar_asserts(program);
+        let Definition::Test { stmts, .. } = &program.defs[0] else {
+            panic!("Expected test definition");
+        };
+        assert_eq!(
+            *stmts,
+            vec![Statement::TellOk {
+                test_name: "test".to_owned(),
+                groups: vec![],
+                directive,
+            }]
+        );
+    }
+
+    #[test]
+    fn assert_between_checks_both_bounds() {
+        let origin = Origin {
+            line: 1,
+            source: r#"(assert-between 5 1 9 "in range")"#.to_owned(),
+            message: Some("in range".to_owned()),
+        };
+        let program = prev::Program {
+            defs: vec![prev::Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![prev::Statement::AssertBetween {
+                    expr: prev::Expr::LitInt(5),
+                    lo: prev::Expr::LitInt(1),
+                    hi: prev::Expr::LitInt(9),
+                    origin: origin.clone(),
+                }],
+            }],
+            var_factory: VarFactory::new(),
+        };
+
+        let program = desugar_asserts(program);
+        let Definition::Test { stmts, .. } = &program.defs[0] else {
+            panic!("Expected test definition");
+        };
+        let failure = vec![Statement::TellNotOk {
+            test_name: "test".to_owned(),
+            groups: vec![],
+            directive: None,
+            diagnostic: Diagnostic {
+                message: "in range".to_owned(),
+                origin: Some(origin),
+                found: vec![Component::Text("5".to_owned())],
+                wanted: vec![
+                    Component::Text("1".to_owned()),
+                    Component::Text("..".to_owned()),
+                    Component::Text("9".to_owned()),
+                ],
+            },
+        }];
+        let tell_ok = Statement::TellOk {
+            test_name: "test".to_owned(),
+            groups: vec![],
+            directive: None,
+        };
+        let at_most = |left, right| {
+            Box::new(Expr::LessEq {
+                left: Box::new(Expr::LitInt(left)),
+                right: Box::new(Expr::LitInt(right)),
+            })
+        };
+        let bundle = |stmts| {
+            Box::new(Expr::Bundle {
+                stmts,
+                expr: Box::new(Expr::LitUnit),
+            })
+        };
+        assert_eq!(
+            *stmts,
+            vec![Statement::Expr(Expr::If {
+                cond: at_most(1, 5),
+                thn: bundle(vec![Statement::Expr(Expr::If {
+                    cond: at_most(5, 9),
+                    thn: bundle(vec![tell_ok]),
+                    els: bundle(failure.clone()),
+                })]),
+                els: bundle(failure),
+            })]
+        );
+    }
+
+    #[test]
+    fn fails_swaps_reports() {
+        let program = prev::Program {
+            defs: vec![prev::Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: true,
+                budget: None,
+                stmts: vec![prev::Statement::Assert {
+                    expr: prev::Expr::LitBool(false),
+                    origin: Origin::default(),
+                }],
+            }],
+            var_factory: VarFactory::new(),
+        };
+
+        let program = desugar_asserts(program);
+        let Definition::Test { stmts, .. } = &program.defs[0] else {
+            panic!("Expected test definition");
+        };
+        let tell_ok = Statement::TellOk {
+            test_name: "test".to_owned(),
+            groups: vec![],
+            directive: None,
+        };
+        let tell_not_ok = Statement::TellNotOk {
+            test_name: "test".to_owned(),
+            groups: vec![],
+            directive: None,
+            diagnostic: Diagnostic {
+                message: "should fail an assertion".to_owned(),
+                origin: None,
+                found: vec![],
+                wanted: vec![],
+            },
+        };
+        assert_eq!(
+            *stmts,
+            vec![Statement::Expr(Expr::If {
+                cond: Box::new(Expr::LitBool(false)),
+                thn: Box::new(Expr::Bundle {
+                    stmts: vec![tell_not_ok],
+                    expr: Box::new(Expr::LitUnit),
+                }),
+                els: Box::new(Expr::Bundle {
+                    stmts: vec![tell_ok],
+                    expr: Box::new(Expr::LitUnit),
+                }),
+            })]
         );
     }
 }
@@ -1,35 +1,147 @@
+use std::collections::HashSet;
+
 use anyhow::Result;
 
-use crate::datapack::Function;
-use crate::reify_locations::{self as prev, Run};
-use crate::runtime::{setup_runtime, Runtime};
-use crate::utility::escape;
+use crate::datapack::{Function, Tag};
+use crate::linearize::{Component, Directive, Event, Test};
+use crate::reify_locations::{self as prev, Index, Run};
+use crate::runtime::{
+    group_failures, setup_runtime, Runtime, BAILED, BUDGET, OVERFLOWED, REPORTED, STACK_BASE,
+    UNLIMITED_BUDGET,
+};
+use crate::utility::{escape, Quoting};
 
-pub fn emit_text(program: prev::Program) -> Vec<Function> {
-    let Runtime {
-        init: mut preamble,
-        mut functions,
-    } = setup_runtime();
+pub fn emit_text(program: prev::Program) -> (Vec<Function>, Vec<Tag>) {
+    let Runtime { init, mut functions } = setup_runtime();
+    // Blocks only count themselves against a budget if a test with one can enter them.
+    let budgeted = budgeted_blocks(&program);
 
-    preamble.push_str(&format!("tellraw @s \"TAP version 14\"\n"));
-    preamble.push_str(&format!("tellraw @s \"1..{}\"\n", program.tests.len()));
-    preamble.push_str(&format!("scoreboard players set ptr stack 10\n"));
+    functions.push(Function {
+        namespace: "mctest".to_owned(),
+        name: "load".to_owned(),
+        content: init,
+    });
 
-    let mut tests = String::new();
     for (i, t) in program.tests.iter().enumerate() {
         let block = program.blocks[t.block].clone();
-        let content = emit_text_block(block);
+        let content = emit_text_block(block, budgeted.contains(&t.block));
         functions.push(Function {
             namespace: "mctest".to_owned(),
             name: format!("test{i}"),
             content,
         });
-        tests.push_str(&format!("function mctest:test{}\n", i));
     }
 
-    for idx in program.blocks.node_indices().filter(|idx| program.tests.iter().map(|t| t.block).find(|i| i == idx).is_none()) {
+    // Each test can also be run on its own, inside the describes it is in, and `list` shows
+    // which function runs which test.
+    let numbered: Vec<_> = program.tests.iter().enumerate().collect();
+    let mut list = String::new();
+    for &(i, test) in &numbered {
+        functions.push(Function {
+            namespace: "mctest".to_owned(),
+            name: format!("run_test{i}"),
+            content: emit_text_entry(&[(i, test)], &program),
+        });
+        let path: Vec<_> = test
+            .groups
+            .iter()
+            .map(|group| group.name.as_str())
+            .chain([test.name.as_str()])
+            .collect();
+        list.push_str(&format!(
+            "tellraw @s \"mctest:run_test{i} - {}\"\n",
+            escape(&path.join(" > "), Quoting::Json)
+        ));
+    }
+    functions.push(Function {
+        namespace: "mctest".to_owned(),
+        name: "list".to_owned(),
+        content: list,
+    });
+
+    for f in &program.functions {
+        let block = program.blocks[f.block].clone();
+        let content = emit_text_block(block, budgeted.contains(&f.block));
+        functions.push(Function {
+            namespace: "mctest".to_owned(),
+            name: format!("func{}", f.id),
+            content,
+        });
+    }
+
+    // Function values are their ids, so indirect calls pick the callee out of this table. The
+    // reset at the end keeps a nested dispatch from matching again once control returns here.
+    if !program.functions.is_empty() {
+        let mut dispatch = String::new();
+        for f in &program.functions {
+            dispatch.push_str(&format!(
+                "execute if score fn registry matches {0} run function mctest:func{0}\n",
+                f.id
+            ));
+        }
+        dispatch.push_str("scoreboard players set fn registry -1\n");
+        functions.push(Function {
+            namespace: "mctest".to_owned(),
+            name: "dispatch".to_owned(),
+            content: dispatch,
+        });
+    }
+
+    // The runtime is set up before any on-load handler runs, as tags run in the order listed.
+    let mut load = vec!["mctest:load".to_owned()];
+    let mut tick = Vec::new();
+    for (i, h) in program.handlers.iter().enumerate() {
+        let block = program.blocks[h.block].clone();
+        let name = format!("on_{}{i}", h.event);
+        functions.push(Function {
+            namespace: "mctest".to_owned(),
+            name: name.clone(),
+            content: framed(emit_text_block(block, budgeted.contains(&h.block)), program.frames[&h.block]),
+        });
+        match h.event {
+            Event::Load => load.push(format!("mctest:{name}")),
+            Event::Tick => tick.push(format!("mctest:{name}")),
+        }
+    }
+
+    let mut tags = vec![Tag {
+        namespace: "minecraft".to_owned(),
+        name: "load".to_owned(),
+        values: load,
+    }];
+    if !tick.is_empty() {
+        tags.push(Tag {
+            namespace: "minecraft".to_owned(),
+            name: "tick".to_owned(),
+            values: tick,
+        });
+    }
+
+    // The teardown is also run on its own for a test that ran out of budget, with budget to
+    // spare.
+    let any_budget = program.tests.iter().any(|t| t.budget.is_some());
+    if let (true, Some(teardown)) = (any_budget, program.teardown) {
+        functions.push(Function {
+            namespace: "mctest".to_owned(),
+            name: "teardown".to_owned(),
+            content: format!(
+                "scoreboard players set {BUDGET} {UNLIMITED_BUDGET}\nfunction mctest:func{teardown}\n"
+            ),
+        });
+    }
+
+    let entries: Vec<_> = program
+        .tests
+        .iter()
+        .map(|t| t.block)
+        .chain(program.functions.iter().map(|f| f.block))
+        .chain(program.handlers.iter().map(|h| h.block))
+        .collect();
+    for idx in program.blocks.node_indices().filter(|idx| !entries.contains(idx)) {
         let block = program.blocks[idx].clone();
-        let content = emit_text_block(block);
+        // The bodies of `after` are scheduled directly, so they reserve their frame themselves.
+        let frame_size = program.frames.get(&idx).copied().unwrap_or(0);
+        let content = framed(emit_text_block(block, budgeted.contains(&idx)), frame_size);
         functions.push(Function {
             namespace: "mctest".to_owned(),
             name: format!("block{}", idx.index()),
@@ -40,16 +152,181 @@ pub fn emit_text(program: prev::Program) -> Vec<Function> {
     functions.push(Function {
         namespace: "mctest".to_owned(),
         name: "run".to_owned(),
-        content: format!("{}\n{}\ntellraw @s \"<EOF>\"", preamble, tests),
+        content: emit_text_entry(&numbered, &program),
     });
 
-    functions
+    (functions, tags)
+}
+
+/// Runs `tests` as a whole TAP document, from the version line to `<EOF>`.
+fn emit_text_entry(tests: &[(usize, &Test)], program: &prev::Program) -> String {
+    let (count, outline) = emit_text_outline(tests, program, 0);
+    let mut preamble = String::new();
+    preamble.push_str(&format!("scoreboard players set {BAILED} 0\n"));
+    preamble.push_str(&format!("scoreboard players set ptr stack {STACK_BASE}\n"));
+    preamble.push_str(&format!("tellraw @s \"TAP version 14\"\n"));
+    preamble.push_str(&format!("tellraw @s \"1..{count}\"\n"));
+    format!("{}\n{}\ntellraw @s \"<EOF>\"", preamble, outline)
 }
 
-fn emit_text_block(block: prev::Block) -> String {
+/// Runs the tests in order, with the tests of each describe run as a subtest: a header, a plan
+/// of their own, and a summary point that fails if any of them did. Returns how many points are
+/// in the plan at `depth` along with the commands.
+fn emit_text_outline(
+    tests: &[(usize, &Test)],
+    program: &prev::Program,
+    depth: usize,
+) -> (usize, String) {
+    let indent = "    ".repeat(depth);
+    let mut count = 0;
     let mut text = String::new();
+    let mut rest = tests;
+    while let Some((i, test)) = rest.first() {
+        count += 1;
+        let Some(group) = test.groups.get(depth) else {
+            text.push_str(&emit_text_isolated(*i, test, program, &indent));
+            rest = &rest[1..];
+            continue;
+        };
+
+        let len = rest
+            .iter()
+            .take_while(|(_, t)| t.groups.get(depth) == Some(group))
+            .count();
+        // Once the run has bailed out nothing more is reported, so a group whose tests did not
+        // all run has no summary.
+        let (inner_count, inner) = emit_text_outline(&rest[..len], program, depth + 1);
+        let unless_bailed = format!("execute if score {BAILED} matches 0");
+        let failures = group_failures(group.id);
+        let name = escape(&group.name, Quoting::Json);
+        text.push_str(&format!("scoreboard players set {failures} 0\n"));
+        text.push_str(&format!("{unless_bailed} run tellraw @s \"{indent}# Subtest: {name}\"\n"));
+        text.push_str(&format!("{unless_bailed} run tellraw @s \"{indent}    1..{inner_count}\"\n"));
+        text.push_str(&inner);
+        text.push_str(&format!(
+            "{unless_bailed} if score {failures} matches 0 run tellraw @s \"{indent}ok - {name}\"\n"
+        ));
+        text.push_str(&format!(
+            "{unless_bailed} unless score {failures} matches 0 run tellraw @s \"{indent}not ok - {name}\"\n"
+        ));
+        rest = &rest[len..];
+    }
+    (count, text)
+}
+
+/// Runs a test in a frame of its own, from a clean registry and stack, unless an earlier test
+/// has bailed out. A test that leaves `ptr` off its base or runs off the end of the stack would
+/// corrupt the ones after it, so the run bails out instead.
+fn emit_text_isolated(i: usize, test: &Test, program: &prev::Program, indent: &str) -> String {
+    let frame_size = program.frames[&test.block];
+    let unless_bailed = format!("execute if score {BAILED} matches 0");
+    let unbalanced = format!("unless score ptr stack matches {STACK_BASE}");
+    let name = escape(&test.name, Quoting::Json);
+    let mut text = String::new();
+    text.push_str(&format!("{unless_bailed} run function mctest:reset\n"));
+    if let Some(budget) = test.budget {
+        text.push_str(&format!("{unless_bailed} run scoreboard players set {BUDGET} {budget}\n"));
+    }
+    if frame_size > 0 {
+        text.push_str(&format!("{unless_bailed} run scoreboard players add ptr stack {frame_size}\n"));
+    }
+    text.push_str(&format!("{unless_bailed} run function mctest:test{i}\n"));
+    if frame_size > 0 {
+        text.push_str(&format!("{unless_bailed} run scoreboard players remove ptr stack {frame_size}\n"));
+    }
+    // A test stopped partway through is still inside its frames, so the stack is put back
+    // rather than bailing out, and then torn down, as nothing it would have run after that
+    // point has run. One that is still to do is expected to fail, as when an assertion fails,
+    // so its describes still pass. A test that ran out after it was reported has been torn
+    // down already, and is left as it was reported.
+    if test.budget.is_some() {
+        let exceeded = format!("{unless_bailed} if score {BUDGET} matches ..-1 if score {REPORTED} matches 0 run");
+        let todo = matches!(test.directive, Some(Directive::Todo));
+        let directive = if todo { "TODO " } else { "" };
+        text.push_str(&format!(
+            "{exceeded} tellraw @s \"{indent}not ok - {name} # {directive}exceeded budget\"\n"
+        ));
+        if !todo {
+            for group in &test.groups {
+                text.push_str(&format!("{exceeded} scoreboard players add {} 1\n", group_failures(group.id)));
+            }
+        }
+        text.push_str(&format!("{exceeded} scoreboard players set ptr stack {STACK_BASE}\n"));
+        if program.teardown.is_some() {
+            text.push_str(&format!("{exceeded} function mctest:teardown\n"));
+        }
+        text.push_str(&format!("scoreboard players set {BUDGET} {UNLIMITED_BUDGET}\n"));
+    }
+    let overflowed = format!("if score {OVERFLOWED} matches 1");
+    text.push_str(&format!(
+        "{unless_bailed} {overflowed} run tellraw @s \"{indent}Bail out! {name} ran out of stack\"\n"
+    ));
+    text.push_str(&format!("execute {overflowed} run scoreboard players set {BAILED} 1\n"));
+    text.push_str(&format!(
+        "{unless_bailed} {unbalanced} run tellraw @s \"{indent}Bail out! {name} left the stack unbalanced\"\n"
+    ));
+    text.push_str(&format!("execute {unbalanced} run scoreboard players set {BAILED} 1\n"));
+    text
+}
+
+/// The blocks a test with a budget can enter while it runs: its own, and those of everything it
+/// calls. The bodies of `after` run later, outside of the test, so they are not among them.
+fn budgeted_blocks(program: &prev::Program) -> HashSet<Index> {
+    let mut blocks = HashSet::new();
+    let mut pending: Vec<_> = program
+        .tests
+        .iter()
+        .filter(|t| t.budget.is_some())
+        .map(|t| t.block)
+        .collect();
+    while let Some(idx) = pending.pop() {
+        if !blocks.insert(idx) {
+            continue;
+        }
+        for instr in &program.blocks[idx].instrs {
+            match instr {
+                prev::Instruction::Function { block } => pending.push(*block),
+                prev::Instruction::Call { id } => pending.extend(
+                    program.functions.iter().filter(|f| f.id == *id).map(|f| f.block),
+                ),
+                prev::Instruction::Dispatch => pending.extend(program.functions.iter().map(|f| f.block)),
+                prev::Instruction::ExecuteIfScoreMatches { run, .. }
+                | prev::Instruction::ExecuteUnlessScoreMatches { run, .. }
+                | prev::Instruction::ExecuteIfScoreCompare { run, .. }
+                | prev::Instruction::ExecuteUnlessScoreCompare { run, .. }
+                | prev::Instruction::ExecuteContext { run, .. }
+                | prev::Instruction::Execute { run } => match run {
+                    Run::Function { block }
+                    | Run::ReturnIfFunction { block }
+                    | Run::FunctionUnlessReturned { block } => pending.push(*block),
+                    Run::Set { .. } => {}
+                },
+                _ => {}
+            }
+        }
+    }
+    blocks
+}
+
+/// Wraps a body that is run from outside of any function in its frame. It cannot return early,
+/// so the frame is always released at the end.
+fn framed(content: String, frame_size: u32) -> String {
+    if frame_size == 0 {
+        return content;
+    }
+    format!(
+        "scoreboard players add ptr stack {frame_size}\n{content}scoreboard players remove ptr stack {frame_size}\n"
+    )
+}
+
+fn emit_text_block(block: prev::Block, budgeted: bool) -> String {
+    let mut text = String::new();
+    if budgeted {
+        text.push_str(&format!("scoreboard players remove {BUDGET} 1\n"));
+    }
     for instr in block.instrs {
-        text.push_str(&emit_text_instr(instr));
+        let instr = emit_text_instr(instr);
+        text.push_str(&if budgeted { within_budget(instr) } else { instr });
     }
     text
 }
@@ -70,14 +347,16 @@ fn emit_text_instr(instr: prev::Instruction) -> String {
         prev::Instruction::Pop { offset } => {
             format!("scoreboard players set offset stack {offset}\nfunction mctest:pop\n")
         }
-        prev::Instruction::Tellraw { text } => format!("tellraw @s \"{}\"\n", escape(&text)),
-        prev::Instruction::Command { text } => format!("{text}\n"),
+        prev::Instruction::Tellraw { components } => {
+            format!("tellraw @s {}\n", emit_text_components(&components))
+        }
+        prev::Instruction::Command { text } => format!("{}\n", escape(&text, Quoting::Command)),
         prev::Instruction::ExecuteIfScoreMatches {
             location,
             value,
             run,
         } => format!(
-            "execute if score {location} matches {value} run {}\n",
+            "execute if score {location} matches {value} {}\n",
             emit_text_run(run),
         ),
         prev::Instruction::ExecuteUnlessScoreMatches {
@@ -85,18 +364,240 @@ fn emit_text_instr(instr: prev::Instruction) -> String {
             value,
             run,
         } => format!(
-            "execute unless score {location} matches {value} run {}\n",
+            "execute unless score {location} matches {value} {}\n",
             emit_text_run(run)
         ),
-        prev::Instruction::ExecuteIfScoreEquals { a, b, run } => todo!(),
-        prev::Instruction::ExecuteUnlessScoreEquals { a, b, run } => todo!(),
+        prev::Instruction::ExecuteContext { context, run } => format!(
+            "execute {} {}\n",
+            context.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(" "),
+            emit_text_run(run),
+        ),
+        prev::Instruction::ExecuteStoreResultScore { location, command } => {
+            format!("execute store result score {location} run {command}\n")
+        }
+        prev::Instruction::ExecuteStoreResultGetScore { location, target } => {
+            format!("execute store result {target} run scoreboard players get {location}\n")
+        }
+        prev::Instruction::ExecuteIfScoreCompare { cmp, a, b, run } => {
+            format!("execute if score {a} {cmp} {b} {}\n", emit_text_run(run))
+        }
+        prev::Instruction::ExecuteUnlessScoreCompare { cmp, a, b, run } => {
+            format!("execute unless score {a} {cmp} {b} {}\n", emit_text_run(run))
+        }
         prev::Instruction::Function { block } => format!("function mctest:block{}\n", block.index()),
+        prev::Instruction::Call { id } => format!("function mctest:func{id}\n"),
+        prev::Instruction::Dispatch => "function mctest:dispatch\n".to_owned(),
+        prev::Instruction::Schedule { block, ticks } => {
+            format!("schedule function mctest:block{} {ticks}t append\n", block.index())
+        }
+        prev::Instruction::Execute { run } => format!("execute {}\n", emit_text_run(run)),
+    }
+}
+
+/// Writes the JSON text component for `tellraw`. Plain text stays a plain string, and anything
+/// that reads a score becomes a list of components.
+fn emit_text_components(components: &[Component]) -> String {
+    let component = |component: &Component| match component {
+        Component::Text(text) => format!("\"{}\"", escape(text, Quoting::Json)),
+        Component::Score { target, objective } => format!(
+            "{{\"score\":{{\"name\":\"{}\",\"objective\":\"{}\"}}}}",
+            escape(target, Quoting::Json),
+            escape(objective, Quoting::Json)
+        ),
+    };
+    match components {
+        [text @ Component::Text(_)] => component(text),
+        components => format!("[{}]", components.iter().map(component).collect::<Vec<_>>().join(",")),
     }
 }
 
 fn emit_text_run(run: prev::Run) -> String {
     match run {
-        Run::Function { block } => format!("function mctest:block{}", block.index()),
-        Run::Set { location, value } => format!("scoreboard players set {location} {value}"),
+        Run::Function { block } => format!("run function mctest:block{}", block.index()),
+        Run::Set { location, value } => format!("run scoreboard players set {location} {value}"),
+        Run::ReturnIfFunction { block } => format!("if function mctest:block{} run return 1", block.index()),
+        Run::FunctionUnlessReturned { block } => format!(
+            "unless score returned registry matches 1 run function mctest:block{}",
+            block.index()
+        ),
+    }
+}
+
+/// Runs each command in `text` only while the test has budget left. One that has used it up
+/// goes back out through the blocks it is in without doing anything more.
+fn within_budget(text: String) -> String {
+    let guard = format!("execute if score {BUDGET} matches 0..");
+    text.lines()
+        .map(|line| match line.strip_prefix("execute ") {
+            Some(subcommands) => format!("{guard} {subcommands}\n"),
+            None => format!("{guard} run {line}\n"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    use crate::compile;
+    use crate::runtime::{BAILED, BUDGET, REPORTED, UNLIMITED_BUDGET};
+    use crate::datapack::Datapack;
+
+    fn function<'a>(datapack: &'a Datapack, name: &str) -> &'a str {
+        &datapack
+            .functions
+            .iter()
+            .find(|f| f.name == name)
+            .unwrap_or_else(|| panic!("Expected a function mctest:{name}"))
+            .content
+    }
+
+    /// A test that keeps `n` variables live at once, so that all but the registers' worth spill.
+    fn live_variables(n: usize) -> String {
+        let mut source = "(test \"live\"\n".to_owned();
+        for i in 0..n {
+            source.push_str(&format!("  (let (v{i} (score \"#v{i}\" \"mcml\")))\n"));
+        }
+        let sum = (1..n).fold("v0".to_owned(), |sum, i| format!("(+ {sum} v{i})"));
+        source.push_str(&format!("  (asserteq {sum} 0))\n"));
+        source
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn tests_reserve_their_frame() -> Result<()> {
+        let datapack = compile(&live_variables(24))?;
+        let run: Vec<_> = function(&datapack, "run").lines().collect();
+        let call = run.iter().position(|l| l.ends_with("run function mctest:test0")).unwrap();
+        assert!(run[call - 1].contains("run scoreboard players add ptr stack "));
+        assert!(run[call + 1].contains("run scoreboard players remove ptr stack "));
+
+        assert!(compile(&live_variables(45)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn scheduled_bodies_take_their_values_off_the_queue() -> Result<()> {
+        let datapack = compile(
+            r##"(test "later"
+                 (let (x (score "#x" "mcml")))
+                 (after 1 (set-score! "#y" "mcml" x)))"##,
+        )?;
+        let scheduler = function(&datapack, "test0");
+        let body = scheduler
+            .lines()
+            .find_map(|l| l.strip_prefix("schedule function mctest:")?.strip_suffix(" 1t append"))
+            .expect("Expected the body to be scheduled");
+        let queue = format!("storage mctest:heap scheduled.{body}");
+        assert!(scheduler.contains(&format!("data modify {queue} append value {{}}\n")));
+        assert!(scheduler.contains(&format!("execute store result {queue}[-1].v0 int 1 run")));
+
+        let lines: Vec<_> = function(&datapack, body).lines().collect();
+        assert!(lines[0].ends_with(&format!("run data get {queue}[0].v0")));
+        assert_eq!(lines[1], format!("data remove {queue}[0]"));
+        Ok(())
+    }
+
+    #[test]
+    fn groups_report_nothing_once_bailed() -> Result<()> {
+        let datapack = compile(
+            r#"(describe "group"
+                 (test "unbalances" (asm "scoreboard players add ptr stack 1"))
+                 (test "never runs"))"#,
+        )?;
+        let group_lines: Vec<_> = function(&datapack, "run")
+            .lines()
+            .filter(|l| l.contains("group\""))
+            .collect();
+        assert_eq!(group_lines.len(), 3);
+        for line in group_lines {
+            assert!(line.starts_with(&format!("execute if score {BAILED} matches 0 ")), "{line}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn nested_tests_run_on_their_own() -> Result<()> {
+        let datapack = compile(
+            r#"(test "first")
+               (describe "outer" (describe "inner" (test "nested")))"#,
+        )?;
+        let tells: Vec<_> = function(&datapack, "run_test1")
+            .lines()
+            .filter_map(|l| l.split_once("tellraw @s ").map(|(_, text)| text))
+            .collect();
+        assert_eq!(tells[0], "\"TAP version 14\"");
+        assert_eq!(tells[1], "\"1..1\"");
+        assert!(tells.contains(&"\"# Subtest: outer\""));
+        assert!(tells.contains(&"\"    # Subtest: inner\""));
+        assert!(tells.contains(&"\"        1..1\""));
+        assert!(!tells.iter().any(|t| t.contains("first")));
+        assert_eq!(tells.last(), Some(&"\"<EOF>\""));
+
+        assert_eq!(
+            function(&datapack, "list").lines().collect::<Vec<_>>(),
+            vec![
+                "tellraw @s \"mctest:run_test0 - first\"",
+                "tellraw @s \"mctest:run_test1 - outer > inner > nested\"",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn budgets_guard_what_budgeted_tests_run() -> Result<()> {
+        let datapack = compile(
+            r##"(defn forever ((n int)) int (forever (+ n 1)))
+               (defn helper ((n int)) int (+ n 1))
+               (on-load (set-score! "#loaded" "mcml" (helper 1)))
+               (teardown (set-score! "#torn" "mcml" 1))
+               (test "runs away" :budget 10 (asserteq (forever 0) 0))
+               (describe "group"
+                 (test-todo "to do" :budget 10 (asserteq (forever 0) 0))
+                 (test "unlimited" (assert true)))"##,
+        )?;
+        for name in ["test0", "func0"] {
+            let mut lines = function(&datapack, name).lines();
+            assert_eq!(lines.next(), Some(format!("scoreboard players remove {BUDGET} 1").as_str()));
+            for line in lines {
+                assert!(line.starts_with(&format!("execute if score {BUDGET} matches 0.. ")), "{line}");
+            }
+        }
+
+        // Nothing a budgeted test runs is guarded outside of it.
+        for name in ["func1", "on_load0"] {
+            assert!(!function(&datapack, name).contains(BUDGET), "{name}");
+        }
+
+        let run = function(&datapack, "run");
+        let exceeded = format!(
+            "execute if score {BAILED} matches 0 if score {BUDGET} matches ..-1 if score {REPORTED} matches 0 run"
+        );
+        let start = run.find(&format!("run scoreboard players set {BUDGET} 10\n")).unwrap();
+        assert!(start < run.find("run function mctest:test0\n").unwrap());
+        assert!(run.contains(&format!("{exceeded} tellraw @s \"not ok - runs away # exceeded budget\"\n")));
+        assert!(run.contains(&format!("{exceeded} tellraw @s \"    not ok - to do # TODO exceeded budget\"\n")));
+        // Only the test that is still to do is in a group, and it does not fail it.
+        assert!(!run.contains(&format!("{exceeded} scoreboard players add")));
+        assert_eq!(run.matches(&format!("scoreboard players set {BUDGET} {UNLIMITED_BUDGET}")).count(), 2);
+        assert_eq!(run.matches(&format!("{exceeded} function mctest:teardown\n")).count(), 2);
+        // A test that ran out only after it was reported is not reported again.
+        let reported = format!("execute if score {BUDGET} matches 0.. run scoreboard players set {REPORTED} 1\n");
+        assert!(datapack.functions.iter().any(|f| f.content.contains(&reported)));
+        // The teardown is the last function, and is given the budget to run in full.
+        let teardown: Vec<_> = function(&datapack, "teardown").lines().collect();
+        assert_eq!(
+            teardown,
+            [format!("scoreboard players set {BUDGET} {UNLIMITED_BUDGET}").as_str(), "function mctest:func2"]
+        );
+        assert!(function(&datapack, "func2").contains("run scoreboard players set #torn mcml 1\n"));
+
+        let unbudgeted = compile(
+            r##"(teardown (set-score! "#torn" "mcml" 1))
+                (test "unlimited" (assert true))"##,
+        )?;
+        assert!(!function(&unbudgeted, "test0").contains(BUDGET));
+        assert!(!function(&unbudgeted, "run").contains(BUDGET));
+        assert!(!unbudgeted.functions.iter().any(|f| f.name == "teardown"));
+        Ok(())
+    }
+}
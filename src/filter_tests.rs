@@ -0,0 +1,115 @@
+use anyhow::Result;
+use regex::Regex;
+
+use crate::parse::Definition;
+
+/// What the names of the tests to compile are matched against.
+pub enum Filter {
+    /// Matches names that contain this.
+    Substring(String),
+    /// Matches names with a match of this anywhere in them.
+    Regex(Regex),
+}
+
+impl Filter {
+    pub fn regex(pattern: &str) -> Result<Self> {
+        Ok(Filter::Regex(Regex::new(pattern)?))
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            Filter::Substring(filter) => name.contains(filter.as_str()),
+            Filter::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// Keeps only the tests whose name matches `filter`, or that are in a describe whose name does.
+/// Describes left without tests are dropped, so that the plan counts only what is run.
+pub fn filter_tests(defs: Vec<Definition>, filter: &Filter) -> Vec<Definition> {
+    defs.into_iter()
+        .filter_map(|def| filter_def(def, filter))
+        .collect()
+}
+
+fn filter_def(def: Definition, filter: &Filter) -> Option<Definition> {
+    match def {
+        Definition::Test { ref name, .. } => filter.matches(name).then_some(def),
+        Definition::Describe { name, defs } => {
+            let defs = if filter.matches(&name) {
+                defs
+            } else {
+                filter_tests(defs, filter)
+            };
+            (!defs.is_empty()).then_some(Definition::Describe { name, defs })
+        }
+        def => Some(def),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{lex::lex, parse::parse, read::read};
+
+    fn names(defs: &[Definition]) -> Vec<String> {
+        defs.iter()
+            .flat_map(|def| match def {
+                Definition::Test { name, .. } => vec![name.clone()],
+                Definition::Describe { name, defs } => names(defs)
+                    .into_iter()
+                    .map(|inner| format!("{name}/{inner}"))
+                    .collect(),
+                _ => vec![],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn by_name() -> anyhow::Result<()> {
+        let defs = || -> anyhow::Result<Vec<Definition>> {
+            parse(read(lex(
+                r#"(defn one () int 1)
+                   (test "adds")
+                   (test "subtracts")
+                   (describe "arrays" (test "push") (test "adds an element"))
+                   (describe "other" (test "x"))"#,
+            )?)?)
+        };
+
+        let substring = |filter: &str| Filter::Substring(filter.to_owned());
+        let filtered = filter_tests(defs()?, &substring("adds"));
+        assert_eq!(names(&filtered), vec!["adds", "arrays/adds an element"]);
+        assert!(matches!(filtered[0], Definition::Function { .. }));
+
+        assert_eq!(
+            names(&filter_tests(defs()?, &substring("arrays"))),
+            vec!["arrays/push", "arrays/adds an element"]
+        );
+        assert!(names(&filter_tests(defs()?, &substring("^adds"))).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn by_regex() -> anyhow::Result<()> {
+        let defs = || -> anyhow::Result<Vec<Definition>> {
+            parse(read(lex(
+                r#"(test "adds")
+                   (test "subtracts")
+                   (describe "arrays" (test "push") (test "adds an element"))
+                   (describe "other" (test "x"))"#,
+            )?)?)
+        };
+
+        assert_eq!(
+            names(&filter_tests(defs()?, &Filter::regex("^adds")?)),
+            vec!["adds", "arrays/adds an element"]
+        );
+        assert_eq!(
+            names(&filter_tests(defs()?, &Filter::regex("^(push|x)$|^sub")?)),
+            vec!["subtracts", "arrays/push", "other/x"]
+        );
+        assert!(Filter::regex("(").is_err());
+        Ok(())
+    }
+}
@@ -1,10 +1,14 @@
+use std::collections::{HashMap, HashSet};
+
+use petgraph::visit::EdgeRef;
 use petgraph::{Direction, EdgeDirection};
 use crate::assign_homes as prev;
 pub use crate::assign_homes::Location;
 use crate::select_instructions::Op;
+use crate::Options;
 
 use petgraph::graph::NodeIndex;
-use crate::linearize::Test;
+use crate::linearize::{Cmp, Component, Context, Fragment, Function, Handler, Test};
 
 type Graph = petgraph::Graph<Block, (), petgraph::Directed, u32>;
 type Index = NodeIndex<u32>;
@@ -13,6 +17,10 @@ type Index = NodeIndex<u32>;
 pub struct Program {
     pub blocks: Graph,
     pub tests: Vec<Test>,
+    pub functions: Vec<Function>,
+    pub handlers: Vec<Handler>,
+    pub teardown: Option<usize>,
+    pub frames: HashMap<Index, u32>,
 }
 
 #[derive(Debug, Clone)]
@@ -32,7 +40,7 @@ pub enum Instruction {
         destination: Location,
     },
     Tellraw {
-        text: String,
+        components: Vec<Component>,
     },
     Command {
         text: String,
@@ -47,48 +55,210 @@ pub enum Instruction {
         value: i64,
         run: Run,
     },
-    ExecuteIfScoreEquals {
+    ExecuteIfScoreCompare {
+        cmp: Cmp,
         a: Location,
         b: Location,
         run: Run,
     },
-    ExecuteUnlessScoreEquals {
+    ExecuteUnlessScoreCompare {
+        cmp: Cmp,
         a: Location,
         b: Location,
         run: Run,
     },
+    ExecuteContext {
+        context: Vec<Context>,
+        run: Run,
+    },
+    ExecuteStoreResultScore {
+        location: Location,
+        command: String,
+    },
+    ExecuteStoreResultGetScore {
+        location: Location,
+        target: String,
+    },
     Function {
         block: Index,
-    }
+    },
+    Enter {
+        frame_size: u32,
+    },
+    LoadArg {
+        location: Location,
+        index: usize,
+    },
+    Call {
+        id: usize,
+        args: Vec<Location>,
+        result: Location,
+    },
+    CallIndirect {
+        function: Location,
+        args: Vec<Location>,
+        result: Location,
+    },
+    Return {
+        location: Location,
+        frame_size: u32,
+    },
+    TailCall {
+        id: usize,
+        args: Vec<Location>,
+        frame_size: u32,
+    },
+    TailCallIndirect {
+        function: Location,
+        args: Vec<Location>,
+        frame_size: u32,
+    },
+    Disarm {
+        location: Location,
+        frame_size: u32,
+    },
+    GetScore {
+        location: Location,
+        target: String,
+        objective: String,
+    },
+    SetScore {
+        target: String,
+        objective: String,
+        location: Location,
+    },
+    Schedule {
+        block: Index,
+        ticks: i64,
+    },
+    Execute {
+        run: Run,
+    },
+    Asm {
+        template: Vec<Fragment>,
+        locations: Vec<Location>,
+    },
 }
 
 #[derive(Debug, Clone)]
 pub enum Run {
     Function { block: Index },
     Set { location: Location, value: i64 },
+    /// Runs the block and returns too if it returned.
+    ReturnIfFunction { block: Index },
+    /// Runs the block unless something before it has returned.
+    FunctionUnlessReturned { block: Index },
 }
 
-pub fn insert_jmps(program: prev::Program) -> Program {
-    let blocks = program.blocks.map(|idx, b| {
-        insert_jmps_block(idx, b.clone(), &program.blocks)
+/// The first pack format that can pass a return on with `execute if function`. Older ones set
+/// a flag instead, and every jump after one that may have returned checks it.
+const RETURN_PACK_FORMAT: usize = 26;
+
+pub fn insert_jmps(program: prev::Program, options: &Options) -> Program {
+    let returning = returning_blocks(&program.blocks);
+    let return_command = options.pack_format >= RETURN_PACK_FORMAT;
+    let mut blocks = program.blocks.map(|idx, b| {
+        insert_jmps_block(idx, b.clone(), &program.blocks, &returning, return_command)
     }, |_, _| ());
 
-    Program { blocks, tests: program.tests }
+    // The flag is cleared once the return has made it all the way out.
+    if !return_command {
+        let entries = program.tests.iter().map(|t| t.block).chain(program.functions.iter().map(|f| f.block));
+        for entry in entries.filter(|entry| returning.contains(entry)) {
+            blocks[entry].instrs.push(Instruction::Command {
+                text: "scoreboard players set returned registry 0".to_owned(),
+            });
+        }
+    }
+
+    Program {
+        blocks,
+        tests: program.tests,
+        functions: program.functions,
+        handlers: program.handlers,
+        teardown: program.teardown,
+        frames: program.frames,
+    }
+}
+
+/// The blocks that may return early, themselves or through a block they jump to.
+fn returning_blocks(graph: &prev::Graph) -> HashSet<Index> {
+    let mut returning: HashSet<Index> = graph
+        .node_indices()
+        .filter(|idx| {
+            graph[*idx]
+                .instrs
+                .iter()
+                .any(|instr| matches!(instr, prev::Instruction::EarlyReturn { .. }))
+        })
+        .collect();
+    loop {
+        let before = returning.len();
+        for edge in graph.edge_references() {
+            if !matches!(edge.weight(), prev::Jmp::Resume) && returning.contains(&edge.target()) {
+                returning.insert(edge.source());
+            }
+        }
+        if returning.len() == before {
+            return returning;
+        }
+    }
 }
 
-fn insert_jmps_block(idx: Index, block: prev::Block, graph: &prev::Graph) -> Block {
-    let mut instrs: Vec<Instruction> = block.instrs.into_iter().map(insert_jmps_instr).collect();
-    for e in graph.edges_directed(idx, Direction::Outgoing) {
-        instrs.push(insert_jmps_jmp(e.weight().clone()));
+fn insert_jmps_block(
+    idx: Index,
+    block: prev::Block,
+    graph: &prev::Graph,
+    returning: &HashSet<Index>,
+    return_command: bool,
+) -> Block {
+    let mut instrs: Vec<Instruction> = block
+        .instrs
+        .into_iter()
+        .flat_map(|instr| insert_jmps_instr(instr, return_command))
+        .collect();
+    // The join back to the code after an if has to run once both branches have been checked.
+    let mut jmps: Vec<(prev::Jmp, Index)> = graph
+        .edges_directed(idx, Direction::Outgoing)
+        .map(|e| (e.weight().clone(), e.target()))
+        .collect();
+    jmps.sort_by_key(|(jmp, _)| matches!(jmp, prev::Jmp::Join { .. }));
+    let mut returned = false;
+    for (jmp, target) in jmps {
+        let run = |block| {
+            if return_command && returning.contains(&block) {
+                Run::ReturnIfFunction { block }
+            } else if returned {
+                Run::FunctionUnlessReturned { block }
+            } else {
+                Run::Function { block }
+            }
+        };
+        instrs.extend(insert_jmps_jmp(jmp, run));
+        returned |= !return_command && returning.contains(&target);
     }
     Block { instrs }
 }
 
-fn insert_jmps_instr(instr: prev::Instruction) -> Instruction {
-    match instr {
+fn insert_jmps_instr(instr: prev::Instruction, return_command: bool) -> Vec<Instruction> {
+    let instr = match instr {
+        prev::Instruction::EarlyReturn { location, frame_size } => {
+            let mut instrs = Vec::new();
+            if let Some(location) = location {
+                instrs.push(Instruction::Return { location, frame_size });
+            }
+            instrs.push(Instruction::Command {
+                text: if return_command {
+                    "return 1".to_owned()
+                } else {
+                    "scoreboard players set returned registry 1".to_owned()
+                },
+            });
+            return instrs;
+        }
         prev::Instruction::Set { location, value } => Instruction::Set { location, value },
         prev::Instruction::Operation { op, source, destination } => Instruction::Operation { op, source, destination },
-        prev::Instruction::Tellraw { text } => Instruction::Tellraw { text },
+        prev::Instruction::Tellraw { components } => Instruction::Tellraw { components },
         prev::Instruction::Command { text } => Instruction::Command { text },
         prev::Instruction::ExecuteIfScoreMatchesSet { location, value, set_location, set_value } =>
             Instruction::ExecuteIfScoreMatches {
@@ -102,27 +272,57 @@ fn insert_jmps_instr(instr: prev::Instruction) -> Instruction {
                 value,
                 run: Run::Set { location: set_location, value: set_value },
             },
-        prev::Instruction::ExecuteIfScoreEqualsSet { a, b, set_location, set_value } =>
-            Instruction::ExecuteIfScoreEquals {
+        prev::Instruction::ExecuteIfScoreCompareSet { cmp, a, b, set_location, set_value } =>
+            Instruction::ExecuteIfScoreCompare {
+                cmp,
                 a,
                 b,
                 run: Run::Set { location: set_location, value: set_value },
             },
-        prev::Instruction::ExecuteUnlessScoreEqualsSet { a, b, set_location, set_value } =>
-            Instruction::ExecuteUnlessScoreEquals {
+        prev::Instruction::ExecuteUnlessScoreCompareSet { cmp, a, b, set_location, set_value } =>
+            Instruction::ExecuteUnlessScoreCompare {
+                cmp,
                 a,
                 b,
                 run: Run::Set { location: set_location, value: set_value },
-            }
-    }
+            },
+        prev::Instruction::ExecuteStoreResultScore { location, command } =>
+            Instruction::ExecuteStoreResultScore { location, command },
+        prev::Instruction::ExecuteStoreResultGetScore { location, target } =>
+            Instruction::ExecuteStoreResultGetScore { location, target },
+        prev::Instruction::Enter { frame_size } => Instruction::Enter { frame_size },
+        prev::Instruction::LoadArg { location, index } => Instruction::LoadArg { location, index },
+        prev::Instruction::Call { id, args, result } => Instruction::Call { id, args, result },
+        prev::Instruction::CallIndirect { function, args, result } =>
+            Instruction::CallIndirect { function, args, result },
+        prev::Instruction::Return { location, frame_size } => Instruction::Return { location, frame_size },
+        prev::Instruction::TailCall { id, args, frame_size } => Instruction::TailCall { id, args, frame_size },
+        prev::Instruction::TailCallIndirect { function, args, frame_size } =>
+            Instruction::TailCallIndirect { function, args, frame_size },
+        prev::Instruction::Disarm { location, frame_size } => Instruction::Disarm { location, frame_size },
+        prev::Instruction::GetScore { location, target, objective } =>
+            Instruction::GetScore { location, target, objective },
+        prev::Instruction::SetScore { target, objective, location } =>
+            Instruction::SetScore { target, objective, location },
+        prev::Instruction::Schedule { block, ticks } => Instruction::Schedule { block, ticks },
+        prev::Instruction::Asm { template, locations } => Instruction::Asm { template, locations },
+    };
+    vec![instr]
 }
 
-fn insert_jmps_jmp(jmp: prev::Jmp) -> Instruction {
-    match jmp {
-        prev::Jmp::ExecuteIfScoreMatchesFunction { location, value, block } => Instruction::ExecuteIfScoreMatches { location, value, run: Run::Function { block } },
-        prev::Jmp::ExecuteUnlessScoreMatchesFunction { location, value, block } => Instruction::ExecuteUnlessScoreMatches { location, value, run: Run::Function { block } },
-        prev::Jmp::ExecuteIfScoreEqualsFunction { a, b, block } => Instruction::ExecuteIfScoreEquals { a, b, run: Run::Function { block } },
-        prev::Jmp::ExecuteUnlessScoreEqualsFunction { a, b, block } => Instruction::ExecuteUnlessScoreEquals { a, b, run: Run::Function { block } },
-        prev::Jmp::Function { block } => Instruction::Function { block },
-    }
+fn insert_jmps_jmp(jmp: prev::Jmp, run: impl Fn(Index) -> Run) -> Option<Instruction> {
+    let instr = match jmp {
+        prev::Jmp::ExecuteIfScoreMatchesFunction { location, value, block } => Instruction::ExecuteIfScoreMatches { location, value, run: run(block) },
+        prev::Jmp::ExecuteUnlessScoreMatchesFunction { location, value, block } => Instruction::ExecuteUnlessScoreMatches { location, value, run: run(block) },
+        prev::Jmp::ExecuteIfScoreCompareFunction { cmp, a, b, block } => Instruction::ExecuteIfScoreCompare { cmp, a, b, run: run(block) },
+        prev::Jmp::ExecuteUnlessScoreCompareFunction { cmp, a, b, block } => Instruction::ExecuteUnlessScoreCompare { cmp, a, b, run: run(block) },
+        prev::Jmp::Function { block } | prev::Jmp::Join { block } => match run(block) {
+            Run::Function { block } => Instruction::Function { block },
+            run => Instruction::Execute { run },
+        },
+        prev::Jmp::Resume => return None,
+        prev::Jmp::ExecuteContextFunction { context, block } =>
+            Instruction::ExecuteContext { context, run: run(block) },
+    };
+    Some(instr)
 }
\ No newline at end of file
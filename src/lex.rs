@@ -1,48 +1,18 @@
 use std::{fmt::Display, iter::Peekable};
 
-use crate::utility::escape;
+use crate::utility::{escape, Quoting};
 use anyhow::{anyhow, Result};
 
 #[derive(PartialEq, Eq, Debug)]
 pub enum Token {
     LeftParen,
     RightParen,
-    Plus,
-    Dash,
-    Star,
-    Slash,
-    DoubleEquals,
-    Ident(String),
-    Test,
-    Assert,
-    AssertEq,
-    Let,
-    If,
-    Boolean(bool),
+    Symbol(String),
     Int(i64),
+    Decimal(String),
     String(String),
-}
-
-impl Token {
-    fn ident(s: String) -> Token {
-        if &s == "test" {
-            Token::Test
-        } else if &s == "assert" {
-            Token::Assert
-        } else if &s == "asserteq" {
-            Token::AssertEq
-        } else if &s == "let" {
-            Token::Let
-        } else if &s == "if" {
-            Token::If
-        } else if &s == "true" {
-            Token::Boolean(true)
-        } else if &s == "false" {
-            Token::Boolean(false)
-        } else {
-            Token::Ident(s)
-        }
-    }
+    /// Kept so that the reader can tell which line each list starts on.
+    Newline,
 }
 
 impl Display for Token {
@@ -50,30 +20,21 @@ impl Display for Token {
         match self {
             Token::LeftParen => write!(f, "("),
             Token::RightParen => write!(f, ")"),
-            Token::Plus => write!(f, "+"),
-            Token::Dash => write!(f, "-"),
-            Token::Star => write!(f, "*"),
-            Token::Slash => write!(f, "/"),
-            Token::DoubleEquals => write!(f, "=="),
-            Token::Ident(i) => write!(f, "{}", i),
-            Token::Test => write!(f, "test"),
-            Token::Assert => write!(f, "assert"),
-            Token::AssertEq => write!(f, "asserteq"),
-            Token::Let => write!(f, "let"),
-            Token::If => write!(f, "if"),
-            Token::Boolean(b) => {
-                if *b {
-                    write!(f, "true")
-                } else {
-                    write!(f, "false")
-                }
-            }
+            Token::Symbol(s) => write!(f, "{}", s),
             Token::Int(i) => write!(f, "{}", i),
-            Token::String(s) => write!(f, "{}", escape(s)),
+            Token::Decimal(d) => write!(f, "{}", d),
+            Token::String(s) => write!(f, "{}", escape(s, Quoting::Source)),
+            Token::Newline => writeln!(f),
         }
     }
 }
 
+/// Whether `c` can be part of a symbol. Symbols cannot start with a digit, and a `-` followed by
+/// one starts a number instead.
+fn is_symbol_char(c: char) -> bool {
+    c.is_alphanumeric() || "+-*/=<>?!:_".contains(c)
+}
+
 struct Characters {
     data: Vec<char>,
     counter: usize,
@@ -106,54 +67,36 @@ pub fn lex(source: &str) -> Result<Vec<Token>> {
             tokens.push(Token::LeftParen);
         } else if c == ')' {
             tokens.push(Token::RightParen);
-        } else if c == '+' {
-            tokens.push(Token::Plus);
-        } else if c == '*' {
-            tokens.push(Token::Star);
-        } else if c == '/' {
-            tokens.push(Token::Slash);
-        } else if c == '=' && cs.peek() == Some(&'=') {
-            cs.next();
-            tokens.push(Token::DoubleEquals);
-        } else if c.is_alphabetic() {
+        } else if c.is_numeric() || (c == '-' && cs.peek().is_some_and(|p| p.is_numeric())) {
+            tokens.push(lex_int(c, &mut cs)?);
+        } else if is_symbol_char(c) {
             let mut s = String::from(c);
-
             while let Some(p) = cs.peek() {
-                if p.is_alphanumeric() {
+                if is_symbol_char(*p) {
                     s.push(cs.next().unwrap())
                 } else {
                     break;
                 }
             }
-
-            tokens.push(Token::ident(s))
+            tokens.push(Token::Symbol(s))
         } else if c == '"' {
             let mut s = String::new();
+            // Lines a string spans still count, after the string itself.
+            let mut newlines = 0;
             while let Some(c) = cs.next() {
                 if c == '"' {
                     tokens.push(Token::String(s.clone()));
                     break;
                 } else if c == '\\' {
-                    match cs.next() {
-                        Some('"') => s.push('"'),
-                        Some('\\') => s.push('\\'),
-                        Some(d) => return Err(anyhow!("Unknown escape sequence \\{d} in string.")),
-                        None => return Err(anyhow!("String ends with an unescaped '\\'")),
-                    }
+                    s.push(lex_escape(&mut cs)?);
                 } else {
+                    newlines += usize::from(c == '\n');
                     s.push(c);
                 }
             }
-        } else if c.is_numeric() {
-            tokens.push(lex_int(c, &mut cs)?);
-        } else if c == '-' {
-            if let Some(next) = cs.peek() {
-                if next.is_numeric() {
-                    tokens.push(lex_int(c, &mut cs)?);
-                } else {
-                    tokens.push(Token::Dash);
-                }
-            }
+            tokens.extend(std::iter::repeat_with(|| Token::Newline).take(newlines));
+        } else if c == '\n' {
+            tokens.push(Token::Newline);
         } else if c.is_whitespace() {
         } else {
             return Err(anyhow!("Unexpected character: {}", c));
@@ -163,6 +106,36 @@ pub fn lex(source: &str) -> Result<Vec<Token>> {
     Ok(tokens)
 }
 
+fn lex_escape(cs: &mut Peekable<Characters>) -> Result<char> {
+    match cs.next() {
+        Some('"') => Ok('"'),
+        Some('\\') => Ok('\\'),
+        Some('n') => Ok('\n'),
+        Some('t') => Ok('\t'),
+        Some('r') => Ok('\r'),
+        Some('0') => Ok('\0'),
+        Some('u') => {
+            if cs.next() != Some('{') {
+                return Err(anyhow!("Expected {{ after \\u in string"));
+            }
+            let mut hex = String::new();
+            loop {
+                match cs.next() {
+                    Some('}') => break,
+                    Some(h) if h.is_ascii_hexdigit() && hex.len() < 6 => hex.push(h),
+                    _ => return Err(anyhow!("Expected up to six hex digits and }} after \\u{{")),
+                }
+            }
+            u32::from_str_radix(&hex, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| anyhow!("\\u{{{hex}}} is not a Unicode character"))
+        }
+        Some(d) => Err(anyhow!("Unknown escape sequence \\{d} in string.")),
+        None => Err(anyhow!("String ends with an unescaped '\\'")),
+    }
+}
+
 fn lex_int(c: char, cs: &mut Peekable<Characters>) -> Result<Token> {
     let mut i = String::from(c);
     loop {
@@ -171,27 +144,51 @@ fn lex_int(c: char, cs: &mut Peekable<Characters>) -> Result<Token> {
             Some(p) if *p == '_' => {
                 cs.next();
             }
+            Some(p) if *p == '.' => {
+                i.push(cs.next().unwrap());
+                return lex_decimal(i, cs);
+            }
             _ => break Ok(Token::Int(i.parse()?)),
         }
     }
 }
 
+/// Decimals are only used as scale factors of NBT values, which Minecraft reads itself, so they
+/// are kept as written.
+fn lex_decimal(mut d: String, cs: &mut Peekable<Characters>) -> Result<Token> {
+    while let Some(p) = cs.peek() {
+        if p.is_numeric() {
+            d.push(cs.next().unwrap());
+        } else {
+            break;
+        }
+    }
+    if d.ends_with('.') {
+        return Err(anyhow!("Expected digits after the decimal point in {d}"));
+    }
+    Ok(Token::Decimal(d))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use Token::*;
-    #[test]
 
+    fn symbols(names: &str) -> Vec<Token> {
+        names.split(' ').map(|name| Symbol(name.to_owned())).collect()
+    }
+
+    #[test]
     fn bools() -> Result<()> {
         assert_eq!(
             vec![
                 LeftParen,
-                Test,
+                Symbol("test".to_owned()),
                 String(r#"a "test" \named\ test"#.to_owned()),
                 LeftParen,
-                Assert,
-                Boolean(true),
-                Boolean(false),
+                Symbol("assert".to_owned()),
+                Symbol("true".to_owned()),
+                Symbol("false".to_owned()),
                 RightParen,
                 RightParen
             ],
@@ -200,9 +197,31 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn escapes() -> Result<()> {
+        assert_eq!(
+            vec![String("a\tb\nc\r\0\"\\ é \u{1F600}".to_owned())],
+            lex(r#""a\tb\nc\r\0\"\\ é \u{1F600}""#)?
+        );
+        assert!(lex(r#""\u{110000}""#).is_err());
+        assert!(lex(r#""\u{}""#).is_err());
+        assert!(lex(r#""\q""#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn newlines_in_strings() -> Result<()> {
+        assert_eq!(
+            vec![String("a\nb".to_owned()), Newline, Symbol("c".to_owned())],
+            lex("\"a\nb\" c")?
+        );
+        assert_eq!(vec![String("a\nb".to_owned())], lex(r#""a\nb""#)?);
+        Ok(())
+    }
+
     #[test]
     fn slash() -> Result<()> {
-        assert_eq!(vec![Slash], lex("/")?);
+        assert_eq!(symbols("/"), lex("/")?);
         Ok(())
     }
 
@@ -217,31 +236,68 @@ mod test {
 
     #[test]
     fn asserteq() -> Result<()> {
-        assert_eq!(vec![AssertEq], lex("asserteq")?);
+        assert_eq!(symbols("asserteq"), lex("asserteq")?);
         Ok(())
     }
 
     #[test]
     fn arithmetic() -> Result<()> {
-        assert_eq!(vec![Plus, Dash, Star, Slash], lex("+ - * /")?);
+        assert_eq!(symbols("+ - * /"), lex("+ - * /")?);
         Ok(())
     }
 
     #[test]
     fn r#let() -> Result<()> {
-        assert_eq!(vec![Let], lex("let")?);
+        assert_eq!(symbols("let"), lex("let")?);
         Ok(())
     }
 
     #[test]
     fn r#if() -> Result<()> {
-        assert_eq!(vec![If], lex("if")?);
+        assert_eq!(symbols("if"), lex("if")?);
         Ok(())
     }
 
     #[test]
     fn double_equals() -> Result<()> {
-        assert_eq!(vec![DoubleEquals], lex("==")?);
+        assert_eq!(symbols("=="), lex("==")?);
+        Ok(())
+    }
+
+    #[test]
+    fn arrays() -> Result<()> {
+        assert_eq!(
+            symbols("array make-array array-get array-set! array-push! length"),
+            lex("array make-array array-get array-set! array-push! length")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dash_in_identifier() -> Result<()> {
+        assert_eq!(
+            vec![LeftParen, Symbol("-".to_owned()), Symbol("player-count".to_owned()), Int(1), RightParen],
+            lex("(- player-count 1)")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn lisp_symbols() -> Result<()> {
+        assert_eq!(
+            symbols("assert-eq empty? set-seed! minecraft:stone <="),
+            lex("assert-eq empty? set-seed! minecraft:stone <=")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn decimals() -> Result<()> {
+        assert_eq!(
+            vec![Symbol("nbt-get".to_owned()), Decimal("0.01".to_owned()), Decimal("-2.5".to_owned()), Int(3)],
+            lex("nbt-get 0.01 -2.5 3")?
+        );
+        assert!(lex("1.").is_err());
         Ok(())
     }
 }
@@ -2,12 +2,16 @@ mod assign_homes;
 mod datapack;
 mod desugar_asserts;
 mod emit_text;
+mod filter_tests;
 mod lex;
 mod linearize;
 mod parse;
+mod read;
 mod reify_locations;
 mod runtime;
 mod select_instructions;
+mod tail_calls;
+mod type_check;
 mod uniquify;
 mod utility;
 mod var;
@@ -18,31 +22,71 @@ use assign_homes::assign_homes;
 pub use datapack::Datapack;
 use desugar_asserts::desugar_asserts;
 use emit_text::emit_text;
+pub use filter_tests::Filter;
+use filter_tests::filter_tests;
 use lex::lex;
 use linearize::linearize;
 use parse::parse;
+use read::read;
 use reify_locations::reify_location;
 use select_instructions::select_instructions;
+use tail_calls::report_tail_calls as report;
 use tap::pipe::Pipe;
+use type_check::type_check;
 use uniquify::uniquify;
 use insert_jmps::insert_jmps;
 
+/// Settings for the datapack being compiled.
+pub struct Options {
+    /// The data pack format of the Minecraft version the datapack is for.
+    pub pack_format: usize,
+    /// When set, only the tests whose name matches this, or the name of a describe around them,
+    /// are compiled.
+    pub filter: Option<Filter>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            pack_format: 18,
+            filter: None,
+        }
+    }
+}
+
 pub fn compile(source: &str) -> Result<Datapack> {
-    let functions = source
+    compile_with_options(source, &Options::default())
+}
+
+pub fn compile_with_options(source: &str, options: &Options) -> Result<Datapack> {
+    let (functions, tags) = source
         .pipe(lex)?
+        .pipe(read)?
         .pipe(parse)?
-        .pipe(uniquify)
+        .pipe(|defs| match &options.filter {
+            Some(filter) => filter_tests(defs, filter),
+            None => defs,
+        })
+        .pipe(uniquify)?
+        .pipe(|program| type_check(program, options))?
         .pipe(desugar_asserts)
         .pipe(linearize)
         .pipe(select_instructions)
-        .pipe(assign_homes)
-        .pipe(insert_jmps)
+        .pipe(assign_homes)?
+        .pipe(|program| insert_jmps(program, options))
         .pipe(reify_location)
         .pipe(emit_text);
 
     Ok(Datapack {
         description: "Datapack generated by MCML".to_owned(),
-        pack_format: 18,
+        pack_format: options.pack_format,
         functions,
+        tags,
     })
 }
+
+/// Describes each call made from a function that could not be compiled as a tail call.
+pub fn report_tail_calls(source: &str) -> Result<Vec<String>> {
+    let program = source.pipe(lex)?.pipe(read)?.pipe(parse)?.pipe(uniquify)?;
+    Ok(report(&program))
+}
@@ -1,8 +1,10 @@
 use std::collections::VecDeque;
+use std::fmt::Display;
 
 use itertools::Itertools;
 
 use crate::desugar_asserts as prev;
+pub use crate::desugar_asserts::{Component, Context, Diagnostic, Directive, Event, Fragment, NbtSource};
 use crate::var::{Var, VarFactory};
 
 pub type Graph = petgraph::Graph<Block, Jmp, petgraph::Directed, u32>;
@@ -11,6 +13,10 @@ pub type Index = petgraph::graph::NodeIndex<u32>;
 pub struct Program {
     pub blocks: Graph,
     pub tests: Vec<Test>,
+    pub functions: Vec<Function>,
+    pub handlers: Vec<Handler>,
+    /// The id of the function the teardown is kept in, when there is one.
+    pub teardown: Option<usize>,
     pub var_factory: VarFactory,
 }
 
@@ -21,9 +27,16 @@ pub struct Block {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Jmp {
-    Unconditional,
     If(Condition),
     Unless(Condition),
+    /// Runs the target under `execute`, once for every entity the contexts select.
+    Context(Vec<Context>),
+    /// Taken once the conditional jumps out of a block have returned. `live` is read on the way
+    /// there, for what a branch that may not run at all writes.
+    Join { live: Vec<Var> },
+    /// Not a jump: the end of a branch, which returns to the block it was entered from. Control
+    /// then carries on at the target, but `live` is still read on the way there.
+    Resume { live: Vec<Var> },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -35,9 +48,43 @@ pub enum Condition {
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub enum Statement {
     Assign { var: Var, expr: Expr },
-    TellOk { test_name: String },
-    TellNotOk { test_name: String },
+    TellOk {
+        test_name: String,
+        groups: Vec<usize>,
+        directive: Option<Directive>,
+    },
+    TellNotOk {
+        test_name: String,
+        groups: Vec<usize>,
+        directive: Option<Directive>,
+        diagnostic: Diagnostic,
+    },
     Command { text: String },
+    ArraySet { array: Atom, index: Atom, field: Option<String>, value: Atom },
+    ArrayPush { array: Atom, value: Element },
+    Enter { params: Vec<Var> },
+    Return { value: Atom },
+    /// A call in tail position, made once the caller's frame has been released.
+    TailCall { call: Expr },
+    /// Sets the condition of an if in tail position to false once its else branch has returned,
+    /// so the then branch is not taken as well.
+    Disarm { var: Var },
+    SetScore { target: String, objective: String, value: Atom },
+    NbtSet { source: NbtSource, path: String, ty: String, scale: String, value: Atom },
+    /// Saves `vars` for the body starting at `block` and schedules it to run `ticks` from now.
+    Schedule { block: Index, ticks: i64, vars: Vec<Var> },
+    /// Loads the variables the body starting at `block` was scheduled with.
+    Restore { block: Index, vars: Vec<Var> },
+    /// Leaves the test or function right away, with `value` as the result of a function.
+    EarlyReturn { value: Option<Atom> },
+    /// A command with the locations of `vars` filled in once they are known.
+    Asm { template: Vec<Fragment>, vars: Vec<Var> },
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Element {
+    Scalar(Atom),
+    Compound(Vec<(String, Atom)>),
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -45,6 +92,15 @@ pub enum Expr {
     Atom(Atom),
     Binary { op: Op, left: Atom, right: Atom },
     Cmp { cmp: Cmp, left: Atom, right: Atom },
+    Array { elements: Vec<Element> },
+    ArrayGet { array: Atom, index: Atom, field: Option<String> },
+    ArrayEq { left: Atom, right: Atom },
+    Length { array: Atom },
+    Call { id: usize, args: Vec<Atom> },
+    CallIndirect { function: Atom, args: Vec<Atom> },
+    Score { target: String, objective: String },
+    NbtGet { source: NbtSource, path: String, scale: String },
+    Random { lo: Atom, hi: Atom },
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -63,42 +119,128 @@ pub enum Op {
     Divide,
 }
 
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum Cmp {
     Eq,
+    Less,
+    LessEq,
+}
+
+/// As written between the scores of `execute if score`.
+impl Display for Cmp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Cmp::Eq => write!(f, "="),
+            Cmp::Less => write!(f, "<"),
+            Cmp::LessEq => write!(f, "<="),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Test {
     pub name: String,
     pub block: Index,
+    /// The describes the test is in, outermost first.
+    pub groups: Vec<Group>,
+    /// Whether the test is skipped or still to do, as its points are marked.
+    pub directive: Option<Directive>,
+    /// How many blocks the test may enter before it is stopped and fails.
+    pub budget: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Group {
+    pub id: usize,
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub id: usize,
+    pub block: Index,
+}
+
+#[derive(Debug, Clone)]
+pub struct Handler {
+    pub event: Event,
+    pub block: Index,
 }
 
 pub fn linearize(
     prev::Program {
         defs,
+        teardown,
         mut var_factory,
     }: prev::Program,
 ) -> Program {
     let mut blocks = Graph::new();
     let mut tests = Vec::new();
+    let mut functions = Vec::new();
+    let mut handlers = Vec::new();
 
     for def in defs {
-        let prev::Definition::Test { name, stmts } = def;
-
-        tests.push(Test {
-            name,
-            block: linearize_stmts(&mut var_factory, &mut blocks, stmts),
-        });
+        match def {
+            def @ (prev::Definition::Test { .. } | prev::Definition::Describe { .. }) => {
+                linearize_test(&mut var_factory, &mut blocks, &mut tests, &[], def)
+            }
+            prev::Definition::Function {
+                id,
+                params,
+                stmts,
+                result,
+            } => functions.push(Function {
+                id,
+                block: linearize_function(&mut var_factory, &mut blocks, params, stmts, result),
+            }),
+            prev::Definition::Handler { event, stmts } => handlers.push(Handler {
+                event,
+                block: linearize_stmts(&mut var_factory, &mut blocks, stmts),
+            }),
+        }
     }
 
     Program {
         blocks,
         tests,
+        functions,
+        handlers,
+        teardown,
         var_factory,
     }
 }
 
+/// Adds a test, or each of the tests in a describe, along with the describes around it.
+fn linearize_test(
+    var_factory: &mut VarFactory,
+    blocks: &mut Graph,
+    tests: &mut Vec<Test>,
+    groups: &[Group],
+    def: prev::Definition,
+) {
+    match def {
+        prev::Definition::Test {
+            name,
+            directive,
+            budget,
+            stmts,
+        } => tests.push(Test {
+            name,
+            block: linearize_stmts(var_factory, blocks, stmts),
+            groups: groups.to_vec(),
+            directive,
+            budget,
+        }),
+        prev::Definition::Describe { id, name, defs } => {
+            let groups = [groups, &[Group { id, name }]].concat();
+            for def in defs {
+                linearize_test(var_factory, blocks, tests, &groups, def);
+            }
+        }
+        _ => unreachable!("describes only hold tests"),
+    }
+}
+
 fn linearize_stmts(
     var_factory: &mut VarFactory,
     blocks: &mut Graph,
@@ -114,23 +256,107 @@ fn linearize_stmts(
     begin
 }
 
+fn linearize_function(
+    var_factory: &mut VarFactory,
+    blocks: &mut Graph,
+    params: Vec<Var>,
+    stmts: Vec<prev::Statement>,
+    result: prev::Expr,
+) -> Index {
+    let begin = blocks.add_node(Block {
+        stmts: vec![Statement::Enter { params }],
+    });
+    let mut current = begin;
+
+    for stmt in stmts {
+        linearize_stmt(var_factory, blocks, &mut current, stmt);
+    }
+    linearize_tail(var_factory, blocks, &mut current, result);
+
+    begin
+}
+
+fn linearize_tail(var_factory: &mut VarFactory, blocks: &mut Graph, current: &mut Index, expr: prev::Expr) {
+    match expr {
+        prev::Expr::Call { id, args } => {
+            let args = args
+                .into_iter()
+                .map(|arg| linearize_expr(var_factory, blocks, current, arg))
+                .collect();
+            blocks[*current].stmts.push(Statement::TailCall { call: Expr::Call { id, args } });
+        }
+        prev::Expr::CallIndirect { function, args } => {
+            let function = linearize_expr(var_factory, blocks, current, *function);
+            let args = args
+                .into_iter()
+                .map(|arg| linearize_expr(var_factory, blocks, current, arg))
+                .collect();
+            blocks[*current].stmts.push(Statement::TailCall { call: Expr::CallIndirect { function, args } });
+        }
+        prev::Expr::If { cond, thn, els } => {
+            // Both branches leave the function, so there is nothing to join. The else branch is
+            // checked first and the then branch is checked after it has returned.
+            let cond = linearize_expr(var_factory, blocks, current, *cond);
+
+            let mut thn_block = blocks.add_node(Block { stmts: Vec::new() });
+            blocks.add_edge(*current, thn_block, Jmp::If(Condition::Atm(cond.clone())));
+            linearize_tail(var_factory, blocks, &mut thn_block, *thn);
+
+            let mut els_block = blocks.add_node(Block { stmts: Vec::new() });
+            blocks.add_edge(*current, els_block, Jmp::Unless(Condition::Atm(cond.clone())));
+            linearize_tail(var_factory, blocks, &mut els_block, *els);
+
+            if let Atom::Var(var) = cond {
+                let disarm = blocks.add_node(Block { stmts: vec![Statement::Disarm { var }] });
+                blocks.add_edge(els_block, disarm, Jmp::Join { live: Vec::new() });
+            }
+        }
+        expr => {
+            let value = linearize_expr(var_factory, blocks, current, expr);
+            blocks[*current].stmts.push(Statement::Return { value });
+        }
+    }
+}
+
 fn linearize_stmt(var_factory: &mut VarFactory, blocks: &mut Graph, current: &mut Index, stmt: prev::Statement) {
     match stmt {
         prev::Statement::Expr(expr) => {
             let _ = linearize_expr(var_factory, blocks, current, expr);
         },
-        prev::Statement::TellOk { test_name } => {
+        prev::Statement::TellOk {
+            test_name,
+            groups,
+            directive,
+        } => {
             let stmts = &mut blocks.node_weight_mut(*current).unwrap().stmts;
-            stmts.push(Statement::TellOk { test_name });
+            stmts.push(Statement::TellOk {
+                test_name,
+                groups,
+                directive,
+            });
         },
-        prev::Statement::TellNotOk { test_name } => {
+        prev::Statement::TellNotOk {
+            test_name,
+            groups,
+            directive,
+            diagnostic,
+        } => {
             let stmts = &mut blocks.node_weight_mut(*current).unwrap().stmts;
-            stmts.push(Statement::TellNotOk { test_name });
+            stmts.push(Statement::TellNotOk {
+                test_name,
+                groups,
+                directive,
+                diagnostic,
+            });
         },
         prev::Statement::Command { text } => {
             let stmts = &mut blocks.node_weight_mut(*current).unwrap().stmts;
             stmts.push(Statement::Command { text });
         }
+        prev::Statement::Asm { template, vars } => {
+            let stmts = &mut blocks.node_weight_mut(*current).unwrap().stmts;
+            stmts.push(Statement::Asm { template, vars });
+        }
         prev::Statement::Let { var, expr } => {
             let atom = linearize_expr(var_factory, blocks, current, expr);
             let stmts = &mut blocks.node_weight_mut(*current).unwrap().stmts;
@@ -139,6 +365,235 @@ fn linearize_stmt(var_factory: &mut VarFactory, blocks: &mut Graph, current: &mu
                 expr: Expr::Atom(atom),
             });
         }
+        prev::Statement::ArraySet { array, index, field, value } => {
+            let array = linearize_expr(var_factory, blocks, current, array);
+            let index = linearize_expr(var_factory, blocks, current, index);
+            let value = linearize_expr(var_factory, blocks, current, value);
+            let stmts = &mut blocks.node_weight_mut(*current).unwrap().stmts;
+            stmts.push(Statement::ArraySet { array, index, field, value });
+        }
+        prev::Statement::ArrayPush { array, value } => {
+            let array = linearize_expr(var_factory, blocks, current, array);
+            let value = linearize_element(var_factory, blocks, current, value);
+            let stmts = &mut blocks.node_weight_mut(*current).unwrap().stmts;
+            stmts.push(Statement::ArrayPush { array, value });
+        }
+        prev::Statement::SetScore { target, objective, value } => {
+            let value = linearize_expr(var_factory, blocks, current, value);
+            let stmts = &mut blocks.node_weight_mut(*current).unwrap().stmts;
+            stmts.push(Statement::SetScore { target, objective, value });
+        }
+        prev::Statement::NbtSet { source, path, ty, scale, value } => {
+            let value = linearize_expr(var_factory, blocks, current, value);
+            let stmts = &mut blocks.node_weight_mut(*current).unwrap().stmts;
+            stmts.push(Statement::NbtSet { source, path, ty, scale, value });
+        }
+        prev::Statement::Execute { context, mut stmts } => {
+            // A block that only holds another one is folded into the same execute command.
+            let mut contexts = vec![context];
+            while let [prev::Statement::Execute { .. }] = stmts.as_slice() {
+                let Some(prev::Statement::Execute { context, stmts: inner }) = stmts.pop() else {
+                    unreachable!()
+                };
+                contexts.push(context);
+                stmts = inner;
+            }
+
+            let begin = blocks.add_node(Block { stmts: Vec::new() });
+            blocks.add_edge(*current, begin, Jmp::Context(contexts));
+            let mut body = begin;
+            for stmt in stmts {
+                linearize_stmt(var_factory, blocks, &mut body, stmt);
+            }
+
+            // The body may run any number of times, so what it reads from outside has to make it
+            // through to the end of the body unchanged.
+            let after = blocks.add_node(Block { stmts: Vec::new() });
+            let live = captured_vars(blocks, begin);
+            blocks.add_edge(body, after, Jmp::Resume { live });
+            // The body writes to variables from outside it, and when it selects no entities
+            // these keep the values they had before.
+            let live = outer_writes(blocks, begin);
+            blocks.add_edge(*current, after, Jmp::Join { live });
+            *current = after;
+        }
+        prev::Statement::After { ticks, stmts } => {
+            // The body runs on its own later on, once the registers have been reused, so what it
+            // reads from outside is saved when it is scheduled and loaded again when it runs.
+            let begin = blocks.add_node(Block { stmts: Vec::new() });
+            let mut body = begin;
+            for stmt in stmts {
+                linearize_stmt(var_factory, blocks, &mut body, stmt);
+            }
+            let vars = captured_vars(blocks, begin);
+            let restore = Statement::Restore { block: begin, vars: vars.clone() };
+            blocks[begin].stmts.insert(0, restore);
+            let stmts = &mut blocks.node_weight_mut(*current).unwrap().stmts;
+            stmts.push(Statement::Schedule { block: begin, ticks, vars });
+        }
+        prev::Statement::When { cond, stmts } => {
+            let cond = linearize_condition(var_factory, blocks, current, cond);
+
+            let begin = blocks.add_node(Block { stmts: Vec::new() });
+            blocks.add_edge(*current, begin, Jmp::If(cond));
+            let mut body = begin;
+            for stmt in stmts {
+                linearize_stmt(var_factory, blocks, &mut body, stmt);
+            }
+
+            // Nothing is checked after the body, so the condition does not need to outlive it.
+            let after = blocks.add_node(Block { stmts: Vec::new() });
+            blocks.add_edge(body, after, Jmp::Resume { live: Vec::new() });
+            blocks.add_edge(*current, after, Jmp::Join { live: Vec::new() });
+            *current = after;
+        }
+        prev::Statement::Return { value } => {
+            let value = value.map(|value| linearize_expr(var_factory, blocks, current, value));
+            let stmts = &mut blocks.node_weight_mut(*current).unwrap().stmts;
+            stmts.push(Statement::EarlyReturn { value });
+            // What follows can never run, so it goes in a block nothing jumps to.
+            *current = blocks.add_node(Block { stmts: Vec::new() });
+        }
+    }
+}
+
+fn linearize_condition(
+    var_factory: &mut VarFactory,
+    blocks: &mut Graph,
+    current: &mut Index,
+    cond: prev::Expr,
+) -> Condition {
+    let (cmp, left, right) = match cond {
+        prev::Expr::Eq { left, right } => (Cmp::Eq, left, right),
+        prev::Expr::Less { left, right } => (Cmp::Less, left, right),
+        prev::Expr::LessEq { left, right } => (Cmp::LessEq, left, right),
+        expr => return Condition::Atm(linearize_expr(var_factory, blocks, current, expr)),
+    };
+    Condition::Cmp {
+        cmp,
+        left: linearize_operand(var_factory, blocks, current, cmp, *left),
+        right: linearize_operand(var_factory, blocks, current, cmp, *right),
+    }
+}
+
+/// Only equality is checked against literals, with `matches`, so the sides of any other
+/// comparison are kept in variables.
+fn linearize_operand(
+    var_factory: &mut VarFactory,
+    blocks: &mut Graph,
+    current: &mut Index,
+    cmp: Cmp,
+    expr: prev::Expr,
+) -> Atom {
+    match linearize_expr(var_factory, blocks, current, expr) {
+        atom @ (Atom::LitInt(_) | Atom::LitBool(_)) if cmp != Cmp::Eq => {
+            linearize_assign(var_factory, blocks, current, Expr::Atom(atom))
+        }
+        atom => atom,
+    }
+}
+
+/// The variables assigned both in `begin` or the blocks added after it, and in those before.
+fn outer_writes(blocks: &Graph, begin: Index) -> Vec<Var> {
+    let (before, after): (Vec<Index>, Vec<Index>) =
+        blocks.node_indices().partition(|idx| idx.index() < begin.index());
+    let outer: Vec<Var> = before.into_iter().flat_map(|idx| assigned_vars(&blocks[idx])).collect();
+    after
+        .into_iter()
+        .flat_map(|idx| assigned_vars(&blocks[idx]))
+        .filter(|var| outer.contains(var))
+        .unique()
+        .collect()
+}
+
+fn assigned_vars(block: &Block) -> Vec<Var> {
+    block
+        .stmts
+        .iter()
+        .flat_map(|stmt| match stmt {
+            Statement::Assign { var, .. } => vec![var.clone()],
+            Statement::Enter { params: vars } | Statement::Restore { vars, .. } => vars.clone(),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+/// The variables read by `begin` and the blocks added after it that are not assigned in them.
+fn captured_vars(blocks: &Graph, begin: Index) -> Vec<Var> {
+    let mut read = Vec::new();
+    let mut read_vars = Vec::new();
+    let mut assigned = Vec::new();
+    for idx in blocks.node_indices().skip(begin.index()) {
+        for stmt in &blocks[idx].stmts {
+            match stmt {
+                Statement::Assign { var, expr } => {
+                    assigned.push(var.clone());
+                    read.extend(expr_atoms(expr));
+                }
+                Statement::ArraySet { array, index, value, .. } => read.extend([array, index, value]),
+                Statement::ArrayPush { array, value } => {
+                    read.push(array);
+                    read.extend(element_atoms(value));
+                }
+                Statement::Return { value }
+                | Statement::SetScore { value, .. }
+                | Statement::NbtSet { value, .. } => read.push(value),
+                Statement::TailCall { call } => read.extend(expr_atoms(call)),
+                Statement::EarlyReturn { value } => read.extend(value),
+                Statement::Enter { params } => assigned.extend(params.iter().cloned()),
+                Statement::Schedule { vars, .. } | Statement::Asm { vars, .. } => {
+                    read_vars.extend(vars.iter().cloned())
+                }
+                Statement::TellOk { .. }
+                | Statement::TellNotOk { .. }
+                | Statement::Command { .. }
+                | Statement::Disarm { .. } => {}
+                // Only gives the variables back the values they already had when scheduled.
+                Statement::Restore { .. } => {}
+            }
+        }
+    }
+    let mut vars: Vec<Var> = read
+        .into_iter()
+        .filter_map(|atom| match atom {
+            Atom::Var(var) => Some(var.clone()),
+            _ => None,
+        })
+        .collect();
+    vars.extend(read_vars);
+    for edge in blocks.edge_indices().filter(|e| blocks.edge_endpoints(*e).unwrap().0 >= begin) {
+        match &blocks[edge] {
+            Jmp::If(cond) | Jmp::Unless(cond) => vars.extend(condition_vars(cond)),
+            Jmp::Resume { live } => vars.extend(live.iter().cloned()),
+            Jmp::Join { live } => vars.extend(live.iter().cloned()),
+            Jmp::Context(_) => {}
+        }
+    }
+    vars.into_iter()
+        .filter(|var| !assigned.contains(var))
+        .unique()
+        .collect()
+}
+
+fn expr_atoms(expr: &Expr) -> Vec<&Atom> {
+    match expr {
+        Expr::Atom(atom) | Expr::Length { array: atom } => vec![atom],
+        Expr::Binary { left, right, .. }
+        | Expr::Cmp { left, right, .. }
+        | Expr::ArrayEq { left, right }
+        | Expr::Random { lo: left, hi: right }
+        | Expr::ArrayGet { array: left, index: right, .. } => vec![left, right],
+        Expr::Array { elements } => elements.iter().flat_map(element_atoms).collect(),
+        Expr::Call { args, .. } => args.iter().collect(),
+        Expr::CallIndirect { function, args } => std::iter::once(function).chain(args).collect(),
+        Expr::Score { .. } | Expr::NbtGet { .. } => vec![],
+    }
+}
+
+fn element_atoms(element: &Element) -> Vec<&Atom> {
+    match element {
+        Element::Scalar(atom) => vec![atom],
+        Element::Compound(fields) => fields.iter().map(|(_, atom)| atom).collect(),
     }
 }
 
@@ -165,14 +620,7 @@ fn linearize_expr(var_factory: &mut VarFactory, blocks: &mut Graph, current: &mu
             linearize_binary(var_factory, blocks, current, Op::Divide, *left, *right)
         }
         prev::Expr::If { cond, thn, els } => {
-            let cond = match *cond {
-                prev::Expr::Eq { left, right } => Condition::Cmp {
-                    cmp: Cmp::Eq,
-                    left: linearize_expr(var_factory, blocks, current, *left),
-                    right: linearize_expr(var_factory, blocks, current, *right),
-                },
-                expr => Condition::Atm(linearize_expr(var_factory, blocks, current, expr)),
-            };
+            let cond = linearize_condition(var_factory, blocks, current, *cond);
             let var = var_factory.tmp();
 
             let mut thn_block = blocks.add_node(Block { stmts: Vec::new() });
@@ -180,12 +628,16 @@ fn linearize_expr(var_factory: &mut VarFactory, blocks: &mut Graph, current: &mu
             linearize_branch(var_factory, blocks, &mut thn_block, var.clone(), *thn);
 
             let mut els_block = blocks.add_node(Block { stmts: Vec::new() });
-            blocks.add_edge(*current, els_block, Jmp::Unless(cond));
+            blocks.add_edge(*current, els_block, Jmp::Unless(cond.clone()));
             linearize_branch(var_factory, blocks, &mut els_block, var.clone(), *els);
 
+            // Each branch returns here when it is done, and the jumps after the one that was
+            // taken still check the condition, so it has to outlive both branches.
             let after = blocks.add_node(Block { stmts: Vec::new() });
-            blocks.add_edge(thn_block, after, Jmp::Unconditional);
-            blocks.add_edge(els_block, after, Jmp::Unconditional);
+            let live = condition_vars(&cond);
+            blocks.add_edge(thn_block, after, Jmp::Resume { live: live.clone() });
+            blocks.add_edge(els_block, after, Jmp::Resume { live });
+            blocks.add_edge(*current, after, Jmp::Join { live: Vec::new() });
 
             *current = after;
             Atom::Var(var)
@@ -193,9 +645,104 @@ fn linearize_expr(var_factory: &mut VarFactory, blocks: &mut Graph, current: &mu
         prev::Expr::Eq { left, right } => {
             linearize_cmp(var_factory, blocks, current, Cmp::Eq, *left, *right)
         }
+        prev::Expr::Less { left, right } => {
+            linearize_cmp(var_factory, blocks, current, Cmp::Less, *left, *right)
+        }
+        prev::Expr::LessEq { left, right } => {
+            linearize_cmp(var_factory, blocks, current, Cmp::LessEq, *left, *right)
+        }
+        prev::Expr::ArrayEq { left, right } => {
+            let left = linearize_expr(var_factory, blocks, current, *left);
+            let right = linearize_expr(var_factory, blocks, current, *right);
+            linearize_assign(var_factory, blocks, current, Expr::ArrayEq { left, right })
+        }
+        prev::Expr::Array { elements } => {
+            let elements = elements
+                .into_iter()
+                .map(|e| linearize_element(var_factory, blocks, current, e))
+                .collect();
+            linearize_assign(var_factory, blocks, current, Expr::Array { elements })
+        }
+        prev::Expr::MakeArray { length, fill } => {
+            let fill = linearize_element(var_factory, blocks, current, *fill);
+            let elements = vec![fill; length as usize];
+            linearize_assign(var_factory, blocks, current, Expr::Array { elements })
+        }
+        prev::Expr::ArrayGet { array, index, field } => {
+            let array = linearize_expr(var_factory, blocks, current, *array);
+            let index = linearize_expr(var_factory, blocks, current, *index);
+            linearize_assign(var_factory, blocks, current, Expr::ArrayGet { array, index, field })
+        }
+        prev::Expr::Length { array } => {
+            let array = linearize_expr(var_factory, blocks, current, *array);
+            linearize_assign(var_factory, blocks, current, Expr::Length { array })
+        }
+        prev::Expr::Random { lo, hi } => {
+            let lo = linearize_expr(var_factory, blocks, current, *lo);
+            let hi = linearize_expr(var_factory, blocks, current, *hi);
+            linearize_assign(var_factory, blocks, current, Expr::Random { lo, hi })
+        }
+        prev::Expr::Score { target, objective } => {
+            linearize_assign(var_factory, blocks, current, Expr::Score { target, objective })
+        }
+        prev::Expr::NbtGet { source, path, scale } => {
+            linearize_assign(var_factory, blocks, current, Expr::NbtGet { source, path, scale })
+        }
+        prev::Expr::Call { id, args } => {
+            let args = args
+                .into_iter()
+                .map(|arg| linearize_expr(var_factory, blocks, current, arg))
+                .collect();
+            linearize_assign(var_factory, blocks, current, Expr::Call { id, args })
+        }
+        prev::Expr::CallIndirect { function, args } => {
+            let function = linearize_expr(var_factory, blocks, current, *function);
+            let args = args
+                .into_iter()
+                .map(|arg| linearize_expr(var_factory, blocks, current, arg))
+                .collect();
+            linearize_assign(var_factory, blocks, current, Expr::CallIndirect { function, args })
+        }
+    }
+}
+
+fn condition_vars(cond: &Condition) -> Vec<Var> {
+    let atoms = match cond {
+        Condition::Cmp { left, right, .. } => vec![left, right],
+        Condition::Atm(atom) => vec![atom],
+    };
+    atoms
+        .into_iter()
+        .filter_map(|atom| match atom {
+            Atom::Var(var) => Some(var.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn linearize_element(var_factory: &mut VarFactory, blocks: &mut Graph, current: &mut Index, element: prev::Element) -> Element {
+    match element {
+        prev::Element::Scalar(expr) => Element::Scalar(linearize_expr(var_factory, blocks, current, expr)),
+        prev::Element::Compound(fields) => Element::Compound(
+            fields
+                .into_iter()
+                .map(|(field, expr)| (field, linearize_expr(var_factory, blocks, current, expr)))
+                .collect(),
+        ),
     }
 }
 
+fn linearize_assign(var_factory: &mut VarFactory, blocks: &mut Graph, current: &mut Index, expr: Expr) -> Atom {
+    let var = var_factory.tmp();
+    let stmts = &mut blocks.node_weight_mut(*current).unwrap().stmts;
+    stmts.push(Statement::Assign {
+        var: var.clone(),
+        expr,
+    });
+
+    Atom::Var(var)
+}
+
 fn linearize_binary(
     var_factory: &mut VarFactory,
     blocks: &mut Graph,
@@ -225,8 +772,8 @@ fn linearize_cmp(
     left: prev::Expr,
     right: prev::Expr,
 ) -> Atom {
-    let left = linearize_expr(var_factory, blocks, current, left);
-    let right = linearize_expr(var_factory, blocks, current, right);
+    let left = linearize_operand(var_factory, blocks, current, cmp, left);
+    let right = linearize_operand(var_factory, blocks, current, cmp, right);
     let var = var_factory.tmp();
 
     let stmts = &mut blocks.node_weight_mut(*current).unwrap().stmts;
@@ -306,6 +853,8 @@ mod test {
     fn command() {
         let def = prev::Definition::Test {
             name: "test".to_owned(),
+            directive: None,
+            budget: None,
             stmts: vec![prev::Statement::Command {
                 text: "command text".to_owned(),
             }],
@@ -313,6 +862,7 @@ mod test {
 
         let program = linearize(prev::Program {
             defs: vec![def],
+            teardown: None,
             var_factory: VarFactory::new(),
         });
         let block = program.blocks[program.tests.first().unwrap().block].clone();
@@ -332,6 +882,8 @@ mod test {
         // (+ (* 1 (- 2 3)) (/ 4 5))
         let def = prev::Definition::Test {
             name: "test".to_owned(),
+            directive: None,
+            budget: None,
             stmts: vec![prev::Statement::Expr(
                 prev::Expr::Plus {
                     left: Box::new(prev::Expr::Times {
@@ -351,6 +903,7 @@ mod test {
 
         let program = linearize(prev::Program {
             defs: vec![def],
+            teardown: None,
             var_factory: VarFactory::new(),
         });
         let block = program.blocks[program.tests.first().unwrap().block].clone();
@@ -439,6 +992,8 @@ mod test {
         let x = var_factory.named("x".to_owned());
         let def = prev::Definition::Test {
             name: "test".to_owned(),
+            directive: None,
+            budget: None,
             stmts: vec![
                 prev::Statement::Let {
                     var: x.clone(),
@@ -455,6 +1010,7 @@ mod test {
 
         let program = linearize(prev::Program {
             defs: vec![def],
+            teardown: None,
             var_factory,
         });
         let block = program.blocks[program.tests.first().unwrap().block].clone();
@@ -496,6 +1052,8 @@ mod test {
         // (if true false true)
         let def = prev::Definition::Test {
             name: "test".to_owned(),
+            directive: None,
+            budget: None,
             stmts: vec![
                 prev::Statement::Expr(prev::Expr::If { cond: Box::new(prev::Expr::LitBool(true)), thn: Box::new(prev::Expr::LitBool(false)), els: Box::new(prev::Expr::LitBool(true)) })
             ],
@@ -503,35 +1061,169 @@ mod test {
 
         let program = linearize(prev::Program {
             defs: vec![def],
+            teardown: None,
             var_factory: VarFactory::new(),
         });
         let test = program.tests.first().unwrap().block;
         let block = program.blocks[test].clone();
         let stmts = block.stmts;
         let edges: Vec<EdgeReference<Jmp>> = program.blocks.edges_directed(test, petgraph::Direction::Outgoing).collect();
+        let target = |jmp: Jmp| edges.iter().find(|e| *e.weight() == jmp).unwrap().target();
 
         assert!(stmts.is_empty());
+        assert_eq!(edges.len(), 3);
 
-        assert_eq!(edges[1].weight().clone(), Jmp::If(Condition::Atm(Atom::LitBool(true))));
-        assert_eq!(edges[0].weight().clone(), Jmp::Unless(Condition::Atm(Atom::LitBool(true))));
+        let thn = target(Jmp::If(Condition::Atm(Atom::LitBool(true))));
+        let els = target(Jmp::Unless(Condition::Atm(Atom::LitBool(true))));
+        let after = target(Jmp::Join { live: Vec::new() });
 
-        let thn_stmts = program.blocks[edges[1].target()].stmts.clone();
+        let thn_stmts = program.blocks[thn].stmts.clone();
         let Statement::Assign { var: tmp, expr: Expr::Atom(Atom::LitBool(false)) } = thn_stmts[0].clone() else {
             panic!();
         };
-        let after_thn: EdgeReference<Jmp> = program.blocks.edges_directed(edges[0].target(), petgraph::Direction::Outgoing).next().unwrap();
+        let after_thn: EdgeReference<Jmp> = program.blocks.edges_directed(thn, petgraph::Direction::Outgoing).next().unwrap();
 
-        let els_stmts = program.blocks[edges[0].target()].stmts.clone();
+        let els_stmts = program.blocks[els].stmts.clone();
         let Statement::Assign { var: tmp_prime, expr: Expr::Atom(Atom::LitBool(true)) } = els_stmts[0].clone() else {
             panic!();
         };
-        let after_els: EdgeReference<Jmp> = program.blocks.edges_directed(edges[1].target(), petgraph::Direction::Outgoing).next().unwrap();
+        let after_els: EdgeReference<Jmp> = program.blocks.edges_directed(els, petgraph::Direction::Outgoing).next().unwrap();
 
         assert_eq!(tmp, tmp_prime);
-        assert_eq!(after_thn.target(), after_els.target());
-        assert_eq!(after_thn.weight().clone(), Jmp::Unconditional);
+        assert_eq!(after_thn.target(), after);
+        assert_eq!(after_els.target(), after);
+        assert_eq!(after_thn.weight().clone(), Jmp::Resume { live: vec![] });
 
-        let after_stmts = program.blocks[after_thn.target()].stmts.clone();
+        let after_stmts = program.blocks[after].stmts.clone();
         assert_eq!(after_stmts.len(), 0);
     }
+
+    #[test]
+    fn make_array() {
+        // (array-get (make-array 2 7) 1)
+        let def = prev::Definition::Test {
+            name: "test".to_owned(),
+            directive: None,
+            budget: None,
+            stmts: vec![prev::Statement::Expr(prev::Expr::ArrayGet {
+                array: Box::new(prev::Expr::MakeArray {
+                    length: 2,
+                    fill: Box::new(prev::Element::Scalar(prev::Expr::LitInt(7))),
+                }),
+                index: Box::new(prev::Expr::LitInt(1)),
+                field: None,
+            })],
+        };
+
+        let program = linearize(prev::Program {
+            defs: vec![def],
+            teardown: None,
+            var_factory: VarFactory::new(),
+        });
+        let block = program.blocks[program.tests.first().unwrap().block].clone();
+        let stmts = block.stmts;
+
+        let Statement::Assign { var: array, expr: Expr::Array { elements } } = stmts[0].clone() else {
+            panic!("Expected tmp1 = [7, 7]");
+        };
+        assert_eq!(
+            elements,
+            vec![
+                Element::Scalar(Atom::LitInt(7)),
+                Element::Scalar(Atom::LitInt(7))
+            ]
+        );
+
+        let Statement::Assign { expr: Expr::ArrayGet { array: got, index, .. }, .. } = stmts[1].clone() else {
+            panic!("Expected tmp2 = tmp1[1]");
+        };
+        assert_eq!(got, Atom::Var(array));
+        assert_eq!(index, Atom::LitInt(1));
+
+        assert_eq!(stmts.len(), 2);
+    }
+
+    #[test]
+    fn execute_captures() {
+        // (let (x 1)) (as "@e" (at "@s" (let (y x))))
+        let mut var_factory = VarFactory::new();
+        let x = var_factory.named("x".to_owned());
+        let y = var_factory.named("y".to_owned());
+        let def = prev::Definition::Test {
+            name: "test".to_owned(),
+            directive: None,
+            budget: None,
+            stmts: vec![
+                prev::Statement::Let { var: x.clone(), expr: prev::Expr::LitInt(1) },
+                prev::Statement::Execute {
+                    context: Context::As("@e".to_owned()),
+                    stmts: vec![prev::Statement::Execute {
+                        context: Context::At("@s".to_owned()),
+                        stmts: vec![prev::Statement::Let { var: y.clone(), expr: prev::Expr::Variable(x.clone()) }],
+                    }],
+                },
+            ],
+        };
+
+        let program = linearize(prev::Program {
+            defs: vec![def],
+            teardown: None,
+            var_factory,
+        });
+        let test = program.tests.first().unwrap().block;
+        let edges: Vec<EdgeReference<Jmp>> = program.blocks.edges_directed(test, petgraph::Direction::Outgoing).collect();
+        let target = |jmp: Jmp| edges.iter().find(|e| *e.weight() == jmp).unwrap().target();
+
+        assert_eq!(edges.len(), 2);
+        let body = target(Jmp::Context(vec![Context::As("@e".to_owned()), Context::At("@s".to_owned())]));
+        let after = target(Jmp::Join { live: Vec::new() });
+
+        assert_eq!(
+            program.blocks[body].stmts,
+            vec![Statement::Assign { var: y, expr: Expr::Atom(Atom::Var(x.clone())) }]
+        );
+        let resume = program.blocks.edges_directed(body, petgraph::Direction::Outgoing).next().unwrap();
+        assert_eq!(resume.target(), after);
+        assert_eq!(*resume.weight(), Jmp::Resume { live: vec![x] });
+    }
+
+    #[test]
+    fn schedule_captures() {
+        // (let (x 1)) (after 20 (let (y x)))
+        let mut var_factory = VarFactory::new();
+        let x = var_factory.named("x".to_owned());
+        let y = var_factory.named("y".to_owned());
+        let def = prev::Definition::Test {
+            name: "test".to_owned(),
+            directive: None,
+            budget: None,
+            stmts: vec![
+                prev::Statement::Let { var: x.clone(), expr: prev::Expr::LitInt(1) },
+                prev::Statement::After {
+                    ticks: 20,
+                    stmts: vec![prev::Statement::Let { var: y.clone(), expr: prev::Expr::Variable(x.clone()) }],
+                },
+            ],
+        };
+
+        let program = linearize(prev::Program {
+            defs: vec![def],
+            teardown: None,
+            var_factory,
+        });
+        let test = program.tests.first().unwrap().block;
+        let Some(Statement::Schedule { block, ticks: 20, vars }) = program.blocks[test].stmts.get(1) else {
+            panic!("Expected the body to be scheduled");
+        };
+
+        assert_eq!(vars, &vec![x.clone()]);
+        assert_eq!(program.blocks.edges_directed(test, petgraph::Direction::Outgoing).count(), 0);
+        assert_eq!(
+            program.blocks[*block].stmts,
+            vec![
+                Statement::Restore { block: *block, vars: vec![x.clone()] },
+                Statement::Assign { var: y, expr: Expr::Atom(Atom::Var(x)) },
+            ]
+        );
+    }
 }
@@ -1,6 +1,6 @@
 use anyhow::Result;
 use clap::Parser;
-use mcml::compile;
+use mcml::{compile_with_options, report_tail_calls, Filter, Options};
 use std::fs;
 use std::path::PathBuf;
 
@@ -8,12 +8,50 @@ use std::path::PathBuf;
 struct Args {
     input: PathBuf,
     output: PathBuf,
+    /// List the calls that could not be compiled as tail calls
+    #[arg(long)]
+    report_tail_calls: bool,
+    /// The data pack format to target
+    #[arg(long)]
+    pack_format: Option<usize>,
+    /// Only compile the tests with this text in their name, or in the name of a describe around
+    /// them. It is matched as it is; see --filter-regex for patterns
+    #[arg(long, conflicts_with = "filter_regex")]
+    filter: Option<String>,
+    /// Only compile the tests whose name, or the name of a describe around them, has a match of
+    /// this regular expression
+    #[arg(long)]
+    filter_regex: Option<String>,
 }
 
 fn main() -> Result<()> {
-    let Args { input, output } = Args::parse();
+    let Args {
+        input,
+        output,
+        report_tail_calls: report,
+        pack_format,
+        filter,
+        filter_regex,
+    } = Args::parse();
+    let filter = match (filter, filter_regex) {
+        (Some(filter), _) => Some(Filter::Substring(filter)),
+        (None, Some(pattern)) => Some(Filter::regex(&pattern)?),
+        (None, None) => None,
+    };
+    let mut options = Options {
+        filter,
+        ..Options::default()
+    };
+    if let Some(pack_format) = pack_format {
+        options.pack_format = pack_format;
+    }
     let source = fs::read_to_string(input)?;
-    let datapack = compile(&source)?;
+    if report {
+        for line in report_tail_calls(&source)? {
+            println!("{line}");
+        }
+    }
+    let datapack = compile_with_options(&source, &options)?;
     fs::write(output, datapack.bytes()?)?;
 
     Ok(())
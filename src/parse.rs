@@ -1,18 +1,170 @@
-use crate::lex::Token;
+use crate::read::Datum;
 use anyhow::{anyhow, Result};
 use std::collections::VecDeque;
+use std::fmt::Display;
 
 #[derive(PartialEq, Eq, Debug)]
 pub enum Definition {
-    Test { name: String, stmts: Vec<Statement> },
+    Test {
+        name: String,
+        directive: Option<Directive>,
+        /// Passes only if one of its assertions fails.
+        fails: bool,
+        /// How many blocks the test may enter before it is stopped and fails.
+        budget: Option<i64>,
+        stmts: Vec<Statement>,
+    },
+    Struct { name: String, fields: Vec<Field> },
+    Function {
+        name: String,
+        params: Vec<Param>,
+        ret: Type,
+        stmts: Vec<Statement>,
+        result: Expr,
+    },
+    Handler { event: Event, stmts: Vec<Statement> },
+    /// Runs before the body of every test in the file.
+    Setup { stmts: Vec<Statement> },
+    /// Runs after every test in the file, whether it passed or not.
+    Teardown { stmts: Vec<Statement> },
+    /// Tests reported together as a subtest. Holds only tests and other describes.
+    Describe { name: String, defs: Vec<Definition> },
+}
+
+/// Marks a test whose result should not fail the run.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Directive {
+    /// Checked, but never run.
+    Skip { reason: String },
+    /// Run, but not expected to pass yet.
+    Todo,
+}
+
+impl Display for Directive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Directive::Skip { reason } => write!(f, "SKIP {reason}"),
+            Directive::Todo => write!(f, "TODO"),
+        }
+    }
+}
+
+/// When the game runs a handler, through the function tag of the same name.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Event {
+    Load,
+    Tick,
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Event::Load => write!(f, "load"),
+            Event::Tick => write!(f, "tick"),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Field {
+    pub name: String,
+    pub ty: Type,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Param {
+    pub name: String,
+    pub ty: Type,
+}
+
+/// A type as written in the source. Names are resolved during type checking.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Type {
+    Named(String),
+    Array(Box<Type>),
+    Function { params: Vec<Type>, ret: Box<Type> },
+}
+
+/// Where an assertion is written, so that a failure can point back at it.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Origin {
+    pub line: usize,
+    pub source: String,
+    /// The string written after the operands, to say what went wrong.
+    pub message: Option<String>,
+}
+
+/// How the two sides of an `assert-ne`, `assert<` or `assert<=` should compare.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Relation {
+    NotEqual,
+    Less,
+    LessOrEqual,
 }
 
 #[derive(PartialEq, Eq, Debug)]
 pub enum Statement {
-    Assert { expr: Expr },
-    AssertEq { left: Expr, right: Expr },
+    Assert { expr: Expr, origin: Origin },
+    AssertEq { left: Expr, right: Expr, origin: Origin },
+    AssertCmp { relation: Relation, left: Expr, right: Expr, origin: Origin },
+    /// Checks that `lo <= expr <= hi`.
+    AssertBetween { expr: Expr, lo: Expr, hi: Expr, origin: Origin },
     Command { text: String },
     Let { variable_name: String, expr: Expr },
+    ArraySet { array: Expr, index: Expr, value: Expr },
+    ArrayPush { array: Expr, value: Expr },
+    Execute { context: Context, stmts: Vec<Statement> },
+    SetScore { target: String, objective: String, value: Expr },
+    NbtSet { source: NbtSource, path: String, ty: String, scale: String, value: Expr },
+    After { ticks: i64, stmts: Vec<Statement> },
+    SetSeed { seed: i64 },
+    When { cond: Expr, stmts: Vec<Statement> },
+    Return { value: Option<Expr> },
+    Asm { template: Vec<Fragment>, vars: Vec<String> },
+}
+
+/// A piece of the text of an `asm` line.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Fragment {
+    Text(String),
+    /// Stands for the location of the variable at this index.
+    Var(usize),
+}
+
+/// Something holding NBT data, as named by `data get` and `execute store`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum NbtSource {
+    Entity(String),
+    Block(String),
+    Storage(String),
+}
+
+impl Display for NbtSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NbtSource::Entity(target) => write!(f, "entity {target}"),
+            NbtSource::Block(pos) => write!(f, "block {pos}"),
+            NbtSource::Storage(id) => write!(f, "storage {id}"),
+        }
+    }
+}
+
+/// A modifier of `execute` that changes who or where the commands in a block run as.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Context {
+    As(String),
+    At(String),
+    Positioned(String),
+}
+
+impl Display for Context {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Context::As(targets) => write!(f, "as {targets}"),
+            Context::At(targets) => write!(f, "at {targets}"),
+            Context::Positioned(pos) => write!(f, "positioned {pos}"),
+        }
+    }
 }
 
 #[derive(PartialEq, Eq, Debug)]
@@ -44,174 +196,705 @@ pub enum Expr {
     Eq {
         left: Box<Expr>,
         right: Box<Expr>,
-    }
+    },
+    Array {
+        elements: Vec<Expr>,
+    },
+    MakeArray {
+        length: i64,
+        fill: Box<Expr>,
+    },
+    ArrayGet {
+        array: Box<Expr>,
+        index: Box<Expr>,
+    },
+    Length {
+        array: Box<Expr>,
+    },
+    Apply {
+        name: String,
+        args: Vec<Expr>,
+    },
+    Score {
+        target: String,
+        objective: String,
+    },
+    NbtGet {
+        source: NbtSource,
+        path: String,
+        scale: String,
+    },
+    Random {
+        lo: Box<Expr>,
+        hi: Box<Expr>,
+    },
 }
 
-struct Tokens {
-    inner: VecDeque<Token>,
+/// The items of a list, taken apart from the front.
+struct Items {
+    inner: VecDeque<Datum>,
 }
 
-impl Tokens {
-    fn new(inner: Vec<Token>) -> Self {
-        Tokens {
-            inner: VecDeque::from(inner),
+impl Items {
+    fn new(items: Vec<Datum>) -> Self {
+        Items {
+            inner: VecDeque::from(items),
         }
     }
 
-    fn require(&mut self, token: Token) -> Result<()> {
+    fn next(&mut self) -> Result<Datum> {
         match self.inner.pop_front() {
-            Some(t) if t == token => Ok(()),
-            Some(t) => Err(anyhow!("Expected {} but saw {}", token, t)),
-            None => Err(anyhow!("Expected {} but ran out of tokens", token)),
+            Some(datum) => Ok(datum),
+            None => Err(anyhow!("Ran out of items in a list")),
         }
     }
 
-    fn next(&mut self) -> Result<Token> {
-        match self.inner.pop_front() {
-            Some(t) => Ok(t),
-            None => Err(anyhow!("Ran out of tokens")),
+    /// The list these items are left from, written back as source. `head` is the name that was
+    /// already taken from its front.
+    fn origin(&self, head: &str, line: usize) -> Origin {
+        let mut source = format!("({head}");
+        for datum in &self.inner {
+            source.push_str(&format!(" {datum}"));
+        }
+        source.push(')');
+        Origin {
+            line,
+            source,
+            message: None,
+        }
+    }
+
+    /// Reads the message an assertion may end with.
+    fn message(&mut self) -> Option<String> {
+        match self.inner.front() {
+            Some(Datum::String(_)) => self.string().ok(),
+            _ => None,
+        }
+    }
+
+    /// Reads the `:budget` a test may be given after its name.
+    fn budget(&mut self) -> Result<Option<i64>> {
+        match self.inner.front() {
+            Some(Datum::Symbol(s)) if s == ":budget" => {
+                self.next()?;
+                match self.next()? {
+                    Datum::Int(budget) if budget > 0 => Ok(Some(budget)),
+                    d => Err(anyhow!("Expected a positive budget but saw {}", d)),
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Fails if anything is left over.
+    fn end(&self) -> Result<()> {
+        match self.inner.front() {
+            Some(datum) => Err(anyhow!("Unexpected {}", datum)),
+            None => Ok(()),
+        }
+    }
+
+    fn symbol(&mut self) -> Result<String> {
+        match self.next()? {
+            Datum::Symbol(s) => Ok(s),
+            d => Err(anyhow!("Expected a name but saw {}", d)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        match self.next()? {
+            Datum::String(s) => Ok(s),
+            d => Err(anyhow!("Expected a string but saw {}", d)),
+        }
+    }
+
+    fn list(&mut self) -> Result<Items> {
+        match self.next()? {
+            Datum::List(items, _) => Ok(Items::new(items)),
+            d => Err(anyhow!("Expected a list but saw {}", d)),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        parse_expr(self.next()?)
+    }
+
+    /// Reads everything that is left as statements.
+    fn stmts(&mut self) -> Result<Vec<Statement>> {
+        std::mem::take(&mut self.inner).into_iter().map(parse_stmt).collect()
+    }
+
+    /// Reads the source and path that `nbt-get` and `nbt-set!` start with.
+    fn nbt(&mut self) -> Result<(NbtSource, String)> {
+        let source = match self.next()? {
+            Datum::Symbol(kind) if kind == "entity" => NbtSource::Entity(self.string()?),
+            Datum::Symbol(kind) if kind == "block" => NbtSource::Block(self.string()?),
+            Datum::Symbol(kind) if kind == "storage" => NbtSource::Storage(self.string()?),
+            d => return Err(anyhow!("Expected entity, block or storage but saw {}", d)),
+        };
+        Ok((source, self.string()?))
+    }
+
+    fn scale(&mut self) -> Result<String> {
+        match self.next()? {
+            Datum::Int(i) => Ok(i.to_string()),
+            Datum::Decimal(d) => Ok(d),
+            d => Err(anyhow!("Expected a scale factor but saw {}", d)),
         }
     }
 }
 
-pub fn parse(tokens: Vec<Token>) -> Result<Vec<Definition>> {
-    let mut tokens = Tokens::new(tokens);
+/// The names that start a statement rather than an expression.
+const STATEMENTS: [&str; 19] = [
+    "assert",
+    "asserteq",
+    "assert-ne",
+    "assert<",
+    "assert<=",
+    "assert-between",
+    "let",
+    "array-set!",
+    "array-push!",
+    "set-score!",
+    "nbt-set!",
+    "after",
+    "set-seed!",
+    "when",
+    "return",
+    "asm",
+    "as",
+    "at",
+    "positioned",
+];
+
+/// Whether `datum` is a statement rather than an expression. A `/` followed by a lone string is
+/// a command, and one followed by anything else a division.
+fn is_statement(datum: &Datum) -> bool {
+    match datum {
+        Datum::List(items, _) => match items.as_slice() {
+            [Datum::Symbol(slash), Datum::String(_)] if slash == "/" => true,
+            [Datum::Symbol(name), ..] => STATEMENTS.contains(&name.as_str()),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+pub fn parse(data: Vec<Datum>) -> Result<Vec<Definition>> {
+    parse_definitions(data)
+}
+
+/// Parses definitions in order, expanding each `test-each` into a test per row.
+fn parse_definitions(data: impl IntoIterator<Item = Datum>) -> Result<Vec<Definition>> {
     let mut defs = Vec::new();
-    while let Ok(token) = tokens.next() {
-        if token == Token::LeftParen {
-            defs.push(parse_definition(&mut tokens)?);
-        } else {
-            return Err(anyhow!("Unexpected token: {}", token));
+    for datum in data {
+        match datum {
+            Datum::List(items, _) if matches!(items.first(), Some(Datum::Symbol(head)) if head == "test-each") => {
+                defs.extend(parse_test_each(Items::new(items))?)
+            }
+            datum => defs.push(parse_definition(datum)?),
         }
     }
     Ok(defs)
 }
 
-fn parse_definition(mut tokens: &mut Tokens) -> Result<Definition> {
-    match tokens.next()? {
-        Token::Test => parse_test(&mut tokens),
-        _ => Err(anyhow!("Unexpected thingy")),
+fn parse_definition(datum: Datum) -> Result<Definition> {
+    let Datum::List(items, _) = datum else {
+        return Err(anyhow!("Expected a definition but saw {}", datum));
+    };
+    let mut items = Items::new(items);
+    match items.symbol()?.as_str() {
+        head @ ("test" | "test-skip" | "test-todo" | "test-fails") => parse_test(head, items),
+        "describe" => parse_describe(items),
+        "defstruct" => parse_struct(items),
+        "defn" => parse_function(items),
+        "on-load" => parse_handler(Event::Load, items),
+        "on-tick" => parse_handler(Event::Tick, items),
+        "setup" => Ok(Definition::Setup { stmts: items.stmts()? }),
+        "teardown" => Ok(Definition::Teardown { stmts: items.stmts()? }),
+        name => Err(anyhow!("Expected a definition but saw ({name} ...)")),
     }
 }
 
-fn parse_test(tokens: &mut Tokens) -> Result<Definition> {
-    if let Token::String(name) = tokens.next()? {
-        let mut stmts = Vec::new();
-        loop {
-            match tokens.next()? {
-                Token::LeftParen => stmts.push(parse_stmt(tokens)?),
-                Token::RightParen => return Ok(Definition::Test { name, stmts }),
-                x => return Err(anyhow!("Expected statement saw {}", x)),
+fn parse_struct(mut items: Items) -> Result<Definition> {
+    let name = items.symbol()?;
+    let mut fields = Vec::new();
+    for field in items.inner {
+        match field {
+            Datum::Symbol(name) => fields.push(Field {
+                name,
+                ty: Type::Named("int".to_owned()),
+            }),
+            Datum::List(field, _) => {
+                let mut field = Items::new(field);
+                let name = field.symbol()?;
+                let ty = parse_type(field.next()?)?;
+                field.end()?;
+                fields.push(Field { name, ty });
             }
+            d => return Err(anyhow!("Expected field saw {}", d)),
         }
-    } else {
-        Err(anyhow!("Expected test to have name"))
     }
+    Ok(Definition::Struct { name, fields })
 }
 
-fn parse_stmt(tokens: &mut Tokens) -> Result<Statement> {
-    let stmt = match tokens.next()? {
-        Token::Assert => Ok(Statement::Assert {
-            expr: parse_expr(tokens)?,
-        }),
-        Token::AssertEq => Ok(Statement::AssertEq {
-            left: parse_expr(tokens)?,
-            right: parse_expr(tokens)?,
+fn parse_function(mut items: Items) -> Result<Definition> {
+    let name = items.symbol()?;
+
+    let mut params = Vec::new();
+    for param in items.list()?.inner {
+        let Datum::List(param, _) = param else {
+            return Err(anyhow!("Expected parameter saw {}", param));
+        };
+        let mut param = Items::new(param);
+        let name = param.symbol()?;
+        let ty = parse_type(param.next()?)?;
+        param.end()?;
+        params.push(Param { name, ty });
+    }
+    let ret = parse_type(items.next()?)?;
+
+    // The body is any number of statements followed by the expression the function returns.
+    let result = match items.inner.pop_back() {
+        Some(result) if !is_statement(&result) => parse_expr(result)?,
+        _ => return Err(anyhow!("The body of {name} has to end with an expression")),
+    };
+    let stmts = items.stmts()?;
+
+    Ok(Definition::Function {
+        name,
+        params,
+        ret,
+        stmts,
+        result,
+    })
+}
+
+fn parse_type(datum: Datum) -> Result<Type> {
+    match datum {
+        Datum::Symbol(name) => Ok(Type::Named(name)),
+        Datum::List(items, _) => {
+            let mut items = Items::new(items);
+            let ty = match items.next()? {
+                Datum::Symbol(kind) if kind == "array" => Type::Array(Box::new(parse_type(items.next()?)?)),
+                Datum::Symbol(kind) if kind == "fn" => {
+                    let params = items.list()?.inner.into_iter().map(parse_type).collect::<Result<_>>()?;
+                    Type::Function {
+                        params,
+                        ret: Box::new(parse_type(items.next()?)?),
+                    }
+                }
+                d => return Err(anyhow!("Expected array or fn type but saw {}", d)),
+            };
+            items.end()?;
+            Ok(ty)
+        }
+        d => Err(anyhow!("Expected a type but saw {}", d)),
+    }
+}
+
+fn parse_test(head: &str, mut items: Items) -> Result<Definition> {
+    let Datum::String(name) = items.next()? else {
+        return Err(anyhow!("Expected test to have name"));
+    };
+    let directive = match head {
+        "test-skip" => Some(Directive::Skip {
+            reason: items.string()?,
         }),
-        Token::Slash => {
-            if let Token::String(text) = tokens.next()? {
-                Ok(Statement::Command { text })
-            } else {
-                Err(anyhow!("Expected a string to follow /"))
-            }
+        "test-todo" => Some(Directive::Todo),
+        _ => None,
+    };
+    Ok(Definition::Test {
+        name,
+        directive,
+        fails: head == "test-fails",
+        budget: items.budget()?,
+        stmts: items.stmts()?,
+    })
+}
+
+/// Expands `(test-each "name ~a" (row ...) (param ...) stmts ...)` into a test per row, which
+/// binds each parameter to its value in the row with a `let` before the body. Each `~a` in the
+/// name is replaced by the next value of the row.
+fn parse_test_each(mut items: Items) -> Result<Vec<Definition>> {
+    items.symbol()?;
+    let name = items.string()?;
+    let rows = items.list()?.inner;
+    let params = std::mem::take(&mut items.list()?.inner)
+        .into_iter()
+        .map(|param| match param {
+            Datum::Symbol(param) => Ok(param),
+            d => Err(anyhow!("Expected a parameter name but saw {}", d)),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if rows.is_empty() {
+        return Err(anyhow!("test-each \"{name}\" has no rows"));
+    }
+    if name.matches("~a").count() > params.len() {
+        return Err(anyhow!("test-each \"{name}\" has more ~a than parameters"));
+    }
+
+    let mut tests = Vec::new();
+    for row in rows {
+        let Datum::List(values, _) = row else {
+            return Err(anyhow!("Expected a row of values but saw {}", row));
+        };
+        if values.len() != params.len() {
+            return Err(anyhow!(
+                "test-each \"{name}\" has {} parameters but a row of {} values",
+                params.len(),
+                values.len()
+            ));
+        }
+        let mut row_name = String::new();
+        let mut rest = name.as_str();
+        for value in &values {
+            let Some(start) = rest.find("~a") else { break };
+            row_name.push_str(&format!("{}{value}", &rest[..start]));
+            rest = &rest[start + 2..];
         }
-        Token::Let => {
-            tokens.require(Token::LeftParen)?;
-            if let Token::Ident(variable_name) = tokens.next()? {
-                let expr = parse_expr(tokens)?;
-                tokens.require(Token::RightParen)?;
+        row_name.push_str(rest);
+
+        let mut stmts = params
+            .iter()
+            .zip(values)
+            .map(|(param, value)| {
                 Ok(Statement::Let {
-                    variable_name,
-                    expr,
+                    variable_name: param.clone(),
+                    expr: parse_expr(value)?,
                 })
-            } else {
-                Err(anyhow!("Expected variable identifier"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        stmts.extend(Items::new(items.inner.iter().cloned().collect()).stmts()?);
+        tests.push(Definition::Test {
+            name: row_name,
+            directive: None,
+            fails: false,
+            budget: None,
+            stmts,
+        });
+    }
+    Ok(tests)
+}
+
+fn parse_describe(mut items: Items) -> Result<Definition> {
+    let name = items.string()?;
+    let defs = parse_definitions(std::mem::take(&mut items.inner))?;
+    if defs.is_empty() {
+        return Err(anyhow!("describe \"{name}\" has no tests"));
+    }
+    if !defs
+        .iter()
+        .all(|def| matches!(def, Definition::Test { .. } | Definition::Describe { .. }))
+    {
+        return Err(anyhow!("describe \"{name}\" can only hold tests and other describes"));
+    }
+    Ok(Definition::Describe { name, defs })
+}
+
+fn parse_handler(event: Event, mut items: Items) -> Result<Definition> {
+    Ok(Definition::Handler {
+        event,
+        stmts: items.stmts()?,
+    })
+}
+
+/// Splits the text of an `asm` line around the `{name}` placeholders for its variables. Braces
+/// around anything else are left alone, as commands use them for NBT and JSON.
+fn parse_template(text: &str, vars: &[String]) -> Vec<Fragment> {
+    let mut template = Vec::new();
+    let mut literal = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        literal.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let placeholder = rest
+            .find('}')
+            .and_then(|end| Some((vars.iter().position(|var| *var == rest[1..end])?, end)));
+        match placeholder {
+            Some((index, end)) => {
+                if !literal.is_empty() {
+                    template.push(Fragment::Text(std::mem::take(&mut literal)));
+                }
+                template.push(Fragment::Var(index));
+                rest = &rest[end + 1..];
+            }
+            None => {
+                literal.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        template.push(Fragment::Text(literal));
+    }
+    template
+}
+
+fn parse_stmt(datum: Datum) -> Result<Statement> {
+    let Datum::List(items, line) = datum else {
+        return Err(anyhow!("Expected a statement but saw {}", datum));
+    };
+    let mut items = Items::new(items);
+    let name = items.symbol()?;
+    let stmt = match name.as_str() {
+        "assert" => {
+            let mut origin = items.origin(&name, line);
+            let expr = items.expr()?;
+            origin.message = items.message();
+            Statement::Assert { expr, origin }
+        }
+        "asserteq" => {
+            let mut origin = items.origin(&name, line);
+            let (left, right) = (items.expr()?, items.expr()?);
+            origin.message = items.message();
+            Statement::AssertEq { left, right, origin }
+        }
+        "assert-ne" | "assert<" | "assert<=" => {
+            let relation = match name.as_str() {
+                "assert-ne" => Relation::NotEqual,
+                "assert<" => Relation::Less,
+                _ => Relation::LessOrEqual,
+            };
+            let mut origin = items.origin(&name, line);
+            let (left, right) = (items.expr()?, items.expr()?);
+            origin.message = items.message();
+            Statement::AssertCmp {
+                relation,
+                left,
+                right,
+                origin,
+            }
+        }
+        "assert-between" => {
+            let mut origin = items.origin(&name, line);
+            let (expr, lo, hi) = (items.expr()?, items.expr()?, items.expr()?);
+            origin.message = items.message();
+            Statement::AssertBetween { expr, lo, hi, origin }
+        }
+        "/" => match items.next()? {
+            Datum::String(text) => Statement::Command { text },
+            _ => return Err(anyhow!("Expected a string to follow /")),
+        },
+        "let" => {
+            let mut binding = items.list()?;
+            let variable_name = binding.symbol()?;
+            let expr = binding.expr()?;
+            binding.end()?;
+            Statement::Let { variable_name, expr }
+        }
+        "array-set!" => Statement::ArraySet {
+            array: items.expr()?,
+            index: items.expr()?,
+            value: items.expr()?,
+        },
+        "array-push!" => Statement::ArrayPush {
+            array: items.expr()?,
+            value: items.expr()?,
+        },
+        "set-score!" => Statement::SetScore {
+            target: items.string()?,
+            objective: items.string()?,
+            value: items.expr()?,
+        },
+        "nbt-set!" => {
+            let (source, path) = items.nbt()?;
+            let ty = match items.next()? {
+                Datum::Symbol(ty)
+                    if ["byte", "short", "int", "long", "float", "double"].contains(&ty.as_str()) =>
+                {
+                    ty
+                }
+                d => return Err(anyhow!("Expected an NBT number type but saw {}", d)),
+            };
+            Statement::NbtSet {
+                source,
+                path,
+                ty,
+                scale: items.scale()?,
+                value: items.expr()?,
+            }
+        }
+        "after" => {
+            let ticks = match items.next()? {
+                Datum::Int(ticks) if ticks > 0 => ticks,
+                d => return Err(anyhow!("Expected a positive number of ticks but saw {}", d)),
+            };
+            Statement::After {
+                ticks,
+                stmts: items.stmts()?,
+            }
+        }
+        "when" => Statement::When {
+            cond: items.expr()?,
+            stmts: items.stmts()?,
+        },
+        "return" => Statement::Return {
+            value: items.inner.pop_front().map(parse_expr).transpose()?,
+        },
+        "asm" => {
+            let text = items.string()?;
+            let mut vars = Vec::new();
+            while !items.inner.is_empty() {
+                let name = items.symbol()?;
+                if vars.contains(&name) {
+                    return Err(anyhow!("{name} is passed to asm more than once"));
+                }
+                vars.push(name);
+            }
+            Statement::Asm {
+                template: parse_template(&text, &vars),
+                vars,
+            }
+        }
+        "set-seed!" => match items.next()? {
+            Datum::Int(seed) => Statement::SetSeed { seed },
+            d => return Err(anyhow!("Expected a literal seed but saw {}", d)),
+        },
+        "as" | "at" | "positioned" => {
+            let Datum::String(arg) = items.next()? else {
+                return Err(anyhow!("Expected a string to follow {name}"));
+            };
+            let context = match name.as_str() {
+                "as" => Context::As(arg),
+                "at" => Context::At(arg),
+                _ => Context::Positioned(arg),
+            };
+            Statement::Execute {
+                context,
+                stmts: items.stmts()?,
             }
         }
-        _ => Err(anyhow!("Expected a statement")),
-    }?;
-    tokens.require(Token::RightParen)?;
+        _ => return Err(anyhow!("Expected a statement but saw ({name} ...)")),
+    };
+    items.end()?;
     Ok(stmt)
 }
 
-fn parse_expr(tokens: &mut Tokens) -> Result<Expr> {
-    match tokens.next()? {
-        Token::Boolean(b) => Ok(Expr::LitBool(b)),
-        Token::Int(i) => Ok(Expr::LitInt(i)),
-        Token::Ident(x) => Ok(Expr::Variable(x)),
-        Token::LeftParen => parse_arithmetic(tokens),
-        _ => Err(anyhow!("Expected an expression")),
+fn parse_expr(datum: Datum) -> Result<Expr> {
+    match datum {
+        Datum::Symbol(b) if b == "true" => Ok(Expr::LitBool(true)),
+        Datum::Symbol(b) if b == "false" => Ok(Expr::LitBool(false)),
+        Datum::Symbol(x) => Ok(Expr::Variable(x)),
+        Datum::Int(i) => Ok(Expr::LitInt(i)),
+        Datum::List(items, _) => parse_compound(items),
+        d => Err(anyhow!("Expected an expression but saw {}", d)),
     }
 }
 
-fn parse_arithmetic(tokens: &mut Tokens) -> Result<Expr> {
-    let expr = match tokens.next()? {
-        Token::Plus => Expr::Plus {
-            left: Box::new(parse_expr(tokens)?),
-            right: Box::new(parse_expr(tokens)?),
+fn parse_compound(items: Vec<Datum>) -> Result<Expr> {
+    let mut items = Items::new(items);
+    let name = match items.next()? {
+        Datum::Symbol(name) => name,
+        d => return Err(anyhow!("Expected an operator or function name but saw {}", d)),
+    };
+    let expr = match name.as_str() {
+        "+" => Expr::Plus {
+            left: Box::new(items.expr()?),
+            right: Box::new(items.expr()?),
+        },
+        "-" => Expr::Minus {
+            left: Box::new(items.expr()?),
+            right: Box::new(items.expr()?),
+        },
+        "*" => Expr::Times {
+            left: Box::new(items.expr()?),
+            right: Box::new(items.expr()?),
         },
-        Token::Dash => Expr::Minus {
-            left: Box::new(parse_expr(tokens)?),
-            right: Box::new(parse_expr(tokens)?),
+        "/" => Expr::Divide {
+            left: Box::new(items.expr()?),
+            right: Box::new(items.expr()?),
         },
-        Token::Star => Expr::Times {
-            left: Box::new(parse_expr(tokens)?),
-            right: Box::new(parse_expr(tokens)?),
+        "if" => Expr::If {
+            cond: Box::new(items.expr()?),
+            thn: Box::new(items.expr()?),
+            els: Box::new(items.expr()?),
         },
-        Token::Slash => Expr::Divide {
-            left: Box::new(parse_expr(tokens)?),
-            right: Box::new(parse_expr(tokens)?),
+        "==" => Expr::Eq {
+            left: Box::new(items.expr()?),
+            right: Box::new(items.expr()?),
         },
-        Token::If => Expr::If {
-            cond: Box::new(parse_expr(tokens)?),
-            thn: Box::new(parse_expr(tokens)?),
-            els: Box::new(parse_expr(tokens)?),
+        "array" => Expr::Array {
+            elements: std::mem::take(&mut items.inner).into_iter().map(parse_expr).collect::<Result<_>>()?,
         },
-        Token::DoubleEquals => Expr::Eq {
-            left: Box::new(parse_expr(tokens)?),
-            right: Box::new(parse_expr(tokens)?),
+        "make-array" => match items.next()? {
+            Datum::Int(length) if length >= 0 => Expr::MakeArray {
+                length,
+                fill: Box::new(items.expr()?),
+            },
+            d => return Err(anyhow!("Expected a non-negative array length but saw {}", d)),
+        },
+        "array-get" => Expr::ArrayGet {
+            array: Box::new(items.expr()?),
+            index: Box::new(items.expr()?),
+        },
+        "length" => Expr::Length {
+            array: Box::new(items.expr()?),
+        },
+        "score" => Expr::Score {
+            target: items.string()?,
+            objective: items.string()?,
+        },
+        "random" => Expr::Random {
+            lo: Box::new(items.expr()?),
+            hi: Box::new(items.expr()?),
+        },
+        "nbt-get" => {
+            let (source, path) = items.nbt()?;
+            Expr::NbtGet {
+                source,
+                path,
+                scale: items.scale()?,
+            }
+        }
+        _ => Expr::Apply {
+            name,
+            args: std::mem::take(&mut items.inner).into_iter().map(parse_expr).collect::<Result<_>>()?,
         },
-        _ => return Err(anyhow!("Expected arithmetic expression")),
     };
-    tokens.require(Token::RightParen)?;
+    items.end()?;
     Ok(expr)
 }
 
 #[cfg(test)]
 mod test {
-    use super::Token::*;
     use super::*;
+    use crate::lex::{lex, Token::*};
+    use crate::read::read;
+
+    fn origin(source: &str) -> Origin {
+        Origin {
+            line: 1,
+            source: source.to_owned(),
+            message: None,
+        }
+    }
 
     #[test]
     fn assert_bool() -> Result<()> {
         let tokens = vec![
             LeftParen,
-            Test,
+            Symbol("test".to_owned()),
             String(r#"test"#.to_owned()),
             LeftParen,
-            Assert,
-            Boolean(true),
+            Symbol("assert".to_owned()),
+            Symbol("true".to_owned()),
             RightParen,
             RightParen,
         ];
         assert_eq!(
             vec![Definition::Test {
                 name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
                 stmts: vec![Statement::Assert {
+                    origin: origin("(assert true)"),
                     expr: Expr::LitBool(true)
                 }]
             }],
-            parse(tokens)?
+            parse(read(tokens)?)?
         );
         Ok(())
     }
@@ -220,10 +903,10 @@ mod test {
     fn command_literal() -> Result<()> {
         let tokens = vec![
             LeftParen,
-            Test,
+            Symbol("test".to_owned()),
             String("test 2".to_owned()),
             LeftParen,
-            Slash,
+            Symbol("/".to_owned()),
             String("cmd text".to_owned()),
             RightParen,
             RightParen,
@@ -231,11 +914,14 @@ mod test {
         assert_eq!(
             vec![Definition::Test {
                 name: "test 2".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
                 stmts: vec![Statement::Command {
                     text: "cmd text".to_owned()
                 }]
             }],
-            parse(tokens)?
+            parse(read(tokens)?)?
         );
         Ok(())
     }
@@ -244,10 +930,10 @@ mod test {
     fn asserteq_ints() -> Result<()> {
         let tokens = vec![
             LeftParen,
-            Test,
+            Symbol("test".to_owned()),
             String("test 3".to_owned()),
             LeftParen,
-            AssertEq,
+            Symbol("asserteq".to_owned()),
             Int(5),
             Int(-5),
             RightParen,
@@ -256,13 +942,222 @@ mod test {
         assert_eq!(
             vec![Definition::Test {
                 name: "test 3".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
                 stmts: vec![Statement::AssertEq {
+                    origin: origin("(asserteq 5 -5)"),
                     left: Expr::LitInt(5),
                     right: Expr::LitInt(-5)
                 }]
             }],
-            parse(tokens)?
+            parse(read(tokens)?)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn assertion_origin() -> Result<()> {
+        let defs = parse(read(lex("(test \"t\"\n  (let (x 1))\n\n  (asserteq x   (+ 1 0)))")?)?)?;
+        let Definition::Test { stmts, .. } = &defs[0] else {
+            panic!("Expected test definition");
+        };
+        let Statement::AssertEq { origin, .. } = &stmts[1] else {
+            panic!("Expected asserteq");
+        };
+        assert_eq!(
+            *origin,
+            Origin {
+                line: 4,
+                source: "(asserteq x (+ 1 0))".to_owned(),
+                message: None,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn describe() -> Result<()> {
+        let defs = parse(read(lex(
+            r#"(describe "outer" (test "a") (describe "inner" (test "b")))"#,
+        )?)?)?;
+        let test = |name: &str| Definition::Test {
+            name: name.to_owned(),
+            directive: None,
+            fails: false,
+            budget: None,
+            stmts: vec![],
+        };
+        assert_eq!(
+            defs,
+            vec![Definition::Describe {
+                name: "outer".to_owned(),
+                defs: vec![
+                    test("a"),
+                    Definition::Describe {
+                        name: "inner".to_owned(),
+                        defs: vec![test("b")],
+                    },
+                ],
+            }]
+        );
+
+        assert!(parse(read(lex(r#"(describe "empty")"#)?)?).is_err());
+        assert!(parse(read(lex(r#"(describe "d" (on-load))"#)?)?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn directives() -> Result<()> {
+        let defs = parse(read(lex(
+            r#"(test-skip "s" "not yet" (assert false)) (test-todo "t" (assert false)) (test-fails "f" (assert false))"#,
+        )?)?)?;
+        let assert_false = || Statement::Assert {
+            expr: Expr::LitBool(false),
+            origin: origin("(assert false)"),
+        };
+        assert_eq!(
+            defs,
+            vec![
+                Definition::Test {
+                    name: "s".to_owned(),
+                    directive: Some(Directive::Skip {
+                        reason: "not yet".to_owned()
+                    }),
+                    fails: false,
+                    budget: None,
+                    stmts: vec![assert_false()],
+                },
+                Definition::Test {
+                    name: "t".to_owned(),
+                    directive: Some(Directive::Todo),
+                    fails: false,
+                    budget: None,
+                    stmts: vec![assert_false()],
+                },
+                Definition::Test {
+                    name: "f".to_owned(),
+                    directive: None,
+                    fails: true,
+                    budget: None,
+                    stmts: vec![assert_false()],
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn budget() -> Result<()> {
+        let defs = parse(read(lex(r#"(test "t" :budget 100 (assert false))"#)?)?)?;
+        assert_eq!(
+            defs,
+            vec![Definition::Test {
+                name: "t".to_owned(),
+                directive: None,
+                fails: false,
+                budget: Some(100),
+                stmts: vec![Statement::Assert {
+                    expr: Expr::LitBool(false),
+                    origin: origin("(assert false)"),
+                }],
+            }]
+        );
+
+        assert!(parse(read(lex(r#"(test "t" :budget 0 (assert true))"#)?)?).is_err());
+        assert!(parse(read(lex(r#"(test "t" :budget "lots" (assert true))"#)?)?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_each() -> Result<()> {
+        let defs = parse(read(lex(
+            r#"(test-each "square ~a" ((1 1) (2 4)) (n want) (asserteq (* n n) want))"#,
+        )?)?)?;
+        let row = |n, want| Definition::Test {
+            name: format!("square {n}"),
+            directive: None,
+            fails: false,
+            budget: None,
+            stmts: vec![
+                Statement::Let {
+                    variable_name: "n".to_owned(),
+                    expr: Expr::LitInt(n),
+                },
+                Statement::Let {
+                    variable_name: "want".to_owned(),
+                    expr: Expr::LitInt(want),
+                },
+                Statement::AssertEq {
+                    left: Expr::Times {
+                        left: Box::new(Expr::Variable("n".to_owned())),
+                        right: Box::new(Expr::Variable("n".to_owned())),
+                    },
+                    right: Expr::Variable("want".to_owned()),
+                    origin: origin("(asserteq (* n n) want)"),
+                },
+            ],
+        };
+        assert_eq!(defs, vec![row(1, 1), row(2, 4)]);
+
+        assert!(parse(read(lex(r#"(test-each "t" ((1 2)) (n) (assert true))"#)?)?).is_err());
+        assert!(parse(read(lex(r#"(test-each "t ~a ~a" ((1)) (n) (assert true))"#)?)?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn comparisons() -> Result<()> {
+        let stmts = |source: &str| -> Result<Vec<Statement>> {
+            match parse(read(lex(&format!("(test \"t\" {source})"))?)?)?.pop() {
+                Some(Definition::Test { stmts, .. }) => Ok(stmts),
+                _ => panic!("Expected test definition"),
+            }
+        };
+        let x = || Expr::Variable("x".to_owned());
+        let message = |source: &str, message: &str| Origin {
+            message: Some(message.to_owned()),
+            ..origin(source)
+        };
+
+        assert_eq!(
+            stmts(r#"(assert-ne x 1) (assert< x 2 "small") (assert<= 0 x)"#)?,
+            vec![
+                Statement::AssertCmp {
+                    relation: Relation::NotEqual,
+                    left: x(),
+                    right: Expr::LitInt(1),
+                    origin: origin("(assert-ne x 1)"),
+                },
+                Statement::AssertCmp {
+                    relation: Relation::Less,
+                    left: x(),
+                    right: Expr::LitInt(2),
+                    origin: message(r#"(assert< x 2 "small")"#, "small"),
+                },
+                Statement::AssertCmp {
+                    relation: Relation::LessOrEqual,
+                    left: Expr::LitInt(0),
+                    right: x(),
+                    origin: origin("(assert<= 0 x)"),
+                },
+            ]
+        );
+        assert_eq!(
+            stmts(r#"(assert-between x 1 9 "in range") (assert true "holds")"#)?,
+            vec![
+                Statement::AssertBetween {
+                    expr: x(),
+                    lo: Expr::LitInt(1),
+                    hi: Expr::LitInt(9),
+                    origin: message(r#"(assert-between x 1 9 "in range")"#, "in range"),
+                },
+                Statement::Assert {
+                    expr: Expr::LitBool(true),
+                    origin: message(r#"(assert true "holds")"#, "holds"),
+                },
+            ]
         );
+        assert!(stmts(r#"(asserteq x 1 "one" "two")"#).is_err());
         Ok(())
     }
 
@@ -270,19 +1165,19 @@ mod test {
     fn multitest() -> Result<()> {
         let tokens = vec![
             LeftParen,
-            Test,
+            Symbol("test".to_owned()),
             String(r#"test 1"#.to_owned()),
             LeftParen,
-            Assert,
-            Boolean(true),
+            Symbol("assert".to_owned()),
+            Symbol("true".to_owned()),
             RightParen,
             RightParen,
             LeftParen,
-            Test,
+            Symbol("test".to_owned()),
             String(r#"test 2"#.to_owned()),
             LeftParen,
-            Assert,
-            Boolean(true),
+            Symbol("assert".to_owned()),
+            Symbol("true".to_owned()),
             RightParen,
             RightParen,
         ];
@@ -290,18 +1185,26 @@ mod test {
             vec![
                 Definition::Test {
                     name: "test 1".to_owned(),
+                    directive: None,
+                    fails: false,
+                    budget: None,
                     stmts: vec![Statement::Assert {
+                        origin: origin("(assert true)"),
                         expr: Expr::LitBool(true)
                     }]
                 },
                 Definition::Test {
                     name: "test 2".to_owned(),
+                    directive: None,
+                    fails: false,
+                    budget: None,
                     stmts: vec![Statement::Assert {
+                        origin: origin("(assert true)"),
                         expr: Expr::LitBool(true)
                     }]
                 }
             ],
-            parse(tokens)?
+            parse(read(tokens)?)?
         );
         Ok(())
     }
@@ -310,12 +1213,12 @@ mod test {
     fn addition() -> Result<()> {
         let tokens = vec![
             LeftParen,
-            Test,
+            Symbol("test".to_owned()),
             String(r#"test"#.to_owned()),
             LeftParen,
-            Assert,
+            Symbol("assert".to_owned()),
             LeftParen,
-            Plus,
+            Symbol("+".to_owned()),
             Int(1),
             Int(1),
             RightParen,
@@ -325,14 +1228,18 @@ mod test {
         assert_eq!(
             vec![Definition::Test {
                 name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
                 stmts: vec![Statement::Assert {
+                    origin: origin("(assert (+ 1 1))"),
                     expr: Expr::Plus {
                         left: Box::new(Expr::LitInt(1)),
                         right: Box::new(Expr::LitInt(1))
                     }
                 }]
             }],
-            parse(tokens)?
+            parse(read(tokens)?)?
         );
         Ok(())
     }
@@ -341,12 +1248,12 @@ mod test {
     fn subtraction() -> Result<()> {
         let tokens = vec![
             LeftParen,
-            Test,
+            Symbol("test".to_owned()),
             String(r#"test"#.to_owned()),
             LeftParen,
-            Assert,
+            Symbol("assert".to_owned()),
             LeftParen,
-            Dash,
+            Symbol("-".to_owned()),
             Int(1),
             Int(1),
             RightParen,
@@ -356,14 +1263,18 @@ mod test {
         assert_eq!(
             vec![Definition::Test {
                 name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
                 stmts: vec![Statement::Assert {
+                    origin: origin("(assert (- 1 1))"),
                     expr: Expr::Minus {
                         left: Box::new(Expr::LitInt(1)),
                         right: Box::new(Expr::LitInt(1))
                     }
                 }]
             }],
-            parse(tokens)?
+            parse(read(tokens)?)?
         );
         Ok(())
     }
@@ -372,12 +1283,12 @@ mod test {
     fn multiplication() -> Result<()> {
         let tokens = vec![
             LeftParen,
-            Test,
+            Symbol("test".to_owned()),
             String(r#"test"#.to_owned()),
             LeftParen,
-            Assert,
+            Symbol("assert".to_owned()),
             LeftParen,
-            Star,
+            Symbol("*".to_owned()),
             Int(1),
             Int(1),
             RightParen,
@@ -387,14 +1298,18 @@ mod test {
         assert_eq!(
             vec![Definition::Test {
                 name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
                 stmts: vec![Statement::Assert {
+                    origin: origin("(assert (* 1 1))"),
                     expr: Expr::Times {
                         left: Box::new(Expr::LitInt(1)),
                         right: Box::new(Expr::LitInt(1))
                     }
                 }]
             }],
-            parse(tokens)?
+            parse(read(tokens)?)?
         );
         Ok(())
     }
@@ -403,12 +1318,12 @@ mod test {
     fn division() -> Result<()> {
         let tokens = vec![
             LeftParen,
-            Test,
+            Symbol("test".to_owned()),
             String(r#"test"#.to_owned()),
             LeftParen,
-            Assert,
+            Symbol("assert".to_owned()),
             LeftParen,
-            Slash,
+            Symbol("/".to_owned()),
             Int(1),
             Int(1),
             RightParen,
@@ -418,14 +1333,18 @@ mod test {
         assert_eq!(
             vec![Definition::Test {
                 name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
                 stmts: vec![Statement::Assert {
+                    origin: origin("(assert (/ 1 1))"),
                     expr: Expr::Divide {
                         left: Box::new(Expr::LitInt(1)),
                         right: Box::new(Expr::LitInt(1))
                     }
                 }]
             }],
-            parse(tokens)?
+            parse(read(tokens)?)?
         );
         Ok(())
     }
@@ -434,15 +1353,15 @@ mod test {
     fn nested_arithmetic() -> Result<()> {
         let tokens = vec![
             LeftParen,
-            Test,
+            Symbol("test".to_owned()),
             String(r#"test"#.to_owned()),
             LeftParen,
-            Assert,
+            Symbol("assert".to_owned()),
             LeftParen,
-            Plus,
+            Symbol("+".to_owned()),
             Int(1),
             LeftParen,
-            Star,
+            Symbol("*".to_owned()),
             Int(1),
             Int(1),
             RightParen,
@@ -453,7 +1372,11 @@ mod test {
         assert_eq!(
             vec![Definition::Test {
                 name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
                 stmts: vec![Statement::Assert {
+                    origin: origin("(assert (+ 1 (* 1 1)))"),
                     expr: Expr::Plus {
                         left: Box::new(Expr::LitInt(1)),
                         right: Box::new(Expr::Times {
@@ -463,7 +1386,7 @@ mod test {
                     }
                 }]
             }],
-            parse(tokens)?
+            parse(read(tokens)?)?
         );
         Ok(())
     }
@@ -473,12 +1396,12 @@ mod test {
         // (test "test" (let (x 1)))
         let tokens = vec![
             LeftParen,
-            Test,
+            Symbol("test".to_owned()),
             String(r#"test"#.to_owned()),
             LeftParen,
-            Let,
+            Symbol("let".to_owned()),
             LeftParen,
-            Ident("x".to_owned()),
+            Symbol("x".to_owned()),
             Int(1),
             RightParen,
             RightParen,
@@ -487,12 +1410,15 @@ mod test {
         assert_eq!(
             vec![Definition::Test {
                 name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
                 stmts: vec![Statement::Let {
                     variable_name: "x".to_owned(),
                     expr: Expr::LitInt(1)
                 }]
             }],
-            parse(tokens)?
+            parse(read(tokens)?)?
         );
         Ok(())
     }
@@ -502,22 +1428,26 @@ mod test {
         // (test "test" x)
         let tokens = vec![
             LeftParen,
-            Test,
+            Symbol("test".to_owned()),
             String(r#"test"#.to_owned()),
             LeftParen,
-            Assert,
-            Ident("x".to_owned()),
+            Symbol("assert".to_owned()),
+            Symbol("x".to_owned()),
             RightParen,
             RightParen,
         ];
         assert_eq!(
             vec![Definition::Test {
                 name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
                 stmts: vec![Statement::Assert {
+                    origin: origin("(assert x)"),
                     expr: Expr::Variable("x".to_owned())
                 }]
             }],
-            parse(tokens)?
+            parse(read(tokens)?)?
         );
         Ok(())
     }
@@ -527,31 +1457,36 @@ mod test {
         // (test "test" (assert true) (assert false))
         let tokens = vec![
             LeftParen,
-            Test,
+            Symbol("test".to_owned()),
             String("test".to_owned()),
             LeftParen,
-            Assert,
-            Boolean(true),
+            Symbol("assert".to_owned()),
+            Symbol("true".to_owned()),
             RightParen,
             LeftParen,
-            Assert,
-            Boolean(false),
+            Symbol("assert".to_owned()),
+            Symbol("false".to_owned()),
             RightParen,
             RightParen,
         ];
         assert_eq!(
             vec![Definition::Test {
                 name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
                 stmts: vec![
                     Statement::Assert {
+                        origin: origin("(assert true)"),
                         expr: Expr::LitBool(true)
                     },
                     Statement::Assert {
+                        origin: origin("(assert false)"),
                         expr: Expr::LitBool(false)
                     }
                 ]
             }],
-            parse(tokens)?
+            parse(read(tokens)?)?
         );
 
         Ok(())
@@ -562,15 +1497,15 @@ mod test {
         // (test "test" (let (x 1)))
         let tokens = vec![
             LeftParen,
-            Test,
+            Symbol("test".to_owned()),
             String(r#"test"#.to_owned()),
             LeftParen,
-            Assert,
+            Symbol("assert".to_owned()),
             LeftParen,
-            If,
-            Boolean(true),
-            Boolean(false),
-            Boolean(true),
+            Symbol("if".to_owned()),
+            Symbol("true".to_owned()),
+            Symbol("false".to_owned()),
+            Symbol("true".to_owned()),
             RightParen,
             RightParen,
             RightParen,
@@ -578,7 +1513,11 @@ mod test {
         assert_eq!(
             vec![Definition::Test {
                 name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
                 stmts: vec![Statement::Assert {
+                    origin: origin("(assert (if true false true))"),
                     expr: Expr::If {
                         cond: Box::new(Expr::LitBool(true)),
                         thn: Box::new(Expr::LitBool(false)),
@@ -586,7 +1525,7 @@ mod test {
                     }
                 }]
             }],
-            parse(tokens)?
+            parse(read(tokens)?)?
         );
         Ok(())
     }
@@ -595,12 +1534,12 @@ mod test {
     fn eq() -> Result<()> {
         let tokens = vec![
             LeftParen,
-            Test,
+            Symbol("test".to_owned()),
             String(r#"test"#.to_owned()),
             LeftParen,
-            Assert,
+            Symbol("assert".to_owned()),
             LeftParen,
-            DoubleEquals,
+            Symbol("==".to_owned()),
             Int(1),
             Int(1),
             RightParen,
@@ -610,14 +1549,327 @@ mod test {
         assert_eq!(
             vec![Definition::Test {
                 name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
                 stmts: vec![Statement::Assert {
+                    origin: origin("(assert (== 1 1))"),
                     expr: Expr::Eq {
                         left: Box::new(Expr::LitInt(1)),
                         right: Box::new(Expr::LitInt(1))
                     }
                 }]
             }],
-            parse(tokens)?
+            parse(read(tokens)?)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn array_literal() -> Result<()> {
+        // (test "test" (asserteq (length (array 1 x)) 2))
+        let tokens = vec![
+            LeftParen,
+            Symbol("test".to_owned()),
+            String(r#"test"#.to_owned()),
+            LeftParen,
+            Symbol("asserteq".to_owned()),
+            LeftParen,
+            Symbol("length".to_owned()),
+            LeftParen,
+            Symbol("array".to_owned()),
+            Int(1),
+            Symbol("x".to_owned()),
+            RightParen,
+            RightParen,
+            Int(2),
+            RightParen,
+            RightParen,
+        ];
+        assert_eq!(
+            vec![Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![Statement::AssertEq {
+                    origin: origin("(asserteq (length (array 1 x)) 2)"),
+                    left: Expr::Length {
+                        array: Box::new(Expr::Array {
+                            elements: vec![Expr::LitInt(1), Expr::Variable("x".to_owned())]
+                        })
+                    },
+                    right: Expr::LitInt(2)
+                }]
+            }],
+            parse(read(tokens)?)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn array_set() -> Result<()> {
+        // (test "test" (array-set! a 0 5))
+        let tokens = vec![
+            LeftParen,
+            Symbol("test".to_owned()),
+            String(r#"test"#.to_owned()),
+            LeftParen,
+            Symbol("array-set!".to_owned()),
+            Symbol("a".to_owned()),
+            Int(0),
+            Int(5),
+            RightParen,
+            RightParen,
+        ];
+        assert_eq!(
+            vec![Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![Statement::ArraySet {
+                    array: Expr::Variable("a".to_owned()),
+                    index: Expr::LitInt(0),
+                    value: Expr::LitInt(5)
+                }]
+            }],
+            parse(read(tokens)?)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn defstruct() -> Result<()> {
+        // (defstruct pos x (on bool))
+        let tokens = vec![
+            LeftParen,
+            Symbol("defstruct".to_owned()),
+            Symbol("pos".to_owned()),
+            Symbol("x".to_owned()),
+            LeftParen,
+            Symbol("on".to_owned()),
+            Symbol("bool".to_owned()),
+            RightParen,
+            RightParen,
+        ];
+        assert_eq!(
+            vec![Definition::Struct {
+                name: "pos".to_owned(),
+                fields: vec![
+                    Field {
+                        name: "x".to_owned(),
+                        ty: Type::Named("int".to_owned())
+                    },
+                    Field {
+                        name: "on".to_owned(),
+                        ty: Type::Named("bool".to_owned())
+                    }
+                ]
+            }],
+            parse(read(tokens)?)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn apply() -> Result<()> {
+        // (test "test" (assert (pos-x p)))
+        let tokens = vec![
+            LeftParen,
+            Symbol("test".to_owned()),
+            String(r#"test"#.to_owned()),
+            LeftParen,
+            Symbol("assert".to_owned()),
+            LeftParen,
+            Symbol("pos-x".to_owned()),
+            Symbol("p".to_owned()),
+            RightParen,
+            RightParen,
+            RightParen,
+        ];
+        assert_eq!(
+            vec![Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![Statement::Assert {
+                    origin: origin("(assert (pos-x p))"),
+                    expr: Expr::Apply {
+                        name: "pos-x".to_owned(),
+                        args: vec![Expr::Variable("p".to_owned())]
+                    }
+                }]
+            }],
+            parse(read(tokens)?)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn defn() -> Result<()> {
+        // (defn apply ((f (fn (int) int)) (x int)) int (let (y (f x))) y)
+        let tokens = vec![
+            LeftParen,
+            Symbol("defn".to_owned()),
+            Symbol("apply".to_owned()),
+            LeftParen,
+            LeftParen,
+            Symbol("f".to_owned()),
+            LeftParen,
+            Symbol("fn".to_owned()),
+            LeftParen,
+            Symbol("int".to_owned()),
+            RightParen,
+            Symbol("int".to_owned()),
+            RightParen,
+            RightParen,
+            LeftParen,
+            Symbol("x".to_owned()),
+            Symbol("int".to_owned()),
+            RightParen,
+            RightParen,
+            Symbol("int".to_owned()),
+            LeftParen,
+            Symbol("let".to_owned()),
+            LeftParen,
+            Symbol("y".to_owned()),
+            LeftParen,
+            Symbol("f".to_owned()),
+            Symbol("x".to_owned()),
+            RightParen,
+            RightParen,
+            RightParen,
+            Symbol("y".to_owned()),
+            RightParen,
+        ];
+        let int = || Type::Named("int".to_owned());
+        assert_eq!(
+            vec![Definition::Function {
+                name: "apply".to_owned(),
+                params: vec![
+                    Param {
+                        name: "f".to_owned(),
+                        ty: Type::Function {
+                            params: vec![int()],
+                            ret: Box::new(int())
+                        }
+                    },
+                    Param {
+                        name: "x".to_owned(),
+                        ty: int()
+                    }
+                ],
+                ret: int(),
+                stmts: vec![Statement::Let {
+                    variable_name: "y".to_owned(),
+                    expr: Expr::Apply {
+                        name: "f".to_owned(),
+                        args: vec![Expr::Variable("x".to_owned())]
+                    }
+                }],
+                result: Expr::Variable("y".to_owned())
+            }],
+            parse(read(tokens)?)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn execute() -> Result<()> {
+        // (test "test" (as "@e" (at "@s" (/ "say hi"))))
+        let tokens = vec![
+            LeftParen,
+            Symbol("test".to_owned()),
+            String("test".to_owned()),
+            LeftParen,
+            Symbol("as".to_owned()),
+            String("@e".to_owned()),
+            LeftParen,
+            Symbol("at".to_owned()),
+            String("@s".to_owned()),
+            LeftParen,
+            Symbol("/".to_owned()),
+            String("say hi".to_owned()),
+            RightParen,
+            RightParen,
+            RightParen,
+            RightParen,
+        ];
+        assert_eq!(
+            vec![Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![Statement::Execute {
+                    context: Context::As("@e".to_owned()),
+                    stmts: vec![Statement::Execute {
+                        context: Context::At("@s".to_owned()),
+                        stmts: vec![Statement::Command {
+                            text: "say hi".to_owned()
+                        }]
+                    }]
+                }]
+            }],
+            parse(read(tokens)?)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nbt() -> Result<()> {
+        let program = parse(read(lex(
+            r#"(test "test" (nbt-set! storage "mctest:t" "x" double 0.5 (nbt-get entity "@s" "Health" 10)))"#,
+        )?)?)?;
+        assert_eq!(
+            vec![Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![Statement::NbtSet {
+                    source: NbtSource::Storage("mctest:t".to_owned()),
+                    path: "x".to_owned(),
+                    ty: "double".to_owned(),
+                    scale: "0.5".to_owned(),
+                    value: Expr::NbtGet {
+                        source: NbtSource::Entity("@s".to_owned()),
+                        path: "Health".to_owned(),
+                        scale: "10".to_owned(),
+                    },
+                }]
+            }],
+            program
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn asm() -> Result<()> {
+        let program = parse(read(lex(
+            r#"(test "test" (asm "data merge {x} {y} {Tags:[]}{y}" y x))"#,
+        )?)?)?;
+        assert_eq!(
+            vec![Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![Statement::Asm {
+                    template: vec![
+                        Fragment::Text("data merge ".to_owned()),
+                        Fragment::Var(1),
+                        Fragment::Text(" ".to_owned()),
+                        Fragment::Var(0),
+                        Fragment::Text(" {Tags:[]}".to_owned()),
+                        Fragment::Var(0),
+                    ],
+                    vars: vec!["y".to_owned(), "x".to_owned()],
+                }]
+            }],
+            program
         );
         Ok(())
     }
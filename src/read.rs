@@ -0,0 +1,108 @@
+use std::fmt::Display;
+
+use crate::lex::Token;
+use crate::utility::{escape, Quoting};
+use anyhow::{anyhow, Result};
+
+/// The source read into nested lists, before any of it is given a meaning.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Datum {
+    /// The items of a list and the line its `(` is on.
+    List(Vec<Datum>, usize),
+    Symbol(String),
+    String(String),
+    Int(i64),
+    Decimal(String),
+}
+
+impl Display for Datum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Datum::List(items, _) => {
+                write!(f, "(")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, ")")
+            }
+            Datum::Symbol(s) => write!(f, "{s}"),
+            Datum::String(s) => write!(f, "\"{}\"", escape(s, Quoting::Source)),
+            Datum::Int(i) => write!(f, "{i}"),
+            Datum::Decimal(d) => write!(f, "{d}"),
+        }
+    }
+}
+
+pub fn read(tokens: Vec<Token>) -> Result<Vec<Datum>> {
+    let mut line = 1;
+    let mut tokens = tokens.into_iter().filter_map(|token| match token {
+        Token::Newline => {
+            line += 1;
+            None
+        }
+        token => Some((token, line)),
+    });
+    let mut data = Vec::new();
+    while let Some((token, line)) = tokens.next() {
+        data.push(read_datum(token, line, &mut tokens)?);
+    }
+    Ok(data)
+}
+
+fn read_datum(
+    token: Token,
+    line: usize,
+    tokens: &mut impl Iterator<Item = (Token, usize)>,
+) -> Result<Datum> {
+    Ok(match token {
+        Token::LeftParen => {
+            let mut items = Vec::new();
+            loop {
+                match tokens.next() {
+                    Some((Token::RightParen, _)) => break Datum::List(items, line),
+                    Some((token, line)) => items.push(read_datum(token, line, tokens)?),
+                    None => return Err(anyhow!("Expected ) before the end of the source")),
+                }
+            }
+        }
+        Token::RightParen => return Err(anyhow!("Unexpected ) without a matching (")),
+        Token::Newline => unreachable!("newlines are only counted"),
+        Token::Symbol(s) => Datum::Symbol(s),
+        Token::String(s) => Datum::String(s),
+        Token::Int(i) => Datum::Int(i),
+        Token::Decimal(d) => Datum::Decimal(d),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lex::lex;
+
+    #[test]
+    fn nested_lists() -> Result<()> {
+        assert_eq!(
+            vec![
+                Datum::List(vec![
+                    Datum::Symbol("test".to_owned()),
+                    Datum::String("t".to_owned()),
+                    Datum::List(vec![Datum::Symbol("/".to_owned()), Datum::String("say hi".to_owned())], 2),
+                    Datum::List(vec![], 2),
+                ], 1),
+                Datum::Int(1),
+            ],
+            read(lex("(test \"t\"\n  (/ \"say hi\") ()) 1")?)?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unbalanced() -> Result<()> {
+        assert!(read(lex("(a (b)")?).is_err());
+        assert!(read(lex("(a))")?).is_err());
+        Ok(())
+    }
+}
@@ -1,9 +1,10 @@
+use std::collections::HashMap;
 use std::fmt::Display;
 
-use crate::linearize::Test;
+use crate::linearize::{Cmp, Component, Context, Fragment, Function, Handler, Test};
 use crate::select_instructions::Op;
 type Graph = petgraph::Graph<Block, (), petgraph::Directed, u32>;
-type Index = petgraph::graph::NodeIndex<u32>;
+pub type Index = petgraph::graph::NodeIndex<u32>;
 
 use crate::assign_homes::Register;
 use crate::insert_jmps as prev;
@@ -12,6 +13,10 @@ use crate::insert_jmps as prev;
 pub struct Program {
     pub blocks: Graph,
     pub tests: Vec<Test>,
+    pub functions: Vec<Function>,
+    pub handlers: Vec<Handler>,
+    pub teardown: Option<usize>,
+    pub frames: HashMap<Index, u32>,
 }
 
 #[derive(Debug, Clone)]
@@ -37,7 +42,7 @@ pub enum Instruction {
         offset: u32,
     },
     Tellraw {
-        text: String,
+        components: Vec<Component>,
     },
     Command {
         text: String,
@@ -52,25 +57,52 @@ pub enum Instruction {
         value: i64,
         run: Run,
     },
-    ExecuteIfScoreEquals {
+    ExecuteIfScoreCompare {
+        cmp: Cmp,
         a: Location,
         b: Location,
         run: Run,
     },
-    ExecuteUnlessScoreEquals {
+    ExecuteUnlessScoreCompare {
+        cmp: Cmp,
         a: Location,
         b: Location,
         run: Run,
     },
+    ExecuteContext {
+        context: Vec<Context>,
+        run: Run,
+    },
+    ExecuteStoreResultScore {
+        location: Location,
+        command: String,
+    },
+    ExecuteStoreResultGetScore {
+        location: Location,
+        target: String,
+    },
     Function {
         block: Index,
-    }
+    },
+    Call {
+        id: usize,
+    },
+    Dispatch,
+    Schedule {
+        block: Index,
+        ticks: i64,
+    },
+    Execute {
+        run: Run,
+    },
 }
 
 #[derive(Debug, Clone)]
 pub enum Run {
     Function { block: Index },
     Set { location: Location, value: i64 },
+    ReturnIfFunction { block: Index },
+    FunctionUnlessReturned { block: Index },
 }
 
 #[derive(Clone, Debug)]
@@ -78,6 +110,14 @@ pub enum Location {
     Register(Register),
     StackItem,
     Scratch,
+    Argument(usize),
+    Fn,
+    /// Holds a spilled variable while an `asm` line uses it.
+    Asm(usize),
+    /// Holds the second side of a comparison that was spilled.
+    Compared,
+    /// A score outside of the ones the compiler manages.
+    Score { target: String, objective: String },
 }
 
 impl Display for Location {
@@ -86,6 +126,11 @@ impl Display for Location {
             Location::Register(r) => write!(f, "{r} registry"),
             Location::StackItem => write!(f, "item stack"),
             Location::Scratch => write!(f, "scratch registry"),
+            Location::Argument(n) => write!(f, "a{n} registry"),
+            Location::Fn => write!(f, "fn registry"),
+            Location::Asm(n) => write!(f, "asm{n} registry"),
+            Location::Compared => write!(f, "compared registry"),
+            Location::Score { target, objective } => write!(f, "{target} {objective}"),
         }
     }
 }
@@ -97,6 +142,10 @@ pub fn reify_location(program: prev::Program) -> Program {
     Program {
         blocks,
         tests: program.tests,
+        functions: program.functions,
+        handlers: program.handlers,
+        teardown: program.teardown,
+        frames: program.frames,
     }
 }
 
@@ -198,7 +247,7 @@ fn reify_location_instr(instr: prev::Instruction) -> Vec<Instruction> {
                 offset: destination_offset,
             },
         ],
-        prev::Instruction::Tellraw { text } => vec![Instruction::Tellraw { text }],
+        prev::Instruction::Tellraw { components } => vec![Instruction::Tellraw { components }],
         prev::Instruction::Command { text } => vec![Instruction::Command { text }],
         prev::Instruction::ExecuteIfScoreMatches {
             location: prev::Location::Register(r),
@@ -262,15 +311,246 @@ fn reify_location_instr(instr: prev::Instruction) -> Vec<Instruction> {
             instrs.extend(after);
             instrs
         }
-        prev::Instruction::ExecuteIfScoreEquals { a, b, run } => todo!(),
-        prev::Instruction::ExecuteUnlessScoreEquals { a, b, run } => todo!(),
+        prev::Instruction::ExecuteContext { context, run } => {
+            let (run, after) = reify_location_run(run);
+            let mut instrs = vec![Instruction::ExecuteContext { context, run }];
+            instrs.extend(after);
+            instrs
+        }
+        prev::Instruction::Execute { run } => {
+            let (run, after) = reify_location_run(run);
+            let mut instrs = vec![Instruction::Execute { run }];
+            instrs.extend(after);
+            instrs
+        }
+        prev::Instruction::ExecuteStoreResultScore {
+            location: prev::Location::Register(r),
+            command,
+        } => vec![Instruction::ExecuteStoreResultScore {
+            location: Location::Register(r),
+            command,
+        }],
+        prev::Instruction::ExecuteStoreResultScore {
+            location: prev::Location::Stack { offset },
+            command,
+        } => vec![
+            Instruction::ExecuteStoreResultScore {
+                location: Location::StackItem,
+                command,
+            },
+            Instruction::Push { offset },
+        ],
+        prev::Instruction::ExecuteStoreResultGetScore {
+            location: prev::Location::Register(r),
+            target,
+        } => vec![Instruction::ExecuteStoreResultGetScore {
+            location: Location::Register(r),
+            target,
+        }],
+        prev::Instruction::ExecuteStoreResultGetScore {
+            location: prev::Location::Stack { offset },
+            target,
+        } => vec![
+            Instruction::Pop { offset },
+            Instruction::ExecuteStoreResultGetScore {
+                location: Location::StackItem,
+                target,
+            },
+        ],
+        prev::Instruction::ExecuteIfScoreCompare { cmp, a, b, run } => {
+            let (mut instrs, a, b, run, after) = reify_comparison(a, b, run);
+            instrs.push(Instruction::ExecuteIfScoreCompare { cmp, a, b, run });
+            instrs.extend(after);
+            instrs
+        }
+        prev::Instruction::ExecuteUnlessScoreCompare { cmp, a, b, run } => {
+            let (mut instrs, a, b, run, after) = reify_comparison(a, b, run);
+            instrs.push(Instruction::ExecuteUnlessScoreCompare { cmp, a, b, run });
+            instrs.extend(after);
+            instrs
+        }
         prev::Instruction::Function { block } => vec![ Instruction::Function { block } ],
+        prev::Instruction::Schedule { block, ticks } => vec![Instruction::Schedule { block, ticks }],
+        // Each spilled variable is brought into a register of its own for the command, and put
+        // back on the stack after it in case the command changed it.
+        prev::Instruction::Asm { template, locations } => {
+            let mut before = Vec::new();
+            let mut after = Vec::new();
+            let locations: Vec<Location> = locations
+                .into_iter()
+                .enumerate()
+                .map(|(i, location)| match location {
+                    prev::Location::Register(r) => Location::Register(r),
+                    spilled => {
+                        before.extend(copy_into(spilled.clone(), Location::Asm(i)));
+                        after.extend(copy_from(Location::Asm(i), spilled));
+                        Location::Asm(i)
+                    }
+                })
+                .collect();
+            let text = template
+                .into_iter()
+                .map(|fragment| match fragment {
+                    Fragment::Text(text) => text,
+                    Fragment::Var(index) => locations[index].to_string(),
+                })
+                .collect();
+            before.push(Instruction::Command { text });
+            before.extend(after);
+            before
+        }
+        prev::Instruction::Enter { frame_size: 0 } => vec![],
+        prev::Instruction::Enter { frame_size } => vec![Instruction::Command {
+            text: format!("scoreboard players add ptr stack {frame_size}"),
+        }],
+        prev::Instruction::LoadArg { location, index } => {
+            copy_from(Location::Argument(index + 1), location)
+        }
+        prev::Instruction::Call { id, args, result } => {
+            let mut instrs = load_args(args);
+            instrs.push(Instruction::Call { id });
+            instrs.extend(copy_from(Location::Argument(1), result));
+            instrs
+        }
+        prev::Instruction::CallIndirect { function, args, result } => {
+            let mut instrs = load_args(args);
+            instrs.extend(copy_into(function, Location::Fn));
+            instrs.push(Instruction::Dispatch);
+            instrs.extend(copy_from(Location::Argument(1), result));
+            instrs
+        }
+        prev::Instruction::Return { location, frame_size } => {
+            let mut instrs = copy_into(location, Location::Argument(1));
+            instrs.extend(release(frame_size));
+            instrs
+        }
+        prev::Instruction::TailCall { id, args, frame_size } => {
+            let mut instrs = load_args(args);
+            instrs.extend(release(frame_size));
+            instrs.push(Instruction::Call { id });
+            instrs
+        }
+        prev::Instruction::TailCallIndirect { function, args, frame_size } => {
+            let mut instrs = load_args(args);
+            instrs.extend(copy_into(function, Location::Fn));
+            instrs.extend(release(frame_size));
+            instrs.push(Instruction::Dispatch);
+            instrs
+        }
+        prev::Instruction::GetScore { location, target, objective } => {
+            copy_from(Location::Score { target, objective }, location)
+        }
+        prev::Instruction::SetScore { target, objective, location } => {
+            copy_into(location, Location::Score { target, objective })
+        }
+        prev::Instruction::Disarm { location: prev::Location::Register(r), .. } => {
+            vec![Instruction::Set { location: Location::Register(r), value: 0 }]
+        }
+        // The frame has already been released, so it is taken back just long enough to write to it.
+        prev::Instruction::Disarm { location: prev::Location::Stack { offset }, frame_size } => vec![
+            Instruction::Command {
+                text: format!("scoreboard players add ptr stack {frame_size}"),
+            },
+            Instruction::Set { location: Location::StackItem, value: 0 },
+            Instruction::Push { offset },
+            Instruction::Command {
+                text: format!("scoreboard players remove ptr stack {frame_size}"),
+            },
+        ],
+    }
+}
+
+fn release(frame_size: u32) -> Vec<Instruction> {
+    if frame_size == 0 {
+        return vec![];
+    }
+    vec![Instruction::Command {
+        text: format!("scoreboard players remove ptr stack {frame_size}"),
+    }]
+}
+
+fn load_args(args: Vec<prev::Location>) -> Vec<Instruction> {
+    args.into_iter()
+        .enumerate()
+        .flat_map(|(i, arg)| copy_into(arg, Location::Argument(i + 1)))
+        .collect()
+}
+
+fn copy_into(source: prev::Location, destination: Location) -> Vec<Instruction> {
+    match source {
+        prev::Location::Register(r) => vec![Instruction::Operation {
+            op: Op::Equals,
+            source: Location::Register(r),
+            destination,
+        }],
+        prev::Location::Stack { offset } => vec![
+            Instruction::Pop { offset },
+            Instruction::Operation {
+                op: Op::Equals,
+                source: Location::StackItem,
+                destination,
+            },
+        ],
+    }
+}
+
+fn copy_from(source: Location, destination: prev::Location) -> Vec<Instruction> {
+    match destination {
+        prev::Location::Register(r) => vec![Instruction::Operation {
+            op: Op::Equals,
+            source,
+            destination: Location::Register(r),
+        }],
+        prev::Location::Stack { offset } => vec![
+            Instruction::Operation {
+                op: Op::Equals,
+                source,
+                destination: Location::StackItem,
+            },
+            Instruction::Push { offset },
+        ],
+    }
+}
+
+/// Copies the spilled sides of a comparison into scores of their own, as there is only one stack
+/// item and the `run` may need it to set a spilled variable.
+fn reify_comparison(
+    a: prev::Location,
+    b: prev::Location,
+    run: prev::Run,
+) -> (Vec<Instruction>, Location, Location, Run, Vec<Instruction>) {
+    let mut before = Vec::new();
+    let mut operand = |location, copy: Location| match location {
+        prev::Location::Register(r) => Location::Register(r),
+        prev::Location::Stack { offset } => {
+            before.push(Instruction::Pop { offset });
+            before.push(Instruction::Operation {
+                op: Op::Equals,
+                source: Location::StackItem,
+                destination: copy.clone(),
+            });
+            copy
+        }
+    };
+    let a = operand(a, Location::Scratch);
+    let b = operand(b, Location::Compared);
+    // The variable keeps its value when the comparison fails, so it is read before being put back.
+    if let prev::Run::Set {
+        location: prev::Location::Stack { offset },
+        ..
+    } = run
+    {
+        before.push(Instruction::Pop { offset });
     }
+    let (run, after) = reify_location_run(run);
+    (before, a, b, run, after)
 }
 
 fn reify_location_run(run: prev::Run) -> (Run, Vec<Instruction>) {
     match run {
         prev::Run::Function { block } => (Run::Function { block }, Vec::new()),
+        prev::Run::ReturnIfFunction { block } => (Run::ReturnIfFunction { block }, Vec::new()),
+        prev::Run::FunctionUnlessReturned { block } => (Run::FunctionUnlessReturned { block }, Vec::new()),
         prev::Run::Set { location: prev::Location::Register(r), value } => (Run::Set { location: Location::Register(r), value }, Vec::new()),
         prev::Run::Set { location: prev::Location::Stack { offset }, value } => (Run::Set { location: Location::StackItem, value }, vec![Instruction::Push { offset }])
     }
@@ -1,5 +1,37 @@
 use crate::datapack::Function;
 
+/// The random sequence `random` draws from, so that `set-seed!` makes it repeatable.
+pub const RANDOM_SEQUENCE: &str = "mctest:random";
+
+/// The score counting the failed tests in a describe, which its summary is reported from.
+pub fn group_failures(id: usize) -> String {
+    format!("group{id} registry")
+}
+
+/// Where `ptr` starts, and where it has to be back at once a test is over.
+pub const STACK_BASE: i64 = 10;
+
+/// How many slots the `stack` objective has. Frames are pushed from `STACK_BASE` up, and one
+/// that would go past the end sets `OVERFLOWED` instead of being written.
+pub const STACK_SIZE: i64 = 32;
+
+/// Set once something has been pushed or popped outside of the stack.
+pub const OVERFLOWED: &str = "overflowed registry";
+
+/// Set once a test has left the stack unbalanced, after which no more tests are run.
+pub const BAILED: &str = "bailed registry";
+
+/// The blocks a test with a budget has left to enter. It goes below zero once the test has
+/// entered one too many, after which no more blocks are entered and nothing more is reported.
+pub const BUDGET: &str = "budget registry";
+
+/// The budget outside of tests with one, which nothing runs long enough to use up.
+pub const UNLIMITED_BUDGET: i64 = i32::MAX as i64;
+
+/// Set once the running test has told whether it passed, so that it is not reported again if it
+/// runs out of budget after that.
+pub const REPORTED: &str = "reported registry";
+
 pub struct Runtime {
     pub init: String,
     pub functions: Vec<Function>,
@@ -17,35 +49,63 @@ fn setup_init() -> String {
 
     // Registry
     init.push_str(&format!("scoreboard objectives add registry dummy\n"));
+    init.push_str(&format!("scoreboard players set {BAILED} 0\n"));
+    init.push_str(&format!("scoreboard players set {BUDGET} {UNLIMITED_BUDGET}\n"));
+
+    // Stack
+    init.push_str(&format!("scoreboard objectives add stack dummy\n"));
+    init.push_str(&format!("scoreboard players set ptr stack {STACK_BASE}\n"));
+
+    init.push_str(&setup_registers());
+
+    // Heap
+    init.push_str("data modify storage mctest:heap arrays set value []\n");
+    init.push_str("data modify storage mctest:heap args set value {}\n");
+    init
+}
+
+/// Clears the registers and the values on the stack, as they are at load and before each test.
+fn setup_registers() -> String {
+    let mut registers = String::new();
+
     // Caller saved registers
     for i in 1..=8 {
-        init.push_str(&format!("scoreboard players set r{i} registry 0\n"));
+        registers.push_str(&format!("scoreboard players set r{i} registry 0\n"));
     }
     // Callee saved registers
     for i in 1..=8 {
-        init.push_str(&format!("scoreboard players set e{i} registry 0\n"));
+        registers.push_str(&format!("scoreboard players set e{i} registry 0\n"));
     }
     // Argument passing registers
     for i in 1..=8 {
-        init.push_str(&format!("scoreboard players set a{i} registry 0\n"));
+        registers.push_str(&format!("scoreboard players set a{i} registry 0\n"));
     }
+    // Id of the function to call through mctest:dispatch
+    registers.push_str(&format!("scoreboard players set fn registry 0\n"));
+    registers.push_str(&format!("scoreboard players set returned registry 0\n"));
+    registers.push_str(&format!("scoreboard players set {OVERFLOWED} 0\n"));
+    registers.push_str(&format!("scoreboard players set {REPORTED} 0\n"));
 
-    // Stack
-    init.push_str(&format!("scoreboard objectives add stack dummy\n"));
-    init.push_str(&format!("scoreboard players set ptr stack 0\n"));
-    init.push_str(&format!("scoreboard players set offset stack 0\n"));
-    init.push_str(&format!("scoreboard players set item stack 0\n"));
+    registers.push_str(&format!("scoreboard players set offset stack 0\n"));
+    registers.push_str(&format!("scoreboard players set item stack 0\n"));
     // Stack values
-    for i in 0..32 {
-        init.push_str(&format!("scoreboard players set {i} stack 0\n"));
+    for i in 0..STACK_SIZE {
+        registers.push_str(&format!("scoreboard players set {i} stack 0\n"));
     }
-    init
+    registers
 }
 
 fn setup_functions() -> Vec<Function> {
     let mut funcs = Vec::new();
     funcs.push(setup_push());
     funcs.push(setup_pop());
+    funcs.extend(setup_arrays());
+    funcs.push(setup_random());
+    funcs.push(Function {
+        namespace: "mctest".to_owned(),
+        name: "reset".to_owned(),
+        content: setup_registers(),
+    });
 
     funcs
 }
@@ -62,9 +122,13 @@ fn setup_push() -> Function {
         "scoreboard players operation tmp stack -= offset stack\n"
     ));
 
-    for i in 0..32 {
+    for i in 0..STACK_SIZE {
         content.push_str(&format!("execute if score tmp stack matches {i} run scoreboard players operation {i} stack = item stack\n"));
     }
+    content.push_str(&format!(
+        "execute unless score tmp stack matches 0..{} run scoreboard players set {OVERFLOWED} 1\n",
+        STACK_SIZE - 1
+    ));
 
     Function {
         namespace,
@@ -85,9 +149,13 @@ fn setup_pop() -> Function {
         "scoreboard players operation tmp stack -= offset stack\n"
     ));
 
-    for i in 0..32 {
+    for i in 0..STACK_SIZE {
         content.push_str(&format!("execute if score tmp stack matches {i} run scoreboard players operation item stack = {i} stack\n"));
     }
+    content.push_str(&format!(
+        "execute unless score tmp stack matches 0..{} run scoreboard players set {OVERFLOWED} 1\n",
+        STACK_SIZE - 1
+    ));
 
     Function {
         namespace,
@@ -95,3 +163,61 @@ fn setup_pop() -> Function {
         content,
     }
 }
+
+/// Draws a number for bounds that are only known at runtime, as `random` only takes literal ranges.
+fn setup_random() -> Function {
+    Function {
+        namespace: "mctest".to_owned(),
+        name: "random".to_owned(),
+        content: format!(
+            "$execute store result storage mctest:heap ret int 1 run random value $(lo)..$(hi) {RANDOM_SEQUENCE}\n"
+        ),
+    }
+}
+
+/// Array handles are indices into `arrays` in `mctest:heap` storage. The functions below are
+/// called as macros with their arguments in `args`, and leave any result in `ret`.
+fn setup_arrays() -> Vec<Function> {
+    let array = |name: &str, content: &str| Function {
+        namespace: "mctest".to_owned(),
+        name: name.to_owned(),
+        content: content.to_owned(),
+    };
+
+    vec![
+        array(
+            "array_get",
+            "$data modify storage mctest:heap ret set from storage mctest:heap arrays[$(array)][$(index)]\n",
+        ),
+        array(
+            "array_set",
+            "$data modify storage mctest:heap arrays[$(array)][$(index)] set value $(value)\n",
+        ),
+        array(
+            "array_get_field",
+            "$data modify storage mctest:heap ret set from storage mctest:heap arrays[$(array)][$(index)].$(field)\n",
+        ),
+        array(
+            "array_set_field",
+            "$data modify storage mctest:heap arrays[$(array)][$(index)].$(field) set value $(value)\n",
+        ),
+        array(
+            "array_push",
+            "$data modify storage mctest:heap arrays[$(array)] append value $(value)\n",
+        ),
+        array(
+            "array_length",
+            "$execute store result storage mctest:heap ret int 1 run data get storage mctest:heap arrays[$(array)]\n",
+        ),
+        // `data modify` only succeeds when it changes something, so copying the right array
+        // over a copy of the left one fails exactly when they are equal.
+        array(
+            "array_eq",
+            concat!(
+                "$data modify storage mctest:heap cmp set from storage mctest:heap arrays[$(left)]\n",
+                "$execute store success score scratch registry run data modify storage mctest:heap cmp set from storage mctest:heap arrays[$(right)]\n",
+                "execute store result storage mctest:heap ret int 1 if score scratch registry matches 0\n",
+            ),
+        ),
+    ]
+}
@@ -3,7 +3,9 @@ use std::fmt::Display;
 use petgraph::data::DataMap;
 use petgraph::Direction;
 
-use crate::linearize::{self as prev, Atom, Cmp, Statement, Test};
+use crate::linearize::{self as prev, Atom, Cmp, Component, Context, Diagnostic, Directive, Fragment, Function, Handler, Statement, Test};
+use crate::runtime::{group_failures, RANDOM_SEQUENCE, REPORTED};
+use crate::utility::{escape, Quoting};
 use crate::select_instructions::Instruction::Tellraw;
 use crate::var::{Var, VarFactory};
 
@@ -14,6 +16,9 @@ pub type Index = petgraph::graph::NodeIndex<u32>;
 pub struct Program {
     pub blocks: Graph,
     pub tests: Vec<Test>,
+    pub functions: Vec<Function>,
+    pub handlers: Vec<Handler>,
+    pub teardown: Option<usize>,
 }
 
 #[derive(Debug, Clone)]
@@ -33,19 +38,33 @@ pub enum Jmp {
         value: i64,
         block: Index,
     },
-    ExecuteIfScoreEqualsFunction {
+    ExecuteIfScoreCompareFunction {
+        cmp: Cmp,
         a: Var,
         b: Var,
         block: Index,
     },
-    ExecuteUnlessScoreEqualsFunction {
+    ExecuteUnlessScoreCompareFunction {
+        cmp: Cmp,
         a: Var,
         b: Var,
         block: Index,
     },
     Function {
         block: Index,
-    }
+    },
+    Join {
+        live: Vec<Var>,
+        block: Index,
+    },
+    Resume {
+        live: Vec<Var>,
+        block: Index,
+    },
+    ExecuteContextFunction {
+        context: Vec<Context>,
+        block: Index,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -60,7 +79,7 @@ pub enum Instruction {
         destination: Var,
     },
     Tellraw {
-        text: String,
+        components: Vec<Component>,
     },
     Command {
         text: String,
@@ -77,18 +96,81 @@ pub enum Instruction {
         set_var: Var,
         set_value: i64,
     },
-    ExecuteIfScoreEqualsSet {
+    ExecuteIfScoreCompareSet {
+        cmp: Cmp,
         a: Var,
         b: Var,
         set_var: Var,
         set_value: i64,
     },
-    ExecuteUnlessScoreEqualsSet {
+    ExecuteUnlessScoreCompareSet {
+        cmp: Cmp,
         a: Var,
         b: Var,
         set_var: Var,
         set_value: i64,
     },
+    ExecuteStoreResultScore {
+        var: Var,
+        command: String,
+    },
+    ExecuteStoreResultGetScore {
+        var: Var,
+        target: String,
+    },
+    /// Sets up the stack frame of a function.
+    Enter,
+    LoadArg {
+        var: Var,
+        index: usize,
+    },
+    Call {
+        id: usize,
+        args: Vec<Var>,
+        result: Var,
+    },
+    CallIndirect {
+        function: Var,
+        args: Vec<Var>,
+        result: Var,
+    },
+    Return {
+        var: Var,
+    },
+    TailCall {
+        id: usize,
+        args: Vec<Var>,
+    },
+    TailCallIndirect {
+        function: Var,
+        args: Vec<Var>,
+    },
+    Disarm {
+        var: Var,
+    },
+    /// Copies the score `target` holds in `objective` into `var`.
+    GetScore {
+        var: Var,
+        target: String,
+        objective: String,
+    },
+    /// Copies `var` into the score `target` holds in `objective`.
+    SetScore {
+        target: String,
+        objective: String,
+        var: Var,
+    },
+    Schedule {
+        block: Index,
+        ticks: i64,
+    },
+    EarlyReturn {
+        var: Option<Var>,
+    },
+    Asm {
+        template: Vec<Fragment>,
+        vars: Vec<Var>,
+    },
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -130,12 +212,17 @@ pub fn select_instructions(mut program: prev::Program) -> Program {
     Program {
         blocks,
         tests: program.tests,
+        functions: program.functions,
+        handlers: program.handlers,
+        teardown: program.teardown,
     }
 }
 
 fn select_instructions_jmp(jmp: &prev::Jmp, block: Index) -> Option<Jmp> {
     match jmp {
-        prev::Jmp::Unconditional => Some(Jmp::Function { block }),
+        prev::Jmp::Join { live } => Some(Jmp::Join { live: live.clone(), block }),
+        prev::Jmp::Context(context) => Some(Jmp::ExecuteContextFunction { context: context.clone(), block }),
+        prev::Jmp::Resume { live } => Some(Jmp::Resume { live: live.clone(), block }),
         prev::Jmp::If(prev::Condition::Atm(Atom::LitBool(b))) => if *b { Some(Jmp::Function { block }) } else { None },
         prev::Jmp::If(prev::Condition::Atm(Atom::Var(var))) => Some(Jmp::ExecuteIfScoreMatchesFunction {
             var: var.clone(),
@@ -158,8 +245,9 @@ fn select_instructions_jmp(jmp: &prev::Jmp, block: Index) -> Option<Jmp> {
                 value: *i,
                 block,
             }),
-        prev::Jmp::If(prev::Condition::Cmp { cmp: Cmp::Eq, left: Atom::Var(left), right: Atom::Var(right) }) =>
-            Some(Jmp::ExecuteIfScoreEqualsFunction {
+        prev::Jmp::If(prev::Condition::Cmp { cmp, left: Atom::Var(left), right: Atom::Var(right) }) =>
+            Some(Jmp::ExecuteIfScoreCompareFunction {
+                cmp: *cmp,
                 a: left.clone(),
                 b: right.clone(),
                 block,
@@ -187,8 +275,9 @@ fn select_instructions_jmp(jmp: &prev::Jmp, block: Index) -> Option<Jmp> {
                 value: *i,
                 block,
             }),
-        prev::Jmp::Unless(prev::Condition::Cmp { cmp: Cmp::Eq, left: Atom::Var(left), right: Atom::Var(right) }) =>
-            Some(Jmp::ExecuteUnlessScoreEqualsFunction {
+        prev::Jmp::Unless(prev::Condition::Cmp { cmp, left: Atom::Var(left), right: Atom::Var(right) }) =>
+            Some(Jmp::ExecuteUnlessScoreCompareFunction {
+                cmp: *cmp,
                 a: left.clone(),
                 b: right.clone(),
                 block,
@@ -207,13 +296,102 @@ fn select_instructions_block(block: prev::Block, var_factory: &mut VarFactory) -
     Block { instrs }
 }
 
+fn tell(text: String) -> Instruction {
+    Tellraw {
+        components: vec![Component::Text(text)],
+    }
+}
+
+fn reported() -> Instruction {
+    Instruction::Command {
+        text: format!("scoreboard players set {REPORTED} 1"),
+    }
+}
+
+/// Lines of a test inside describes are indented once for each, as TAP subtests are.
+fn indent(groups: &[usize]) -> String {
+    "    ".repeat(groups.len())
+}
+
+fn directive_suffix(directive: Option<Directive>) -> String {
+    directive.map(|directive| format!(" # {directive}")).unwrap_or_default()
+}
+
+/// Reports a failed assertion as the TAP YAML block that follows its `not ok` line. The sides
+/// of a comparison are read from their scores when the block is shown.
+fn tell_diagnostic(indent: &str, diagnostic: Diagnostic) -> Vec<Instruction> {
+    let quoted = |s: &str| format!("\"{}\"", escape(s, Quoting::Json));
+    let mut instrs = vec![
+        tell(format!("{indent}  ---")),
+        tell(format!("{indent}  message: {}", quoted(&diagnostic.message))),
+    ];
+    if let Some(origin) = diagnostic.origin {
+        instrs.push(tell(format!("{indent}  line: {}", origin.line)));
+        instrs.push(tell(format!("{indent}  source: {}", quoted(&origin.source))));
+    }
+    for (key, value) in [("found", diagnostic.found), ("wanted", diagnostic.wanted)] {
+        if value.is_empty() {
+            continue;
+        }
+        // Text next to text is merged, so that a line of only text stays a plain string.
+        let mut components = vec![Component::Text(format!("{indent}  {key}: "))];
+        for component in value {
+            match (components.last_mut(), component) {
+                (Some(Component::Text(line)), Component::Text(text)) => line.push_str(&text),
+                (_, component) => components.push(component),
+            }
+        }
+        instrs.push(Tellraw { components });
+    }
+    instrs.push(tell(format!("{indent}  ...")));
+    instrs
+}
+
 fn select_instructions_stmt(
     stmt: prev::Statement,
     var_factory: &mut VarFactory,
 ) -> Vec<Instruction> {
     match stmt {
-        prev::Statement::TellOk { test_name } => vec![Tellraw { text: format!("ok - {test_name}") }],
-        prev::Statement::TellNotOk { test_name } => vec![Tellraw { text: format!("not ok - {test_name}") }],
+        prev::Statement::TellOk {
+            test_name,
+            groups,
+            directive,
+        } => vec![
+            reported(),
+            tell(format!(
+                "{}ok - {test_name}{}",
+                indent(&groups),
+                directive_suffix(directive)
+            )),
+        ],
+        prev::Statement::TellNotOk {
+            test_name,
+            groups,
+            directive,
+            diagnostic,
+        } => {
+            let indent = indent(&groups);
+            // A test that is still to do is expected to fail, so its describes still pass.
+            let mut instrs = vec![reported()];
+            if directive != Some(Directive::Todo) {
+                instrs.extend(groups.iter().map(|&id| Instruction::Command {
+                    text: format!("scoreboard players add {} 1", group_failures(id)),
+                }));
+            }
+            // A message written with the assertion also describes the failure on this line.
+            let message = diagnostic
+                .origin
+                .as_ref()
+                .and_then(|origin| origin.message.as_ref())
+                .map(|message| format!(" - {message}"))
+                .unwrap_or_default();
+            instrs.push(tell(format!(
+                "{indent}not ok - {test_name}{message}{}",
+                directive_suffix(directive)
+            )));
+            instrs.extend(tell_diagnostic(&indent, diagnostic));
+            instrs
+        }
         prev::Statement::Assign {
             var,
             expr: prev::Expr::Atom(prev::Atom::LitUnit),
@@ -237,15 +415,15 @@ fn select_instructions_stmt(
             source,
             destination,
         }],
-        prev::Statement::Assign { var, expr: prev::Expr::Cmp { cmp: Cmp::Eq, left, right } } =>
+        prev::Statement::Assign { var, expr: prev::Expr::Cmp { cmp, left, right } } =>
             match (left, right) {
-                (Atom::LitInt(l), Atom::LitInt(r)) => vec![
+                (Atom::LitInt(l), Atom::LitInt(r)) if cmp == Cmp::Eq => vec![
                     Instruction::Set { var, value: if l == r { 1 } else { 0 } }
                 ],
-                (Atom::LitBool(l), Atom::LitBool(r)) => vec![
+                (Atom::LitBool(l), Atom::LitBool(r)) if cmp == Cmp::Eq => vec![
                     Instruction::Set { var, value: if l == r { 1 } else { 0 }}
                 ],
-                (Atom::Var(v), Atom::LitInt(i)) | (Atom::LitInt(i), Atom::Var(v)) => vec![
+                (Atom::Var(v), Atom::LitInt(i)) | (Atom::LitInt(i), Atom::Var(v)) if cmp == Cmp::Eq => vec![
                     Instruction::ExecuteIfScoreMatchesSet {
                         var: v.clone(),
                         value: i,
@@ -259,7 +437,7 @@ fn select_instructions_stmt(
                         set_value: 0,
                     }
                 ],
-                (Atom::Var(v), Atom::LitBool(b)) | (Atom::LitBool(b), Atom::Var(v)) => vec![
+                (Atom::Var(v), Atom::LitBool(b)) | (Atom::LitBool(b), Atom::Var(v)) if cmp == Cmp::Eq => vec![
                     Instruction::ExecuteIfScoreMatchesSet {
                         var: v.clone(),
                         value: if b { 1 } else { 0 },
@@ -274,13 +452,15 @@ fn select_instructions_stmt(
                     }
                 ],
                 (Atom::Var(left), Atom::Var(right)) => vec![
-                    Instruction::ExecuteIfScoreEqualsSet {
+                    Instruction::ExecuteIfScoreCompareSet {
+                        cmp,
                         a: left.clone(),
                         b: right.clone(),
                         set_var: var.clone(),
                         set_value: 1,
                     },
-                    Instruction::ExecuteUnlessScoreEqualsSet {
+                    Instruction::ExecuteUnlessScoreCompareSet {
+                        cmp,
                         a: left,
                         b: right,
                         set_var: var,
@@ -351,7 +531,389 @@ fn select_instructions_stmt(
             instrs
         }
         prev::Statement::Command { text } => vec![Instruction::Command { text }],
+        prev::Statement::Assign {
+            var,
+            expr: prev::Expr::Array { elements },
+        } => {
+            let values: Vec<String> = elements.iter().map(nbt_template).collect();
+            let mut instrs = vec![
+                Instruction::ExecuteStoreResultScore {
+                    var,
+                    command: "data get storage mctest:heap arrays".to_owned(),
+                },
+                Instruction::Command {
+                    text: format!(
+                        "data modify storage mctest:heap arrays append value [{}]",
+                        values.join(",")
+                    ),
+                },
+            ];
+            for (i, element) in elements.into_iter().enumerate() {
+                instrs.extend(store_element_vars(
+                    &format!("storage mctest:heap arrays[-1][{i}]"),
+                    element,
+                ));
+            }
+            instrs
+        }
+        prev::Statement::Assign {
+            var,
+            expr:
+                prev::Expr::ArrayGet {
+                    array,
+                    index,
+                    field: None,
+                },
+        } => vec![
+            store_arg("array", array),
+            store_arg("index", index),
+            call_with_args("array_get"),
+            load_ret(var),
+        ],
+        prev::Statement::Assign {
+            var,
+            expr:
+                prev::Expr::ArrayGet {
+                    array,
+                    index,
+                    field: Some(field),
+                },
+        } => vec![
+            store_arg("array", array),
+            store_arg("index", index),
+            store_field_arg(&field),
+            call_with_args("array_get_field"),
+            load_ret(var),
+        ],
+        prev::Statement::Assign {
+            var,
+            expr: prev::Expr::Score { target, objective },
+        } => vec![Instruction::GetScore { var, target, objective }],
+        prev::Statement::SetScore {
+            target,
+            objective,
+            value: Atom::Var(var),
+        } => vec![Instruction::SetScore { target, objective, var }],
+        prev::Statement::Assign {
+            var,
+            expr:
+                prev::Expr::Random {
+                    lo: Atom::LitInt(lo),
+                    hi: Atom::LitInt(hi),
+                },
+        } => vec![Instruction::ExecuteStoreResultScore {
+            var,
+            command: format!("random value {lo}..{hi} {RANDOM_SEQUENCE}"),
+        }],
+        prev::Statement::Assign {
+            var,
+            expr: prev::Expr::Random { lo, hi },
+        } => vec![
+            store_arg("lo", lo),
+            store_arg("hi", hi),
+            call_with_args("random"),
+            load_ret(var),
+        ],
+        prev::Statement::Assign {
+            var,
+            expr: prev::Expr::NbtGet { source, path, scale },
+        } => vec![Instruction::ExecuteStoreResultScore {
+            var,
+            command: format!("data get {source} {path} {scale}"),
+        }],
+        prev::Statement::NbtSet {
+            source,
+            path,
+            ty,
+            scale,
+            value,
+        } => {
+            let mut instrs = Vec::new();
+            let var = atom_var(var_factory, &mut instrs, value);
+            instrs.push(Instruction::ExecuteStoreResultGetScore {
+                var,
+                target: format!("{source} {path} {ty} {scale}"),
+            });
+            instrs
+        }
+        prev::Statement::SetScore { target, objective, value } => vec![Instruction::Command {
+            text: format!("scoreboard players set {target} {objective} {}", nbt_value(&value)),
+        }],
+        prev::Statement::Assign {
+            var,
+            expr: prev::Expr::Length { array },
+        } => vec![
+            store_arg("array", array),
+            call_with_args("array_length"),
+            load_ret(var),
+        ],
+        prev::Statement::Assign {
+            var,
+            expr: prev::Expr::ArrayEq { left, right },
+        } => vec![
+            store_arg("left", left),
+            store_arg("right", right),
+            call_with_args("array_eq"),
+            load_ret(var),
+        ],
+        prev::Statement::ArraySet {
+            array,
+            index,
+            field: None,
+            value,
+        } => vec![
+            store_arg("array", array),
+            store_arg("index", index),
+            store_arg("value", value),
+            call_with_args("array_set"),
+        ],
+        prev::Statement::ArraySet {
+            array,
+            index,
+            field: Some(field),
+            value,
+        } => vec![
+            store_arg("array", array),
+            store_arg("index", index),
+            store_field_arg(&field),
+            store_arg("value", value),
+            call_with_args("array_set_field"),
+        ],
+        prev::Statement::Enter { params } => {
+            let mut instrs = vec![Instruction::Enter];
+            for (index, var) in params.into_iter().enumerate() {
+                instrs.push(Instruction::LoadArg { var, index });
+            }
+            instrs
+        }
+        prev::Statement::Return { value } => {
+            let mut instrs = Vec::new();
+            let var = atom_var(var_factory, &mut instrs, value);
+            instrs.push(Instruction::Return { var });
+            instrs
+        }
+        prev::Statement::Assign {
+            var,
+            expr: prev::Expr::Call { id, args },
+        } => {
+            let mut instrs = Vec::new();
+            let args = args
+                .into_iter()
+                .map(|arg| atom_var(var_factory, &mut instrs, arg))
+                .collect();
+            instrs.push(Instruction::Call {
+                id,
+                args,
+                result: var,
+            });
+            instrs
+        }
+        prev::Statement::Assign {
+            var,
+            expr: prev::Expr::CallIndirect { function, args },
+        } => {
+            let mut instrs = Vec::new();
+            let function = atom_var(var_factory, &mut instrs, function);
+            let args = args
+                .into_iter()
+                .map(|arg| atom_var(var_factory, &mut instrs, arg))
+                .collect();
+            instrs.push(Instruction::CallIndirect {
+                function,
+                args,
+                result: var,
+            });
+            instrs
+        }
+        prev::Statement::TailCall {
+            call: prev::Expr::Call { id, args },
+        } => {
+            let mut instrs = Vec::new();
+            let args = args
+                .into_iter()
+                .map(|arg| atom_var(var_factory, &mut instrs, arg))
+                .collect();
+            instrs.push(Instruction::TailCall { id, args });
+            instrs
+        }
+        prev::Statement::TailCall {
+            call: prev::Expr::CallIndirect { function, args },
+        } => {
+            let mut instrs = Vec::new();
+            let function = atom_var(var_factory, &mut instrs, function);
+            let args = args
+                .into_iter()
+                .map(|arg| atom_var(var_factory, &mut instrs, arg))
+                .collect();
+            instrs.push(Instruction::TailCallIndirect { function, args });
+            instrs
+        }
+        prev::Statement::TailCall { .. } => panic!("Only calls can be made in tail position"),
+        prev::Statement::Disarm { var } => vec![Instruction::Disarm { var }],
+        prev::Statement::Asm { template, vars } => vec![Instruction::Asm { template, vars }],
+        prev::Statement::EarlyReturn { value } => {
+            let mut instrs = Vec::new();
+            let var = value.map(|value| atom_var(var_factory, &mut instrs, value));
+            instrs.push(Instruction::EarlyReturn { var });
+            instrs
+        }
+        // Each body has a queue of the variables it was scheduled with, as it may be scheduled
+        // again before it runs. Bodies scheduled for the same tick run in the order they were.
+        prev::Statement::Schedule { block, ticks, vars } => {
+            let queue = format!("storage mctest:heap scheduled.block{}", block.index());
+            let mut instrs = Vec::new();
+            if !vars.is_empty() {
+                instrs.push(Instruction::Command {
+                    text: format!("data modify {queue} append value {{}}"),
+                });
+            }
+            for (i, var) in vars.into_iter().enumerate() {
+                instrs.push(Instruction::ExecuteStoreResultGetScore {
+                    var,
+                    target: format!("{queue}[-1].v{i} int 1"),
+                });
+            }
+            instrs.push(Instruction::Schedule { block, ticks });
+            instrs
+        }
+        prev::Statement::Restore { vars, .. } if vars.is_empty() => vec![],
+        prev::Statement::Restore { block, vars } => {
+            let queue = format!("storage mctest:heap scheduled.block{}", block.index());
+            let mut instrs: Vec<_> = vars
+                .into_iter()
+                .enumerate()
+                .map(|(i, var)| Instruction::ExecuteStoreResultScore {
+                    var,
+                    command: format!("data get {queue}[0].v{i}"),
+                })
+                .collect();
+            instrs.push(Instruction::Command {
+                text: format!("data remove {queue}[0]"),
+            });
+            instrs
+        }
+        prev::Statement::ArrayPush {
+            array,
+            value: prev::Element::Scalar(value),
+        } => vec![
+            store_arg("array", array),
+            store_arg("value", value),
+            call_with_args("array_push"),
+        ],
+        prev::Statement::ArrayPush { array, value } => {
+            let mut instrs = vec![
+                store_arg("array", array),
+                Instruction::Command {
+                    text: format!(
+                        "data modify storage mctest:heap args.value set value {}",
+                        nbt_template(&value)
+                    ),
+                },
+            ];
+            instrs.extend(store_element_vars("storage mctest:heap args.value", value));
+            instrs.push(call_with_args("array_push"));
+            instrs
+        }
+    }
+}
+
+/// Calls pass their arguments through registers, so literals are first given a variable of
+/// their own.
+fn atom_var(var_factory: &mut VarFactory, instrs: &mut Vec<Instruction>, atom: Atom) -> Var {
+    match atom {
+        Atom::Var(var) => var,
+        atom => {
+            let var = var_factory.tmp();
+            instrs.push(Instruction::Set {
+                var: var.clone(),
+                value: nbt_value(&atom),
+            });
+            var
+        }
+    }
+}
+
+fn nbt_value(atom: &Atom) -> i64 {
+    match atom {
+        Atom::LitInt(i) => *i,
+        Atom::LitBool(b) => if *b { 1 } else { 0 },
+        Atom::LitUnit => panic!("Type error! Tried to store unit"),
+        Atom::Var(_) => panic!("Variables have no NBT literal"),
+    }
+}
+
+/// The SNBT written for an element before its variables are stored into it. Variables start out
+/// as 0 and are filled in by [`store_element_vars`].
+fn nbt_template(element: &prev::Element) -> String {
+    let scalar = |atom: &Atom| match atom {
+        Atom::Var(_) => "0".to_owned(),
+        atom => nbt_value(atom).to_string(),
+    };
+    match element {
+        prev::Element::Scalar(atom) => scalar(atom),
+        prev::Element::Compound(fields) => format!(
+            "{{{}}}",
+            fields
+                .iter()
+                .map(|(field, atom)| format!("{field}:{}", scalar(atom)))
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+    }
+}
+
+fn store_element_vars(path: &str, element: prev::Element) -> Vec<Instruction> {
+    let fields = match element {
+        prev::Element::Scalar(atom) => vec![(path.to_owned(), atom)],
+        prev::Element::Compound(fields) => fields
+            .into_iter()
+            .map(|(field, atom)| (format!("{path}.{field}"), atom))
+            .collect(),
+    };
+    fields
+        .into_iter()
+        .filter_map(|(path, atom)| match atom {
+            Atom::Var(var) => Some(Instruction::ExecuteStoreResultGetScore {
+                var,
+                target: format!("{path} int 1"),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+fn store_field_arg(field: &str) -> Instruction {
+    Instruction::Command {
+        text: format!("data modify storage mctest:heap args.field set value \"{field}\""),
+    }
+}
+
+/// Writes `atom` into the macro arguments used by the array runtime functions.
+fn store_arg(name: &str, atom: Atom) -> Instruction {
+    match atom {
+        Atom::Var(var) => Instruction::ExecuteStoreResultGetScore {
+            var,
+            target: format!("storage mctest:heap args.{name} int 1"),
+        },
+        atom => Instruction::Command {
+            text: format!(
+                "data modify storage mctest:heap args.{name} set value {}",
+                nbt_value(&atom)
+            ),
+        },
+    }
+}
+
+fn call_with_args(function: &str) -> Instruction {
+    Instruction::Command {
+        text: format!("function mctest:{function} with storage mctest:heap args"),
+    }
+}
 
+fn load_ret(var: Var) -> Instruction {
+    Instruction::ExecuteStoreResultScore {
+        var,
+        command: "data get storage mctest:heap ret".to_owned(),
     }
 }
 
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+
+use crate::uniquify::{Definition, Expr, Program, Statement};
+
+/// Lists the calls made from inside functions that are not in tail position, and so each need a
+/// stack frame of their own. A call is in tail position when it is the result of its function,
+/// either directly or as a branch of an if that is.
+pub fn report_tail_calls(program: &Program) -> Vec<String> {
+    let names: HashMap<usize, &str> = program
+        .defs
+        .iter()
+        .filter_map(|def| match def {
+            Definition::Function { id, name, .. } => Some((*id, name.as_str())),
+            _ => None,
+        })
+        .collect();
+
+    let mut report = Vec::new();
+    for def in &program.defs {
+        let Definition::Function {
+            name, stmts, result, ..
+        } = def
+        else {
+            continue;
+        };
+        let mut calls = Vec::new();
+        for stmt in stmts {
+            stmt_calls(stmt, &mut calls);
+        }
+        expr_calls(result, true, &mut calls);
+
+        for call in calls {
+            let callee = match call {
+                Expr::Call { id, .. } => names[id].to_owned(),
+                Expr::CallIndirect { function, .. } => match function.as_ref() {
+                    Expr::Variable(var) => var.name.clone().unwrap_or_else(|| "<function>".to_owned()),
+                    _ => "<function>".to_owned(),
+                },
+                _ => unreachable!(),
+            };
+            report.push(format!("{name}: call to {callee} is not in tail position"));
+        }
+    }
+    report
+}
+
+fn stmt_calls<'a>(stmt: &'a Statement, calls: &mut Vec<&'a Expr>) {
+    match stmt {
+        Statement::Assert { expr, .. } => expr_calls(expr, false, calls),
+        Statement::AssertEq { left, right, .. } | Statement::AssertCmp { left, right, .. } => {
+            expr_calls(left, false, calls);
+            expr_calls(right, false, calls);
+        }
+        Statement::AssertBetween { expr, lo, hi, .. } => {
+            expr_calls(expr, false, calls);
+            expr_calls(lo, false, calls);
+            expr_calls(hi, false, calls);
+        }
+        Statement::Command { .. } | Statement::SetSeed { .. } | Statement::Asm { .. } => {}
+        Statement::Let { expr, .. } => expr_calls(expr, false, calls),
+        Statement::ArraySet {
+            array,
+            index,
+            value,
+        } => {
+            expr_calls(array, false, calls);
+            expr_calls(index, false, calls);
+            expr_calls(value, false, calls);
+        }
+        Statement::ArrayPush { array, value } => {
+            expr_calls(array, false, calls);
+            expr_calls(value, false, calls);
+        }
+        Statement::SetScore { value, .. } | Statement::NbtSet { value, .. } => {
+            expr_calls(value, false, calls)
+        }
+        Statement::When { cond, stmts } => {
+            expr_calls(cond, false, calls);
+            for stmt in stmts {
+                stmt_calls(stmt, calls);
+            }
+        }
+        Statement::Return { value } => {
+            if let Some(value) = value {
+                expr_calls(value, false, calls);
+            }
+        }
+        Statement::Execute { stmts, .. } | Statement::After { stmts, .. } => {
+            for stmt in stmts {
+                stmt_calls(stmt, calls);
+            }
+        }
+    }
+}
+
+/// Collects the calls in `expr` that are not tail calls.
+fn expr_calls<'a>(expr: &'a Expr, tail: bool, calls: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::LitBool(_)
+        | Expr::LitInt(_)
+        | Expr::Variable(_)
+        | Expr::Function { .. }
+        | Expr::Score { .. }
+        | Expr::NbtGet { .. } => {}
+        Expr::Plus { left, right }
+        | Expr::Minus { left, right }
+        | Expr::Times { left, right }
+        | Expr::Divide { left, right }
+        | Expr::Eq { left, right }
+        | Expr::Random {
+            lo: left,
+            hi: right,
+        } => {
+            expr_calls(left, false, calls);
+            expr_calls(right, false, calls);
+        }
+        Expr::If { cond, thn, els } => {
+            expr_calls(cond, false, calls);
+            expr_calls(thn, tail, calls);
+            expr_calls(els, tail, calls);
+        }
+        Expr::Array { elements } => {
+            for element in elements {
+                expr_calls(element, false, calls);
+            }
+        }
+        Expr::MakeArray { fill, .. } => expr_calls(fill, false, calls),
+        Expr::ArrayGet { array, index } => {
+            expr_calls(array, false, calls);
+            expr_calls(index, false, calls);
+        }
+        Expr::Length { array } => expr_calls(array, false, calls),
+        Expr::Construct { args, .. } => {
+            for arg in args {
+                expr_calls(arg, false, calls);
+            }
+        }
+        Expr::Field { expr, .. } => expr_calls(expr, false, calls),
+        Expr::Call { args, .. } => {
+            if !tail {
+                calls.push(expr);
+            }
+            for arg in args {
+                expr_calls(arg, false, calls);
+            }
+        }
+        Expr::CallIndirect { function, args } => {
+            if !tail {
+                calls.push(expr);
+            }
+            expr_calls(function, false, calls);
+            for arg in args {
+                expr_calls(arg, false, calls);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{lex::lex, parse::parse, read::read, uniquify::uniquify};
+
+    #[test]
+    fn report() -> anyhow::Result<()> {
+        let program = uniquify(parse(read(lex(
+            "(defn fact ((n int)) int (if (== n 0) 1 (* n (fact (- n 1)))))
+             (defn sum ((n int) (acc int)) int (if (== n 0) acc (sum (- n 1) (+ acc n))))",
+        )?)?)?)?;
+
+        assert_eq!(
+            report_tail_calls(&program),
+            vec!["fact: call to fact is not in tail position".to_owned()]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,1618 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use anyhow::{anyhow, Result};
+
+use crate::runtime::RANDOM_SEQUENCE;
+use crate::uniquify as prev;
+use crate::Options;
+pub use crate::uniquify::{Context, Directive, Event, Fragment, NbtSource, Origin, Relation};
+use crate::var::{Var, VarFactory};
+
+pub struct Program {
+    pub defs: Vec<Definition>,
+    pub var_factory: VarFactory,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Type {
+    Int,
+    Bool,
+    Array(Box<Type>),
+    Struct(String),
+    Function { params: Vec<Type>, ret: Box<Type> },
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Bool => write!(f, "bool"),
+            Type::Array(element) => write!(f, "(array {element})"),
+            Type::Struct(name) => write!(f, "{name}"),
+            Type::Function { params, ret } => {
+                write!(f, "(fn (")?;
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{param}")?;
+                }
+                write!(f, ") {ret})")
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum Definition {
+    Test {
+        name: String,
+        directive: Option<Directive>,
+        fails: bool,
+        budget: Option<i64>,
+        stmts: Vec<Statement>,
+    },
+    Function {
+        id: usize,
+        params: Vec<Var>,
+        stmts: Vec<Statement>,
+        result: Expr,
+    },
+    Handler { event: Event, stmts: Vec<Statement> },
+    Setup { stmts: Vec<Statement> },
+    Teardown { stmts: Vec<Statement> },
+    Describe { name: String, defs: Vec<Definition> },
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum Statement {
+    Assert { expr: Expr, origin: Origin },
+    AssertEq { left: Expr, right: Expr, origin: Origin },
+    AssertCmp { relation: Relation, left: Expr, right: Expr, origin: Origin },
+    AssertBetween { expr: Expr, lo: Expr, hi: Expr, origin: Origin },
+    Command { text: String },
+    Let { var: Var, expr: Expr },
+    ArraySet { array: Expr, index: Expr, field: Option<String>, value: Expr },
+    ArrayPush { array: Expr, value: Element },
+    Execute { context: Context, stmts: Vec<Statement> },
+    SetScore { target: String, objective: String, value: Expr },
+    NbtSet { source: NbtSource, path: String, ty: String, scale: String, value: Expr },
+    After { ticks: i64, stmts: Vec<Statement> },
+    When { cond: Expr, stmts: Vec<Statement> },
+    Return { value: Option<Expr> },
+    Asm { template: Vec<Fragment>, vars: Vec<Var> },
+}
+
+/// An array element as it is laid out in NBT. Structs are only ever stored as compounds here;
+/// everywhere else they are scalarized into one expression per field.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Element {
+    Scalar(Expr),
+    Compound(Vec<(String, Expr)>),
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Expr {
+    LitBool(bool),
+    LitInt(i64),
+    Variable(Var),
+    Plus {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Minus {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Times {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Divide {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    If {
+        cond: Box<Expr>,
+        thn: Box<Expr>,
+        els: Box<Expr>,
+    },
+    Eq {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    ArrayEq {
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+    Array {
+        elements: Vec<Element>,
+    },
+    MakeArray {
+        length: i64,
+        fill: Box<Element>,
+    },
+    ArrayGet {
+        array: Box<Expr>,
+        index: Box<Expr>,
+        field: Option<String>,
+    },
+    Length {
+        array: Box<Expr>,
+    },
+    Call {
+        id: usize,
+        args: Vec<Expr>,
+    },
+    CallIndirect {
+        function: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    Score {
+        target: String,
+        objective: String,
+    },
+    NbtGet {
+        source: NbtSource,
+        path: String,
+        scale: String,
+    },
+    Random {
+        lo: Box<Expr>,
+        hi: Box<Expr>,
+    },
+    /// Evaluates `value` into `var` and then `body`. A struct keeps what more than one of its
+    /// fields needs in temporaries bound by its first field, so that it is only evaluated once.
+    Let {
+        var: Var,
+        value: Box<Expr>,
+        body: Box<Expr>,
+    },
+}
+
+#[derive(Default)]
+struct Env {
+    types: HashMap<Var, Type>,
+    /// The variables each struct-typed variable was scalarized into, in field order.
+    fields: HashMap<Var, Vec<Var>>,
+    /// What `return` leaves, or `None` where it cannot be used.
+    returns: Option<Returns>,
+}
+
+#[derive(Clone)]
+enum Returns {
+    Test,
+    Function(Type),
+}
+
+type Structs = HashMap<String, Vec<(String, Type)>>;
+
+/// Arguments are passed in the registers a1 to a8.
+const MAX_ARGS: usize = 8;
+
+/// The first pack format with the `random` command.
+const RANDOM_PACK_FORMAT: usize = 18;
+
+/// The first pack format with function macros, which the array runtime is made of.
+const ARRAYS_PACK_FORMAT: usize = 18;
+
+struct Globals {
+    structs: Structs,
+    /// The parameter and return types of each function, by id.
+    functions: HashMap<usize, (Vec<Type>, Type)>,
+    pack_format: usize,
+}
+
+pub fn type_check(mut program: prev::Program, options: &Options) -> Result<Program> {
+    let mut defs = Vec::new();
+    let mut globals = Globals {
+        structs: Structs::new(),
+        functions: HashMap::new(),
+        pack_format: options.pack_format,
+    };
+
+    for def in &program.defs {
+        if let prev::Definition::Struct { name, fields } = def {
+            let mut new_fields: Vec<(String, Type)> = Vec::new();
+            for field in fields {
+                if new_fields.iter().any(|(name, _)| *name == field.name) {
+                    return Err(anyhow!("Struct {name} has more than one field {}", field.name));
+                }
+                new_fields.push((field.name.clone(), field_type(&field.ty)?));
+            }
+            globals.structs.insert(name.clone(), new_fields);
+        }
+    }
+
+    for def in &program.defs {
+        if let prev::Definition::Function {
+            id,
+            name,
+            params,
+            ret,
+            ..
+        } = def
+        {
+            let params: Vec<_> = params
+                .iter()
+                .map(|(_, ty)| resolve_type(&globals.structs, ty))
+                .collect::<Result<_>>()?;
+            let ret = resolve_type(&globals.structs, ret)?;
+            if let Type::Struct(_) = ret {
+                return Err(anyhow!("Functions cannot return structs, but {name} returns {ret}"));
+            }
+            if params
+                .iter()
+                .any(|ty| matches!(ty, Type::Array(_) | Type::Struct(_)))
+            {
+                require_arrays(&globals)?;
+            }
+            globals.functions.insert(*id, (params, ret));
+        }
+    }
+
+    for def in program.defs {
+        match def {
+            def @ (prev::Definition::Test { .. } | prev::Definition::Describe { .. }) => {
+                defs.push(type_check_test(&globals, &mut program.var_factory, def)?)
+            }
+            prev::Definition::Function {
+                id,
+                name,
+                params,
+                stmts,
+                result,
+                ..
+            } => {
+                let (param_tys, ret) = globals.functions[&id].clone();
+                let mut env = Env {
+                    returns: Some(Returns::Function(ret.clone())),
+                    ..Env::default()
+                };
+                if params.len() > MAX_ARGS {
+                    return Err(anyhow!("Function {name} takes more than {MAX_ARGS} arguments"));
+                }
+                let mut new_params = Vec::new();
+                let mut new_stmts = Vec::new();
+                for ((var, _), ty) in params.into_iter().zip(param_tys) {
+                    let Type::Struct(struct_name) = &ty else {
+                        new_params.extend(bind(&mut env, &globals, &mut program.var_factory, var, ty));
+                        continue;
+                    };
+                    // A struct argument arrives as a handle to the compound it was stored in,
+                    // which is unpacked into its fields before the body runs.
+                    let handle = program.var_factory.tmp();
+                    let fields = globals.structs[struct_name].clone();
+                    let field_vars = bind(&mut env, &globals, &mut program.var_factory, var, ty);
+                    for ((field, _), field_var) in fields.into_iter().zip(field_vars) {
+                        new_stmts.push(Statement::Let {
+                            var: field_var,
+                            expr: Expr::ArrayGet {
+                                array: Box::new(Expr::Variable(handle.clone())),
+                                index: Box::new(Expr::LitInt(0)),
+                                field: Some(field),
+                            },
+                        });
+                    }
+                    new_params.push(handle);
+                }
+
+                for stmt in stmts {
+                    if stmt.is_assertion() {
+                        return Err(anyhow!("Function {name} uses an assertion outside of a test"));
+                    }
+                    new_stmts.extend(type_check_stmt(
+                        &mut env,
+                        &globals,
+                        &mut program.var_factory,
+                        stmt,
+                    )?);
+                }
+                let (result, result_ty) = type_check_expr(&env, &globals, &mut program.var_factory, result)?;
+                require(&ret, &result_ty)?;
+
+                defs.push(Definition::Function {
+                    id,
+                    params: new_params,
+                    stmts: new_stmts,
+                    result,
+                });
+            }
+            prev::Definition::Handler { event, stmts } => {
+                let mut env = Env::default();
+                let mut new_stmts = Vec::new();
+                for stmt in stmts {
+                    if stmt.is_assertion() {
+                        return Err(anyhow!("The on-{event} handler uses an assertion outside of a test"));
+                    }
+                    new_stmts.extend(type_check_stmt(
+                        &mut env,
+                        &globals,
+                        &mut program.var_factory,
+                        stmt,
+                    )?);
+                }
+                defs.push(Definition::Handler {
+                    event,
+                    stmts: new_stmts,
+                });
+            }
+            prev::Definition::Setup { stmts } => defs.push(Definition::Setup {
+                stmts: type_check_fixture("setup", &globals, &mut program.var_factory, stmts)?,
+            }),
+            prev::Definition::Teardown { stmts } => defs.push(Definition::Teardown {
+                stmts: type_check_fixture("teardown", &globals, &mut program.var_factory, stmts)?,
+            }),
+            prev::Definition::Struct { .. } => {}
+        }
+    }
+
+    Ok(Program {
+        defs,
+        var_factory: program.var_factory,
+    })
+}
+
+/// Checks a test, or each of the tests in a describe.
+fn type_check_test(
+    globals: &Globals,
+    var_factory: &mut VarFactory,
+    def: prev::Definition,
+) -> Result<Definition> {
+    match def {
+        prev::Definition::Test {
+            name,
+            directive,
+            fails,
+            budget,
+            stmts,
+        } => {
+            let mut env = Env {
+                returns: Some(Returns::Test),
+                ..Env::default()
+            };
+            let mut new_stmts = Vec::new();
+            for stmt in stmts {
+                new_stmts.extend(type_check_stmt(&mut env, globals, var_factory, stmt)?);
+            }
+            Ok(Definition::Test {
+                name,
+                directive,
+                fails,
+                budget,
+                stmts: new_stmts,
+            })
+        }
+        prev::Definition::Describe { name, defs } => Ok(Definition::Describe {
+            name,
+            defs: defs
+                .into_iter()
+                .map(|def| type_check_test(globals, var_factory, def))
+                .collect::<Result<_>>()?,
+        }),
+        _ => unreachable!("describes only hold tests"),
+    }
+}
+
+/// Checks the body of a setup or teardown. Both run around every test, but are not part of any
+/// one of them, so they can neither assert nor return.
+fn type_check_fixture(
+    kind: &str,
+    globals: &Globals,
+    var_factory: &mut VarFactory,
+    stmts: Vec<prev::Statement>,
+) -> Result<Vec<Statement>> {
+    let mut env = Env::default();
+    let mut new_stmts = Vec::new();
+    for stmt in stmts {
+        if stmt.is_assertion() {
+            return Err(anyhow!("The {kind} uses an assertion outside of a test"));
+        }
+        new_stmts.extend(type_check_stmt(&mut env, globals, var_factory, stmt)?);
+    }
+    Ok(new_stmts)
+}
+
+fn type_check_stmt(
+    env: &mut Env,
+    globals: &Globals,
+    var_factory: &mut VarFactory,
+    stmt: prev::Statement,
+) -> Result<Vec<Statement>> {
+    match stmt {
+        prev::Statement::SetSeed { seed } => {
+            require_random(globals)?;
+            Ok(vec![Statement::Command {
+                text: format!("random reset {RANDOM_SEQUENCE} {seed} false false"),
+            }])
+        }
+        prev::Statement::Assert { expr, origin } => {
+            let (expr, ty) = type_check_expr(env, globals, var_factory, expr)?;
+            require(&Type::Bool, &ty)?;
+            Ok(vec![Statement::Assert { expr, origin }])
+        }
+        prev::Statement::AssertEq {
+            left,
+            right,
+            origin,
+        } => {
+            let (left, left_ty) = type_check_value(env, globals, var_factory, left)?;
+            let (right, right_ty) = type_check_value(env, globals, var_factory, right)?;
+            require(&left_ty, &right_ty)?;
+            // Arrays and structs have no score-level equality, so the assertion is rewritten to
+            // assert the result of an element-wise or field-wise comparison instead.
+            match left_ty {
+                Type::Array(_) | Type::Struct(_) => Ok(vec![Statement::Assert {
+                    expr: equal(&left_ty, left, right),
+                    origin,
+                }]),
+                _ => Ok(vec![Statement::AssertEq {
+                    left: single(left),
+                    right: single(right),
+                    origin,
+                }]),
+            }
+        }
+        prev::Statement::AssertCmp {
+            relation: Relation::NotEqual,
+            left,
+            right,
+            origin,
+        } => {
+            let (left, left_ty) = type_check_value(env, globals, var_factory, left)?;
+            let (right, right_ty) = type_check_value(env, globals, var_factory, right)?;
+            require(&left_ty, &right_ty)?;
+            // As with asserteq, arrays and structs are compared in an expression instead.
+            match left_ty {
+                Type::Array(_) | Type::Struct(_) => Ok(vec![Statement::Assert {
+                    expr: Expr::If {
+                        cond: Box::new(equal(&left_ty, left, right)),
+                        thn: Box::new(Expr::LitBool(false)),
+                        els: Box::new(Expr::LitBool(true)),
+                    },
+                    origin,
+                }]),
+                _ => Ok(vec![Statement::AssertCmp {
+                    relation: Relation::NotEqual,
+                    left: single(left),
+                    right: single(right),
+                    origin,
+                }]),
+            }
+        }
+        prev::Statement::AssertCmp {
+            relation,
+            left,
+            right,
+            origin,
+        } => {
+            let (left, left_ty) = type_check_expr(env, globals, var_factory, left)?;
+            require(&Type::Int, &left_ty)?;
+            let (right, right_ty) = type_check_expr(env, globals, var_factory, right)?;
+            require(&Type::Int, &right_ty)?;
+            Ok(vec![Statement::AssertCmp {
+                relation,
+                left,
+                right,
+                origin,
+            }])
+        }
+        prev::Statement::AssertBetween { expr, lo, hi, origin } => {
+            let (expr, ty) = type_check_expr(env, globals, var_factory, expr)?;
+            require(&Type::Int, &ty)?;
+            let (lo, lo_ty) = type_check_expr(env, globals, var_factory, lo)?;
+            require(&Type::Int, &lo_ty)?;
+            let (hi, hi_ty) = type_check_expr(env, globals, var_factory, hi)?;
+            require(&Type::Int, &hi_ty)?;
+            Ok(vec![Statement::AssertBetween { expr, lo, hi, origin }])
+        }
+        prev::Statement::Command { text } => Ok(vec![Statement::Command { text }]),
+        prev::Statement::Let { var, expr } => {
+            let (values, ty) = type_check_value(env, globals, var_factory, expr)?;
+            let Some(old_ty) = env.types.get(&var) else {
+                return Ok(bind(env, globals, var_factory, var, ty)
+                    .into_iter()
+                    .zip(values)
+                    .map(|(var, expr)| Statement::Let { var, expr })
+                    .collect());
+            };
+            // A let inside a context block writes to the variable it rebinds, which keeps its
+            // type. The new fields of a struct can read the old ones, so they are all computed
+            // before any is written.
+            require(old_ty, &ty)?;
+            let Some(fields) = env.fields.get(&var).cloned() else {
+                return Ok(vec![Statement::Let {
+                    var,
+                    expr: single(values),
+                }]);
+            };
+            let temps: Vec<Var> = fields.iter().map(|_| var_factory.tmp()).collect();
+            Ok(temps
+                .iter()
+                .cloned()
+                .zip(values)
+                .chain(fields.into_iter().zip(temps.iter().cloned().map(Expr::Variable)))
+                .map(|(var, expr)| Statement::Let { var, expr })
+                .collect())
+        }
+        prev::Statement::ArraySet {
+            array,
+            index,
+            value,
+        } => {
+            let (array, array_ty) = type_check_expr(env, globals, var_factory, array)?;
+            let element_ty = element_type(&array_ty)?;
+            let (index, index_ty) = type_check_expr(env, globals, var_factory, index)?;
+            require(&Type::Int, &index_ty)?;
+            let (values, value_ty) = type_check_value(env, globals, var_factory, value)?;
+            require(&element_ty, &value_ty)?;
+            if let Type::Struct(name) = &element_ty {
+                // Each field is set on its own, so the array and index are evaluated only once
+                // beforehand.
+                let mut stmts = Vec::new();
+                let mut once = |expr: Expr| {
+                    if is_pure(&expr) {
+                        return expr;
+                    }
+                    let var = var_factory.tmp();
+                    stmts.push(Statement::Let {
+                        var: var.clone(),
+                        expr,
+                    });
+                    Expr::Variable(var)
+                };
+                let (array, index) = (once(array), once(index));
+                stmts.extend(globals.structs[name].iter().zip(values).map(|((field, _), value)| {
+                    Statement::ArraySet {
+                        array: array.clone(),
+                        index: index.clone(),
+                        field: Some(field.clone()),
+                        value,
+                    }
+                }));
+                Ok(stmts)
+            } else {
+                Ok(vec![Statement::ArraySet {
+                    array,
+                    index,
+                    field: None,
+                    value: single(values),
+                }])
+            }
+        }
+        prev::Statement::ArrayPush { array, value } => {
+            let (array, array_ty) = type_check_expr(env, globals, var_factory, array)?;
+            let element_ty = element_type(&array_ty)?;
+            let (values, value_ty) = type_check_value(env, globals, var_factory, value)?;
+            require(&element_ty, &value_ty)?;
+            Ok(vec![Statement::ArrayPush {
+                array,
+                value: element(&globals.structs, &value_ty, values),
+            }])
+        }
+        prev::Statement::SetScore {
+            target,
+            objective,
+            value,
+        } => {
+            validate_objective(&objective)?;
+            let (value, value_ty) = type_check_expr(env, globals, var_factory, value)?;
+            require(&Type::Int, &value_ty)?;
+            Ok(vec![Statement::SetScore {
+                target,
+                objective,
+                value,
+            }])
+        }
+        prev::Statement::NbtSet {
+            source,
+            path,
+            ty,
+            scale,
+            value,
+        } => {
+            let (value, value_ty) = type_check_expr(env, globals, var_factory, value)?;
+            require(&Type::Int, &value_ty)?;
+            Ok(vec![Statement::NbtSet {
+                source,
+                path,
+                ty,
+                scale,
+                value,
+            }])
+        }
+        prev::Statement::When { cond, stmts } => {
+            let (cond, cond_ty) = type_check_expr(env, globals, var_factory, cond)?;
+            require(&Type::Bool, &cond_ty)?;
+            let mut new_stmts = Vec::new();
+            for stmt in stmts {
+                if stmt.is_assertion() {
+                    return Err(anyhow!("Assertions cannot be made inside (when ...)"));
+                }
+                new_stmts.extend(type_check_stmt(env, globals, var_factory, stmt)?);
+            }
+            Ok(vec![Statement::When {
+                cond,
+                stmts: new_stmts,
+            }])
+        }
+        prev::Statement::Asm { template, vars } => {
+            for var in &vars {
+                match &env.types[var] {
+                    Type::Int | Type::Bool => {}
+                    ty => return Err(anyhow!("asm can only use int and bool variables, not {ty}")),
+                }
+            }
+            Ok(vec![Statement::Asm { template, vars }])
+        }
+        prev::Statement::Return { value } => match (env.returns.clone(), value) {
+            (Some(Returns::Test), None) => Ok(vec![Statement::Return { value: None }]),
+            (Some(Returns::Test), Some(_)) => Err(anyhow!("Tests cannot return a value")),
+            (Some(Returns::Function(ret)), Some(value)) => {
+                let (value, value_ty) = type_check_expr(env, globals, var_factory, value)?;
+                require(&ret, &value_ty)?;
+                Ok(vec![Statement::Return { value: Some(value) }])
+            }
+            (Some(Returns::Function(ret)), None) => {
+                Err(anyhow!("Expected a value of type {ret} to return"))
+            }
+            (None, _) => Err(anyhow!(
+                "return can only be used in tests and functions, outside of context and after blocks"
+            )),
+        },
+        prev::Statement::Execute { context, stmts } => {
+            let returns = env.returns.take();
+            let mut new_stmts = Vec::new();
+            for stmt in stmts {
+                // The block runs once for every entity it is executed as, if any.
+                if stmt.is_assertion() {
+                    return Err(anyhow!("Assertions cannot be made inside ({context} ...)"));
+                }
+                new_stmts.extend(type_check_stmt(env, globals, var_factory, stmt)?);
+            }
+            env.returns = returns;
+            Ok(vec![Statement::Execute {
+                context,
+                stmts: new_stmts,
+            }])
+        }
+        prev::Statement::After { ticks, stmts } => {
+            let returns = env.returns.take();
+            let mut new_stmts = Vec::new();
+            for stmt in stmts {
+                // By the time the body runs, the test has already reported its result.
+                if stmt.is_assertion() {
+                    return Err(anyhow!("Assertions cannot be made inside (after {ticks} ...)"));
+                }
+                new_stmts.extend(type_check_stmt(env, globals, var_factory, stmt)?);
+            }
+            env.returns = returns;
+            Ok(vec![Statement::After {
+                ticks,
+                stmts: new_stmts,
+            }])
+        }
+    }
+}
+
+/// Brings `var` into scope, returning the variables that hold its value: one per field for
+/// structs, and `var` itself otherwise.
+fn bind(
+    env: &mut Env,
+    globals: &Globals,
+    var_factory: &mut VarFactory,
+    var: Var,
+    ty: Type,
+) -> Vec<Var> {
+    let vars = if let Type::Struct(name) = &ty {
+        let field_vars: Vec<Var> = globals.structs[name]
+            .iter()
+            .map(|(field, _)| {
+                var_factory.named(format!(
+                    "{}.{}",
+                    var.name.clone().unwrap_or_default(),
+                    field
+                ))
+            })
+            .collect();
+        env.fields.insert(var.clone(), field_vars.clone());
+        field_vars
+    } else {
+        vec![var.clone()]
+    };
+    env.types.insert(var, ty);
+    vars
+}
+
+/// Checks an expression that must not be a struct, since it has to fit in a single score.
+fn type_check_expr(
+    env: &Env,
+    globals: &Globals,
+    var_factory: &mut VarFactory,
+    expr: prev::Expr,
+) -> Result<(Expr, Type)> {
+    let (values, ty) = type_check_value(env, globals, var_factory, expr)?;
+    if let Type::Struct(_) = ty {
+        return Err(anyhow!("Type error: expected a scalar or array but found {ty}"));
+    }
+    Ok((single(values), ty))
+}
+
+/// Checks an expression of any type. Structs are returned as one expression per field and
+/// everything else as a single expression.
+fn type_check_value(
+    env: &Env,
+    globals: &Globals,
+    var_factory: &mut VarFactory,
+    expr: prev::Expr,
+) -> Result<(Vec<Expr>, Type)> {
+    match expr {
+        prev::Expr::LitBool(b) => Ok((vec![Expr::LitBool(b)], Type::Bool)),
+        prev::Expr::LitInt(i) => Ok((vec![Expr::LitInt(i)], Type::Int)),
+        prev::Expr::Variable(var) => {
+            let ty = env.types[&var].clone();
+            match env.fields.get(&var) {
+                Some(fields) => Ok((fields.iter().cloned().map(Expr::Variable).collect(), ty)),
+                None => Ok((vec![Expr::Variable(var)], ty)),
+            }
+        }
+        prev::Expr::Plus { left, right } => {
+            let (left, right) = type_check_arithmetic(env, globals, var_factory, *left, *right)?;
+            Ok((vec![Expr::Plus { left, right }], Type::Int))
+        }
+        prev::Expr::Minus { left, right } => {
+            let (left, right) = type_check_arithmetic(env, globals, var_factory, *left, *right)?;
+            Ok((vec![Expr::Minus { left, right }], Type::Int))
+        }
+        prev::Expr::Times { left, right } => {
+            let (left, right) = type_check_arithmetic(env, globals, var_factory, *left, *right)?;
+            Ok((vec![Expr::Times { left, right }], Type::Int))
+        }
+        prev::Expr::Divide { left, right } => {
+            let (left, right) = type_check_arithmetic(env, globals, var_factory, *left, *right)?;
+            Ok((vec![Expr::Divide { left, right }], Type::Int))
+        }
+        prev::Expr::If { cond, thn, els } => {
+            let (cond, cond_ty) = type_check_expr(env, globals, var_factory, *cond)?;
+            require(&Type::Bool, &cond_ty)?;
+            let (thn, thn_ty) = type_check_value(env, globals, var_factory, *thn)?;
+            let (els, els_ty) = type_check_value(env, globals, var_factory, *els)?;
+            require(&thn_ty, &els_ty)?;
+            // A struct is chosen field by field, all on the same condition.
+            let (cond, binding) = share(var_factory, cond, thn.len());
+            let values = thn
+                .into_iter()
+                .zip(els)
+                .map(|(thn, els)| Expr::If {
+                    cond: Box::new(cond.clone()),
+                    thn: Box::new(thn),
+                    els: Box::new(els),
+                })
+                .collect();
+            Ok((bind_first(binding.into_iter().collect(), values), thn_ty))
+        }
+        prev::Expr::Eq { left, right } => {
+            let (left, left_ty) = type_check_value(env, globals, var_factory, *left)?;
+            let (right, right_ty) = type_check_value(env, globals, var_factory, *right)?;
+            require(&left_ty, &right_ty)?;
+            Ok((vec![equal(&left_ty, left, right)], Type::Bool))
+        }
+        prev::Expr::Array { elements } => {
+            require_arrays(globals)?;
+            let mut element_ty = None;
+            let mut new_elements = Vec::new();
+            for e in elements {
+                let (values, ty) = type_check_value(env, globals, var_factory, e)?;
+                require_element(&ty)?;
+                if let Some(expected) = &element_ty {
+                    require(expected, &ty)?;
+                }
+                new_elements.push(element(&globals.structs, &ty, values));
+                element_ty = Some(ty);
+            }
+            let element_ty = element_ty.ok_or_else(|| {
+                anyhow!("Type error: cannot infer the element type of an empty array")
+            })?;
+            Ok((
+                vec![Expr::Array {
+                    elements: new_elements,
+                }],
+                Type::Array(Box::new(element_ty)),
+            ))
+        }
+        prev::Expr::MakeArray { length, fill } => {
+            require_arrays(globals)?;
+            let (fill, fill_ty) = type_check_value(env, globals, var_factory, *fill)?;
+            require_element(&fill_ty)?;
+            Ok((
+                vec![Expr::MakeArray {
+                    length,
+                    fill: Box::new(element(&globals.structs, &fill_ty, fill)),
+                }],
+                Type::Array(Box::new(fill_ty)),
+            ))
+        }
+        prev::Expr::ArrayGet { array, index } => {
+            let (array, array_ty) = type_check_expr(env, globals, var_factory, *array)?;
+            let element_ty = element_type(&array_ty)?;
+            let (index, index_ty) = type_check_expr(env, globals, var_factory, *index)?;
+            require(&Type::Int, &index_ty)?;
+            let fields: Vec<_> = match &element_ty {
+                Type::Struct(name) => globals.structs[name]
+                    .iter()
+                    .map(|(field, _)| Some(field.clone()))
+                    .collect(),
+                _ => vec![None],
+            };
+            // A struct is read field by field, all from the same element.
+            let (array, array_binding) = share(var_factory, array, fields.len());
+            let (index, index_binding) = share(var_factory, index, fields.len());
+            let values = fields
+                .into_iter()
+                .map(|field| Expr::ArrayGet {
+                    array: Box::new(array.clone()),
+                    index: Box::new(index.clone()),
+                    field,
+                })
+                .collect();
+            let bindings = array_binding.into_iter().chain(index_binding).collect();
+            Ok((bind_first(bindings, values), element_ty))
+        }
+        prev::Expr::Score { target, objective } => {
+            validate_objective(&objective)?;
+            Ok((vec![Expr::Score { target, objective }], Type::Int))
+        }
+        prev::Expr::Random { lo, hi } => {
+            require_random(globals)?;
+            if let (prev::Expr::LitInt(lo), prev::Expr::LitInt(hi)) = (lo.as_ref(), hi.as_ref()) {
+                validate_range(*lo, *hi)?;
+            }
+            let (lo, hi) = type_check_arithmetic(env, globals, var_factory, *lo, *hi)?;
+            Ok((vec![Expr::Random { lo, hi }], Type::Int))
+        }
+        prev::Expr::NbtGet { source, path, scale } => {
+            Ok((vec![Expr::NbtGet { source, path, scale }], Type::Int))
+        }
+        prev::Expr::Length { array } => {
+            let (array, array_ty) = type_check_expr(env, globals, var_factory, *array)?;
+            element_type(&array_ty)?;
+            Ok((
+                vec![Expr::Length {
+                    array: Box::new(array),
+                }],
+                Type::Int,
+            ))
+        }
+        prev::Expr::Construct { name, args } => {
+            let fields = &globals.structs[&name];
+            if fields.len() != args.len() {
+                return Err(anyhow!(
+                    "make-{name} expects {} arguments but got {}",
+                    fields.len(),
+                    args.len()
+                ));
+            }
+            let mut values = Vec::new();
+            for ((_, field_ty), arg) in fields.iter().zip(args) {
+                let (arg, arg_ty) = type_check_expr(env, globals, var_factory, arg)?;
+                require(field_ty, &arg_ty)?;
+                values.push(arg);
+            }
+            Ok((values, Type::Struct(name)))
+        }
+        prev::Expr::Field { name, field, expr } => {
+            let (mut values, ty) = type_check_value(env, globals, var_factory, *expr)?;
+            require(&Type::Struct(name.clone()), &ty)?;
+            let index = globals.structs[&name]
+                .iter()
+                .position(|(f, _)| *f == field)
+                .unwrap();
+            let ty = globals.structs[&name][index].1.clone();
+            if values.iter().all(is_pure) {
+                return Ok((vec![values.swap_remove(index)], ty));
+            }
+            // The other fields are still evaluated, in order, as they may have effects of their
+            // own and may read what the first one binds.
+            let vars: Vec<Var> = values.iter().map(|_| var_factory.tmp()).collect();
+            let value = vars.iter().cloned().zip(values).rev().fold(
+                Expr::Variable(vars[index].clone()),
+                |body, (var, value)| Expr::Let {
+                    var,
+                    value: Box::new(value),
+                    body: Box::new(body),
+                },
+            );
+            Ok((vec![value], ty))
+        }
+        prev::Expr::Function { id } => {
+            let (params, ret) = globals.functions[&id].clone();
+            Ok((
+                vec![Expr::LitInt(id as i64)],
+                Type::Function {
+                    params,
+                    ret: Box::new(ret),
+                },
+            ))
+        }
+        prev::Expr::Call { id, args } => {
+            let (params, ret) = &globals.functions[&id];
+            let args = type_check_args(env, globals, var_factory, params, args)?;
+            Ok((vec![Expr::Call { id, args }], ret.clone()))
+        }
+        prev::Expr::CallIndirect { function, args } => {
+            let (function, function_ty) = type_check_expr(env, globals, var_factory, *function)?;
+            let Type::Function { params, ret } = function_ty else {
+                return Err(anyhow!("Type error: expected a function but found {function_ty}"));
+            };
+            let args = type_check_args(env, globals, var_factory, &params, args)?;
+            Ok((
+                vec![Expr::CallIndirect {
+                    function: Box::new(function),
+                    args,
+                }],
+                *ret,
+            ))
+        }
+    }
+}
+
+/// Checks the arguments of a call against the parameter types. A struct argument is stored as
+/// the one compound element of an array, and passed as its handle.
+fn type_check_args(
+    env: &Env,
+    globals: &Globals,
+    var_factory: &mut VarFactory,
+    params: &[Type],
+    args: Vec<prev::Expr>,
+) -> Result<Vec<Expr>> {
+    if params.len() != args.len() {
+        return Err(anyhow!(
+            "Expected {} arguments but got {}",
+            params.len(),
+            args.len()
+        ));
+    }
+    let mut new_args = Vec::new();
+    for (param, arg) in params.iter().zip(args) {
+        let (values, ty) = type_check_value(env, globals, var_factory, arg)?;
+        require(param, &ty)?;
+        new_args.push(match &ty {
+            Type::Struct(_) => {
+                require_arrays(globals)?;
+                Expr::Array {
+                    elements: vec![element(&globals.structs, &ty, values)],
+                }
+            }
+            _ => single(values),
+        });
+    }
+    Ok(new_args)
+}
+
+fn type_check_arithmetic(
+    env: &Env,
+    globals: &Globals,
+    var_factory: &mut VarFactory,
+    left: prev::Expr,
+    right: prev::Expr,
+) -> Result<(Box<Expr>, Box<Expr>)> {
+    let (left, left_ty) = type_check_expr(env, globals, var_factory, left)?;
+    require(&Type::Int, &left_ty)?;
+    let (right, right_ty) = type_check_expr(env, globals, var_factory, right)?;
+    require(&Type::Int, &right_ty)?;
+    Ok((Box::new(left), Box::new(right)))
+}
+
+/// Builds the comparison of two values of type `ty`. Structs are compared field by field,
+/// short-circuiting on the first field that differs.
+fn equal(ty: &Type, left: Vec<Expr>, right: Vec<Expr>) -> Expr {
+    match ty {
+        Type::Array(_) => Expr::ArrayEq {
+            left: Box::new(single(left)),
+            right: Box::new(single(right)),
+        },
+        Type::Struct(_) => left
+            .into_iter()
+            .zip(right)
+            .rev()
+            .fold(None, |rest, (left, right)| {
+                let eq = Expr::Eq {
+                    left: Box::new(left),
+                    right: Box::new(right),
+                };
+                Some(match rest {
+                    None => eq,
+                    Some(rest) => Expr::If {
+                        cond: Box::new(eq),
+                        thn: Box::new(rest),
+                        els: Box::new(Expr::LitBool(false)),
+                    },
+                })
+            })
+            .unwrap_or(Expr::LitBool(true)),
+        _ => Expr::Eq {
+            left: Box::new(single(left)),
+            right: Box::new(single(right)),
+        },
+    }
+}
+
+fn element(structs: &Structs, ty: &Type, values: Vec<Expr>) -> Element {
+    match ty {
+        Type::Struct(name) => Element::Compound(
+            structs[name]
+                .iter()
+                .map(|(field, _)| field.clone())
+                .zip(values)
+                .collect(),
+        ),
+        _ => Element::Scalar(single(values)),
+    }
+}
+
+/// Whether evaluating `expr` has no effects and gives the same value every time.
+fn is_pure(expr: &Expr) -> bool {
+    matches!(expr, Expr::LitBool(_) | Expr::LitInt(_) | Expr::Variable(_))
+}
+
+/// Puts `expr` in a temporary when it is needed by each of the `fields` of a struct. Returns
+/// what to read it from and the binding to evaluate it, if any.
+fn share(var_factory: &mut VarFactory, expr: Expr, fields: usize) -> (Expr, Option<(Var, Expr)>) {
+    if fields <= 1 || is_pure(&expr) {
+        return (expr, None);
+    }
+    let var = var_factory.tmp();
+    (Expr::Variable(var.clone()), Some((var, expr)))
+}
+
+/// Evaluates `bindings` before the first field of a struct, which is evaluated before the others.
+fn bind_first(bindings: Vec<(Var, Expr)>, mut values: Vec<Expr>) -> Vec<Expr> {
+    let first = bindings.into_iter().rev().fold(values.remove(0), |body, (var, value)| Expr::Let {
+        var,
+        value: Box::new(value),
+        body: Box::new(body),
+    });
+    values.insert(0, first);
+    values
+}
+
+fn single(mut values: Vec<Expr>) -> Expr {
+    assert_eq!(values.len(), 1, "Expected a single value");
+    values.pop().unwrap()
+}
+
+fn field_type(ty: &prev::Type) -> Result<Type> {
+    match ty {
+        prev::Type::Named(name) if name == "int" => Ok(Type::Int),
+        prev::Type::Named(name) if name == "bool" => Ok(Type::Bool),
+        _ => Err(anyhow!("Struct fields can only be int or bool")),
+    }
+}
+
+fn resolve_type(structs: &Structs, ty: &prev::Type) -> Result<Type> {
+    match ty {
+        prev::Type::Named(name) if name == "int" => Ok(Type::Int),
+        prev::Type::Named(name) if name == "bool" => Ok(Type::Bool),
+        prev::Type::Named(name) if structs.contains_key(name) => Ok(Type::Struct(name.clone())),
+        prev::Type::Named(name) => Err(anyhow!("Unknown type {name}")),
+        prev::Type::Array(element) => {
+            let element = resolve_type(structs, element)?;
+            require_element(&element)?;
+            Ok(Type::Array(Box::new(element)))
+        }
+        prev::Type::Function { params, ret } => {
+            let params = params
+                .iter()
+                .map(|param| resolve_type(structs, param))
+                .collect::<Result<_>>()?;
+            let ret = resolve_type(structs, ret)?;
+            if let Type::Struct(_) = ret {
+                return Err(anyhow!("Functions cannot return structs, but found one returning {ret}"));
+            }
+            Ok(Type::Function {
+                params,
+                ret: Box::new(ret),
+            })
+        }
+    }
+}
+
+/// Objective names may only use the characters Minecraft accepts in an unquoted string.
+fn validate_objective(objective: &str) -> Result<()> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || "_-.+".contains(c);
+    if objective.is_empty() || !objective.chars().all(valid) {
+        return Err(anyhow!("Invalid scoreboard objective name {objective:?}"));
+    }
+    Ok(())
+}
+
+fn require_random(globals: &Globals) -> Result<()> {
+    if globals.pack_format < RANDOM_PACK_FORMAT {
+        return Err(anyhow!(
+            "random needs pack format {RANDOM_PACK_FORMAT} or later, but the pack format is {}",
+            globals.pack_format
+        ));
+    }
+    Ok(())
+}
+
+fn require_arrays(globals: &Globals) -> Result<()> {
+    if globals.pack_format < ARRAYS_PACK_FORMAT {
+        return Err(anyhow!(
+            "Arrays and struct arguments need pack format {ARRAYS_PACK_FORMAT} or later, but the pack format is {}",
+            globals.pack_format
+        ));
+    }
+    Ok(())
+}
+
+/// `random value` only accepts ranges of at least two and at most 2147483646 values.
+fn validate_range(lo: i64, hi: i64) -> Result<()> {
+    if hi <= lo {
+        return Err(anyhow!("(random {lo} {hi}) needs an upper bound above its lower bound"));
+    }
+    if hi - lo >= i32::MAX as i64 - 1 {
+        return Err(anyhow!("(random {lo} {hi}) spans more values than Minecraft allows"));
+    }
+    Ok(())
+}
+
+fn require(expected: &Type, found: &Type) -> Result<()> {
+    if expected == found {
+        Ok(())
+    } else {
+        Err(anyhow!("Type error: expected {expected} but found {found}"))
+    }
+}
+
+fn require_element(ty: &Type) -> Result<()> {
+    match ty {
+        Type::Int | Type::Bool | Type::Struct(_) | Type::Function { .. } => Ok(()),
+        ty => Err(anyhow!("Type error: arrays can only hold int, bool, structs or functions, not {ty}")),
+    }
+}
+
+fn element_type(ty: &Type) -> Result<Type> {
+    match ty {
+        Type::Array(element) => Ok(*element.clone()),
+        ty => Err(anyhow!("Type error: expected an array but found {ty}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mismatch() {
+        let program = prev::Program {
+            defs: vec![prev::Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![prev::Statement::AssertEq {
+                    origin: Origin::default(),
+                    left: prev::Expr::LitInt(1),
+                    right: prev::Expr::LitBool(true),
+                }],
+            }],
+            var_factory: VarFactory::new(),
+        };
+
+        assert!(type_check(program, &Options::default()).is_err());
+    }
+
+    #[test]
+    fn array_asserteq() -> Result<()> {
+        let mut var_factory = VarFactory::new();
+        let a = var_factory.named("a".to_owned());
+
+        let program = prev::Program {
+            defs: vec![prev::Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![
+                    prev::Statement::Let {
+                        var: a.clone(),
+                        expr: prev::Expr::MakeArray {
+                            length: 2,
+                            fill: Box::new(prev::Expr::LitInt(0)),
+                        },
+                    },
+                    prev::Statement::AssertEq {
+                        origin: Origin::default(),
+                        left: prev::Expr::Variable(a.clone()),
+                        right: prev::Expr::Array {
+                            elements: vec![prev::Expr::LitInt(0), prev::Expr::LitInt(0)],
+                        },
+                    },
+                ],
+            }],
+            var_factory,
+        };
+
+        let program = type_check(program, &Options::default())?;
+        let Definition::Test { stmts, .. } = &program.defs[0] else {
+            panic!("Expected test definition");
+        };
+
+        assert_eq!(
+            stmts[1],
+            Statement::Assert {
+                origin: Origin::default(),
+                expr: Expr::ArrayEq {
+                    left: Box::new(Expr::Variable(a)),
+                    right: Box::new(Expr::Array {
+                        elements: vec![
+                            Element::Scalar(Expr::LitInt(0)),
+                            Element::Scalar(Expr::LitInt(0))
+                        ]
+                    })
+                }
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn empty_array() {
+        let program = prev::Program {
+            defs: vec![prev::Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![prev::Statement::Assert {
+                    origin: Origin::default(),
+                    expr: prev::Expr::Eq {
+                        left: Box::new(prev::Expr::Length {
+                            array: Box::new(prev::Expr::Array { elements: vec![] }),
+                        }),
+                        right: Box::new(prev::Expr::LitInt(0)),
+                    },
+                }],
+            }],
+            var_factory: VarFactory::new(),
+        };
+
+        assert!(type_check(program, &Options::default()).is_err());
+    }
+
+    #[test]
+    fn struct_scalarized() -> Result<()> {
+        let mut var_factory = VarFactory::new();
+        let p = var_factory.named("p".to_owned());
+
+        let program = prev::Program {
+            defs: vec![
+                prev::Definition::Struct {
+                    name: "point".to_owned(),
+                    fields: vec![
+                        prev::Field {
+                            name: "x".to_owned(),
+                            ty: prev::Type::Named("int".to_owned()),
+                        },
+                        prev::Field {
+                            name: "y".to_owned(),
+                            ty: prev::Type::Named("int".to_owned()),
+                        },
+                    ],
+                },
+                prev::Definition::Test {
+                    name: "test".to_owned(),
+                    directive: None,
+                    fails: false,
+                    budget: None,
+                    stmts: vec![
+                        prev::Statement::Let {
+                            var: p.clone(),
+                            expr: prev::Expr::Construct {
+                                name: "point".to_owned(),
+                                args: vec![prev::Expr::LitInt(1), prev::Expr::LitInt(2)],
+                            },
+                        },
+                        prev::Statement::AssertEq {
+                            origin: Origin::default(),
+                            left: prev::Expr::Field {
+                                name: "point".to_owned(),
+                                field: "y".to_owned(),
+                                expr: Box::new(prev::Expr::Variable(p)),
+                            },
+                            right: prev::Expr::LitInt(2),
+                        },
+                    ],
+                },
+            ],
+            var_factory,
+        };
+
+        let program = type_check(program, &Options::default())?;
+        let Definition::Test { stmts, .. } = &program.defs[0] else {
+            panic!("Expected test definition");
+        };
+
+        let Statement::Let { var: x, expr: Expr::LitInt(1) } = &stmts[0] else {
+            panic!("Expected p.x = 1");
+        };
+        let Statement::Let { var: y, expr: Expr::LitInt(2) } = &stmts[1] else {
+            panic!("Expected p.y = 2");
+        };
+        assert_ne!(x, y);
+        assert_eq!(
+            stmts[2],
+            Statement::AssertEq {
+                origin: Origin::default(),
+                left: Expr::Variable(y.clone()),
+                right: Expr::LitInt(2),
+            }
+        );
+        assert_eq!(stmts.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn struct_if_evaluates_condition_once() -> Result<()> {
+        let mut var_factory = VarFactory::new();
+        let p = var_factory.named("p".to_owned());
+        let point = |x, y| prev::Expr::Construct {
+            name: "point".to_owned(),
+            args: vec![prev::Expr::LitInt(x), prev::Expr::LitInt(y)],
+        };
+        let score = || prev::Expr::Score {
+            target: "#flag".to_owned(),
+            objective: "flags".to_owned(),
+        };
+
+        let program = prev::Program {
+            defs: vec![
+                prev::Definition::Struct {
+                    name: "point".to_owned(),
+                    fields: ["x", "y"]
+                        .into_iter()
+                        .map(|name| prev::Field {
+                            name: name.to_owned(),
+                            ty: prev::Type::Named("int".to_owned()),
+                        })
+                        .collect(),
+                },
+                prev::Definition::Test {
+                    name: "test".to_owned(),
+                    directive: None,
+                    fails: false,
+                    budget: None,
+                    stmts: vec![prev::Statement::Let {
+                        var: p,
+                        expr: prev::Expr::If {
+                            cond: Box::new(prev::Expr::Eq {
+                                left: Box::new(score()),
+                                right: Box::new(prev::Expr::LitInt(1)),
+                            }),
+                            thn: Box::new(point(1, 2)),
+                            els: Box::new(point(3, 4)),
+                        },
+                    }],
+                },
+            ],
+            var_factory,
+        };
+
+        let program = type_check(program, &Options::default())?;
+        let Definition::Test { stmts, .. } = &program.defs[0] else {
+            panic!("Expected test definition");
+        };
+        let [Statement::Let { expr: x, .. }, Statement::Let { expr: y, .. }] = stmts.as_slice() else {
+            panic!("Expected p.x and p.y to be bound");
+        };
+        let Expr::Let { var: cond, value, body } = x else {
+            panic!("Expected the condition to be bound before p.x");
+        };
+        assert!(matches!(value.as_ref(), Expr::Eq { .. }));
+        let choose = |thn, els| Expr::If {
+            cond: Box::new(Expr::Variable(cond.clone())),
+            thn: Box::new(Expr::LitInt(thn)),
+            els: Box::new(Expr::LitInt(els)),
+        };
+        assert_eq!(**body, choose(1, 3));
+        assert_eq!(*y, choose(2, 4));
+        Ok(())
+    }
+
+    #[test]
+    fn call_argument_mismatch() {
+        let mut var_factory = VarFactory::new();
+        let x = var_factory.named("x".to_owned());
+
+        let program = prev::Program {
+            defs: vec![
+                prev::Definition::Function {
+                    id: 0,
+                    name: "id".to_owned(),
+                    params: vec![(x.clone(), prev::Type::Named("int".to_owned()))],
+                    ret: prev::Type::Named("int".to_owned()),
+                    stmts: vec![],
+                    result: prev::Expr::Variable(x),
+                },
+                prev::Definition::Test {
+                    name: "test".to_owned(),
+                    directive: None,
+                    fails: false,
+                    budget: None,
+                    stmts: vec![prev::Statement::AssertEq {
+                        origin: Origin::default(),
+                        left: prev::Expr::Call {
+                            id: 0,
+                            args: vec![prev::Expr::LitBool(true)],
+                        },
+                        right: prev::Expr::LitInt(1),
+                    }],
+                },
+            ],
+            var_factory,
+        };
+
+        assert!(type_check(program, &Options::default()).is_err());
+    }
+
+    #[test]
+    fn assert_in_context() {
+        let program = prev::Program {
+            defs: vec![prev::Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![prev::Statement::Execute {
+                    context: Context::As("@e".to_owned()),
+                    stmts: vec![prev::Statement::Assert {
+                        origin: Origin::default(),
+                        expr: prev::Expr::LitBool(true),
+                    }],
+                }],
+            }],
+            var_factory: VarFactory::new(),
+        };
+
+        assert!(type_check(program, &Options::default()).is_err());
+    }
+
+    #[test]
+    fn return_checks() {
+        let function = |value: Option<prev::Expr>| prev::Program {
+            defs: vec![prev::Definition::Function {
+                id: 0,
+                name: "f".to_owned(),
+                params: vec![],
+                ret: prev::Type::Named("int".to_owned()),
+                stmts: vec![prev::Statement::Return { value }],
+                result: prev::Expr::LitInt(0),
+            }],
+            var_factory: VarFactory::new(),
+        };
+        let test = |stmt: prev::Statement| prev::Program {
+            defs: vec![prev::Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![stmt],
+            }],
+            var_factory: VarFactory::new(),
+        };
+
+        assert!(type_check(function(Some(prev::Expr::LitInt(1))), &Options::default()).is_ok());
+        assert!(type_check(function(Some(prev::Expr::LitBool(true))), &Options::default()).is_err());
+        assert!(type_check(function(None), &Options::default()).is_err());
+        assert!(type_check(test(prev::Statement::Return { value: None }), &Options::default()).is_ok());
+        let value = prev::Statement::Return { value: Some(prev::Expr::LitInt(1)) };
+        assert!(type_check(test(value), &Options::default()).is_err());
+        let in_context = prev::Statement::Execute {
+            context: Context::As("@e".to_owned()),
+            stmts: vec![prev::Statement::Return { value: None }],
+        };
+        assert!(type_check(test(in_context), &Options::default()).is_err());
+    }
+
+    #[test]
+    fn assert_in_handler() {
+        let program = prev::Program {
+            defs: vec![prev::Definition::Handler {
+                event: Event::Load,
+                stmts: vec![prev::Statement::Assert {
+                    origin: Origin::default(),
+                    expr: prev::Expr::LitBool(true),
+                }],
+            }],
+            var_factory: VarFactory::new(),
+        };
+
+        assert!(type_check(program, &Options::default()).is_err());
+    }
+
+    #[test]
+    fn invalid_objective() {
+        let score = |objective: &str| prev::Program {
+            defs: vec![prev::Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![prev::Statement::AssertEq {
+                    origin: Origin::default(),
+                    left: prev::Expr::Score {
+                        target: "@s".to_owned(),
+                        objective: objective.to_owned(),
+                    },
+                    right: prev::Expr::LitInt(0),
+                }],
+            }],
+            var_factory: VarFactory::new(),
+        };
+
+        assert!(type_check(score("kills"), &Options::default()).is_ok());
+        assert!(type_check(score("two words"), &Options::default()).is_err());
+        assert!(type_check(score(""), &Options::default()).is_err());
+    }
+
+    #[test]
+    fn random_bounds() {
+        let random = |lo: i64, hi: i64| prev::Program {
+            defs: vec![prev::Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![prev::Statement::AssertEq {
+                    origin: Origin::default(),
+                    left: prev::Expr::Random {
+                        lo: Box::new(prev::Expr::LitInt(lo)),
+                        hi: Box::new(prev::Expr::LitInt(hi)),
+                    },
+                    right: prev::Expr::LitInt(1),
+                }],
+            }],
+            var_factory: VarFactory::new(),
+        };
+
+        assert!(type_check(random(1, 6), &Options::default()).is_ok());
+        assert!(type_check(random(6, 6), &Options::default()).is_err());
+        assert!(type_check(random(6, 1), &Options::default()).is_err());
+        assert!(type_check(random(i32::MIN as i64, i32::MAX as i64), &Options::default()).is_err());
+        assert!(type_check(random(1, 6), &Options {
+            pack_format: 15,
+            ..Options::default()
+        }).is_err());
+    }
+
+    #[test]
+    fn arrays_need_macros() {
+        let array = || prev::Program {
+            defs: vec![prev::Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![prev::Statement::AssertEq {
+                    origin: Origin::default(),
+                    left: prev::Expr::Length {
+                        array: Box::new(prev::Expr::MakeArray {
+                            length: 2,
+                            fill: Box::new(prev::Expr::LitInt(0)),
+                        }),
+                    },
+                    right: prev::Expr::LitInt(2),
+                }],
+            }],
+            var_factory: VarFactory::new(),
+        };
+
+        assert!(type_check(array(), &Options::default()).is_ok());
+        assert!(type_check(array(), &Options {
+            pack_format: 15,
+            ..Options::default()
+        }).is_err());
+    }
+}
@@ -1,6 +1,9 @@
 use std::collections::HashMap;
 
+use anyhow::{anyhow, Result};
+
 use crate::parse as prev;
+pub use crate::parse::{Context, Directive, Event, Field, Fragment, NbtSource, Origin, Relation, Type};
 use crate::var::{Var, VarFactory};
 
 pub struct Program {
@@ -10,15 +13,59 @@ pub struct Program {
 
 #[derive(PartialEq, Eq, Debug)]
 pub enum Definition {
-    Test { name: String, stmts: Vec<Statement> },
+    Test {
+        name: String,
+        directive: Option<Directive>,
+        fails: bool,
+        budget: Option<i64>,
+        stmts: Vec<Statement>,
+    },
+    Struct { name: String, fields: Vec<Field> },
+    Function {
+        id: usize,
+        name: String,
+        params: Vec<(Var, Type)>,
+        ret: Type,
+        stmts: Vec<Statement>,
+        result: Expr,
+    },
+    Handler { event: Event, stmts: Vec<Statement> },
+    Setup { stmts: Vec<Statement> },
+    Teardown { stmts: Vec<Statement> },
+    Describe { name: String, defs: Vec<Definition> },
 }
 
 #[derive(PartialEq, Eq, Debug)]
 pub enum Statement {
-    Assert { expr: Expr },
-    AssertEq { left: Expr, right: Expr },
+    Assert { expr: Expr, origin: Origin },
+    AssertEq { left: Expr, right: Expr, origin: Origin },
+    AssertCmp { relation: Relation, left: Expr, right: Expr, origin: Origin },
+    AssertBetween { expr: Expr, lo: Expr, hi: Expr, origin: Origin },
     Command { text: String },
     Let { var: Var, expr: Expr },
+    ArraySet { array: Expr, index: Expr, value: Expr },
+    ArrayPush { array: Expr, value: Expr },
+    Execute { context: Context, stmts: Vec<Statement> },
+    SetScore { target: String, objective: String, value: Expr },
+    NbtSet { source: NbtSource, path: String, ty: String, scale: String, value: Expr },
+    After { ticks: i64, stmts: Vec<Statement> },
+    SetSeed { seed: i64 },
+    When { cond: Expr, stmts: Vec<Statement> },
+    Return { value: Option<Expr> },
+    Asm { template: Vec<Fragment>, vars: Vec<Var> },
+}
+
+impl Statement {
+    /// Whether this is one of the assertions, which only a test can make.
+    pub fn is_assertion(&self) -> bool {
+        matches!(
+            self,
+            Statement::Assert { .. }
+                | Statement::AssertEq { .. }
+                | Statement::AssertCmp { .. }
+                | Statement::AssertBetween { .. }
+        )
+    }
 }
 
 #[derive(PartialEq, Eq, Debug)]
@@ -50,93 +97,473 @@ pub enum Expr {
     Eq {
         left: Box<Expr>,
         right: Box<Expr>,
-    }
+    },
+    Array {
+        elements: Vec<Expr>,
+    },
+    MakeArray {
+        length: i64,
+        fill: Box<Expr>,
+    },
+    ArrayGet {
+        array: Box<Expr>,
+        index: Box<Expr>,
+    },
+    Length {
+        array: Box<Expr>,
+    },
+    Construct {
+        name: String,
+        args: Vec<Expr>,
+    },
+    Field {
+        name: String,
+        field: String,
+        expr: Box<Expr>,
+    },
+    Function {
+        id: usize,
+    },
+    Call {
+        id: usize,
+        args: Vec<Expr>,
+    },
+    CallIndirect {
+        function: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    Score {
+        target: String,
+        objective: String,
+    },
+    NbtGet {
+        source: NbtSource,
+        path: String,
+        scale: String,
+    },
+    Random {
+        lo: Box<Expr>,
+        hi: Box<Expr>,
+    },
 }
 
 type Env = HashMap<String, Var>;
+/// In the order they are defined, so that accessors are looked up the same way every time.
+type Structs = Vec<(String, Vec<Field>)>;
 
-pub fn uniquify(defs: Vec<prev::Definition>) -> Program {
+/// Everything defined at the top level, visible from every test and function.
+struct Globals {
+    structs: Structs,
+    functions: HashMap<String, usize>,
+}
+
+pub fn uniquify(defs: Vec<prev::Definition>) -> Result<Program> {
     let mut new_defs = Vec::new();
     let mut var_factory = VarFactory::new();
+    let mut globals = Globals {
+        structs: Structs::new(),
+        functions: HashMap::new(),
+    };
+    let (mut setups, mut teardowns) = (0, 0);
+    for def in &defs {
+        match def {
+            prev::Definition::Struct { name, fields } => {
+                if globals.structs.iter().any(|(defined, _)| defined == name) {
+                    return Err(anyhow!("Struct {name} is defined more than once"));
+                }
+                globals.structs.push((name.clone(), fields.clone()));
+            }
+            prev::Definition::Function { name, .. } => {
+                let id = globals.functions.len();
+                if globals.functions.insert(name.clone(), id).is_some() {
+                    return Err(anyhow!("Function {name} is defined more than once"));
+                }
+            }
+            prev::Definition::Setup { .. } => {
+                setups += 1;
+                if setups > 1 {
+                    return Err(anyhow!("setup is defined more than once"));
+                }
+            }
+            prev::Definition::Teardown { .. } => {
+                teardowns += 1;
+                if teardowns > 1 {
+                    return Err(anyhow!("teardown is defined more than once"));
+                }
+            }
+            prev::Definition::Test { .. }
+            | prev::Definition::Handler { .. }
+            | prev::Definition::Describe { .. } => {}
+        }
+    }
 
     for def in defs {
-        let prev::Definition::Test { name, stmts } = def;
-        new_defs.push(uniquify_test(&mut var_factory, name, stmts));
+        match def {
+            prev::Definition::Test {
+                name,
+                directive,
+                fails,
+                budget,
+                stmts,
+            } => new_defs.push(uniquify_test(&mut var_factory, &globals, name, directive, fails, budget, stmts)?),
+            prev::Definition::Describe { name, defs } => {
+                new_defs.push(uniquify_describe(&mut var_factory, &globals, name, defs)?)
+            }
+            prev::Definition::Struct { name, fields } => {
+                new_defs.push(Definition::Struct { name, fields })
+            }
+            prev::Definition::Function {
+                name,
+                params,
+                ret,
+                stmts,
+                result,
+            } => {
+                let mut env = Env::new();
+                let params = params
+                    .into_iter()
+                    .map(|param| {
+                        let var = var_factory.named(param.name.clone());
+                        env.insert(param.name, var.clone());
+                        (var, param.ty)
+                    })
+                    .collect();
+                let stmts = stmts
+                    .into_iter()
+                    .map(|stmt| uniquify_stmt(&mut var_factory, &mut env, &globals, stmt))
+                    .collect::<Result<_>>()?;
+                new_defs.push(Definition::Function {
+                    id: globals.functions[&name],
+                    name,
+                    params,
+                    ret,
+                    stmts,
+                    result: uniquify_expr(&env, &globals, result)?,
+                })
+            }
+            prev::Definition::Handler { event, stmts } => {
+                let mut env = Env::new();
+                let stmts = stmts
+                    .into_iter()
+                    .map(|stmt| uniquify_stmt(&mut var_factory, &mut env, &globals, stmt))
+                    .collect::<Result<_>>()?;
+                new_defs.push(Definition::Handler { event, stmts })
+            }
+            prev::Definition::Setup { stmts } => {
+                let mut env = Env::new();
+                let stmts = stmts
+                    .into_iter()
+                    .map(|stmt| uniquify_stmt(&mut var_factory, &mut env, &globals, stmt))
+                    .collect::<Result<_>>()?;
+                new_defs.push(Definition::Setup { stmts })
+            }
+            prev::Definition::Teardown { stmts } => {
+                let mut env = Env::new();
+                let stmts = stmts
+                    .into_iter()
+                    .map(|stmt| uniquify_stmt(&mut var_factory, &mut env, &globals, stmt))
+                    .collect::<Result<_>>()?;
+                new_defs.push(Definition::Teardown { stmts })
+            }
+        }
     }
 
-    Program {
+    Ok(Program {
         defs: new_defs,
         var_factory,
-    }
+    })
 }
 
 fn uniquify_test(
     var_factory: &mut VarFactory,
+    globals: &Globals,
     name: String,
+    directive: Option<Directive>,
+    fails: bool,
+    budget: Option<i64>,
     stmts: Vec<prev::Statement>,
-) -> Definition {
+) -> Result<Definition> {
     let mut env = Env::new();
     let mut new_stmts = Vec::new();
 
     for stmt in stmts {
-        new_stmts.push(match stmt {
-            prev::Statement::Assert { expr } => Statement::Assert {
-                expr: uniquify_expr(&env, expr),
-            },
-            prev::Statement::AssertEq { left, right } => Statement::AssertEq {
-                left: uniquify_expr(&env, left),
-                right: uniquify_expr(&env, right),
-            },
-            prev::Statement::Command { text } => Statement::Command { text },
-            prev::Statement::Let {
-                variable_name,
-                expr,
-            } => {
-                let expr = uniquify_expr(&env, expr);
-                let var = var_factory.named(variable_name.clone());
-                env.insert(variable_name, var.clone());
-                Statement::Let { var, expr }
-            }
-        });
+        new_stmts.push(uniquify_stmt(var_factory, &mut env, globals, stmt)?);
     }
 
-    Definition::Test {
+    Ok(Definition::Test {
         name,
+        directive,
+        fails,
+        budget,
         stmts: new_stmts,
-    }
+    })
+}
+
+fn uniquify_describe(
+    var_factory: &mut VarFactory,
+    globals: &Globals,
+    name: String,
+    defs: Vec<prev::Definition>,
+) -> Result<Definition> {
+    let defs = defs
+        .into_iter()
+        .map(|def| match def {
+            prev::Definition::Test {
+                name,
+                directive,
+                fails,
+                budget,
+                stmts,
+            } => uniquify_test(var_factory, globals, name, directive, fails, budget, stmts),
+            prev::Definition::Describe { name, defs } => uniquify_describe(var_factory, globals, name, defs),
+            _ => unreachable!("describes only hold tests"),
+        })
+        .collect::<Result<_>>()?;
+    Ok(Definition::Describe { name, defs })
 }
 
-fn uniquify_expr(env: &Env, expr: prev::Expr) -> Expr {
-    match expr {
+fn uniquify_stmt(
+    var_factory: &mut VarFactory,
+    env: &mut Env,
+    globals: &Globals,
+    stmt: prev::Statement,
+) -> Result<Statement> {
+    Ok(match stmt {
+        prev::Statement::Assert { expr, origin } => Statement::Assert {
+            expr: uniquify_expr(env, globals, expr)?,
+            origin,
+        },
+        prev::Statement::AssertEq { left, right, origin } => Statement::AssertEq {
+            left: uniquify_expr(env, globals, left)?,
+            right: uniquify_expr(env, globals, right)?,
+            origin,
+        },
+        prev::Statement::AssertCmp {
+            relation,
+            left,
+            right,
+            origin,
+        } => Statement::AssertCmp {
+            relation,
+            left: uniquify_expr(env, globals, left)?,
+            right: uniquify_expr(env, globals, right)?,
+            origin,
+        },
+        prev::Statement::AssertBetween { expr, lo, hi, origin } => Statement::AssertBetween {
+            expr: uniquify_expr(env, globals, expr)?,
+            lo: uniquify_expr(env, globals, lo)?,
+            hi: uniquify_expr(env, globals, hi)?,
+            origin,
+        },
+        prev::Statement::Command { text } => Statement::Command { text },
+        prev::Statement::Let {
+            variable_name,
+            expr,
+        } => {
+            let expr = uniquify_expr(env, globals, expr)?;
+            let var = var_factory.named(variable_name.clone());
+            env.insert(variable_name, var.clone());
+            Statement::Let { var, expr }
+        }
+        prev::Statement::ArraySet {
+            array,
+            index,
+            value,
+        } => Statement::ArraySet {
+            array: uniquify_expr(env, globals, array)?,
+            index: uniquify_expr(env, globals, index)?,
+            value: uniquify_expr(env, globals, value)?,
+        },
+        prev::Statement::ArrayPush { array, value } => Statement::ArrayPush {
+            array: uniquify_expr(env, globals, array)?,
+            value: uniquify_expr(env, globals, value)?,
+        },
+        prev::Statement::SetScore {
+            target,
+            objective,
+            value,
+        } => Statement::SetScore {
+            target,
+            objective,
+            value: uniquify_expr(env, globals, value)?,
+        },
+        prev::Statement::NbtSet {
+            source,
+            path,
+            ty,
+            scale,
+            value,
+        } => Statement::NbtSet {
+            source,
+            path,
+            ty,
+            scale,
+            value: uniquify_expr(env, globals, value)?,
+        },
+        prev::Statement::Execute { context, stmts } => {
+            // The block runs once for each entity it selects, so a let of a name from outside
+            // writes to that variable: each run sees the last one's write, and so does the code
+            // after the block. Names first bound inside go out of scope at its end.
+            let outer = env.clone();
+            let mut env = env.clone();
+            Statement::Execute {
+                context,
+                stmts: stmts
+                    .into_iter()
+                    .map(|stmt| match stmt {
+                        prev::Statement::Let {
+                            variable_name,
+                            expr,
+                        } if outer.contains_key(&variable_name) => Ok(Statement::Let {
+                            var: outer[&variable_name].clone(),
+                            expr: uniquify_expr(&env, globals, expr)?,
+                        }),
+                        stmt => uniquify_stmt(var_factory, &mut env, globals, stmt),
+                    })
+                    .collect::<Result<_>>()?,
+            }
+        }
+        prev::Statement::SetSeed { seed } => Statement::SetSeed { seed },
+        prev::Statement::When { cond, stmts } => {
+            let cond = uniquify_expr(env, globals, cond)?;
+            let mut env = env.clone();
+            Statement::When {
+                cond,
+                stmts: stmts
+                    .into_iter()
+                    .map(|stmt| uniquify_stmt(var_factory, &mut env, globals, stmt))
+                    .collect::<Result<_>>()?,
+            }
+        }
+        prev::Statement::Asm { template, vars } => Statement::Asm {
+            template,
+            vars: vars
+                .into_iter()
+                .map(|name| env.get(&name).cloned().ok_or_else(|| anyhow!("Unbound variable {name}")))
+                .collect::<Result<_>>()?,
+        },
+        prev::Statement::Return { value } => Statement::Return {
+            value: value.map(|value| uniquify_expr(env, globals, value)).transpose()?,
+        },
+        prev::Statement::After { ticks, stmts } => {
+            let mut env = env.clone();
+            Statement::After {
+                ticks,
+                stmts: stmts
+                    .into_iter()
+                    .map(|stmt| uniquify_stmt(var_factory, &mut env, globals, stmt))
+                    .collect::<Result<_>>()?,
+            }
+        }
+    })
+}
+
+fn uniquify_expr(env: &Env, globals: &Globals, expr: prev::Expr) -> Result<Expr> {
+    let boxed = |expr: Box<prev::Expr>| -> Result<Box<Expr>> {
+        Ok(Box::new(uniquify_expr(env, globals, *expr)?))
+    };
+
+    Ok(match expr {
         prev::Expr::LitBool(b) => Expr::LitBool(b),
         prev::Expr::LitInt(i) => Expr::LitInt(i),
-        prev::Expr::Variable(name) => Expr::Variable(env[&name].clone()),
+        prev::Expr::Variable(name) => match (env.get(&name), globals.functions.get(&name)) {
+            (Some(var), _) => Expr::Variable(var.clone()),
+            (None, Some(id)) => Expr::Function { id: *id },
+            (None, None) => return Err(anyhow!("Unbound variable {name}")),
+        },
         prev::Expr::Plus { left, right } => Expr::Plus {
-            left: Box::new(uniquify_expr(env, *left)),
-            right: Box::new(uniquify_expr(env, *right)),
+            left: boxed(left)?,
+            right: boxed(right)?,
         },
         prev::Expr::Minus { left, right } => Expr::Minus {
-            left: Box::new(uniquify_expr(env, *left)),
-            right: Box::new(uniquify_expr(env, *right)),
+            left: boxed(left)?,
+            right: boxed(right)?,
         },
         prev::Expr::Times { left, right } => Expr::Times {
-            left: Box::new(uniquify_expr(env, *left)),
-            right: Box::new(uniquify_expr(env, *right)),
+            left: boxed(left)?,
+            right: boxed(right)?,
         },
         prev::Expr::Divide { left, right } => Expr::Divide {
-            left: Box::new(uniquify_expr(env, *left)),
-            right: Box::new(uniquify_expr(env, *right)),
+            left: boxed(left)?,
+            right: boxed(right)?,
         },
         prev::Expr::If { cond, thn, els } => Expr::If {
-            cond: Box::new(uniquify_expr(env, *cond)),
-            thn: Box::new(uniquify_expr(env, *thn)),
-            els: Box::new(uniquify_expr(env, *els)),
+            cond: boxed(cond)?,
+            thn: boxed(thn)?,
+            els: boxed(els)?,
         },
         prev::Expr::Eq { left, right } => Expr::Eq {
-            left: Box::new(uniquify_expr(env, *left)),
-            right: Box::new(uniquify_expr(env, *right)),
+            left: boxed(left)?,
+            right: boxed(right)?,
+        },
+        prev::Expr::Array { elements } => Expr::Array {
+            elements: elements
+                .into_iter()
+                .map(|e| uniquify_expr(env, globals, e))
+                .collect::<Result<_>>()?,
+        },
+        prev::Expr::MakeArray { length, fill } => Expr::MakeArray {
+            length,
+            fill: boxed(fill)?,
+        },
+        prev::Expr::ArrayGet { array, index } => Expr::ArrayGet {
+            array: boxed(array)?,
+            index: boxed(index)?,
+        },
+        prev::Expr::Length { array } => Expr::Length {
+            array: boxed(array)?,
+        },
+        prev::Expr::Score { target, objective } => Expr::Score { target, objective },
+        prev::Expr::NbtGet { source, path, scale } => Expr::NbtGet { source, path, scale },
+        prev::Expr::Random { lo, hi } => Expr::Random {
+            lo: boxed(lo)?,
+            hi: boxed(hi)?,
+        },
+        prev::Expr::Apply { name, args } => {
+            let args = args
+                .into_iter()
+                .map(|e| uniquify_expr(env, globals, e))
+                .collect::<Result<_>>()?;
+            uniquify_apply(env, globals, name, args)?
+        }
+    })
+}
+
+/// Resolves what is being applied: a local holding a function, a function defined with `defn`,
+/// or one of the accessors `defstruct` implicitly defines, `make-<struct>` and `<struct>-<field>`.
+fn uniquify_apply(env: &Env, globals: &Globals, name: String, mut args: Vec<Expr>) -> Result<Expr> {
+    if let Some(var) = env.get(&name) {
+        return Ok(Expr::CallIndirect {
+            function: Box::new(Expr::Variable(var.clone())),
+            args,
+        });
+    }
+    if let Some(id) = globals.functions.get(&name) {
+        return Ok(Expr::Call { id: *id, args });
+    }
+
+    for (struct_name, fields) in &globals.structs {
+        if name.strip_prefix("make-") == Some(struct_name) {
+            return Ok(Expr::Construct {
+                name: struct_name.clone(),
+                args,
+            });
+        }
+
+        let field = fields.iter().find(|field| name == format!("{struct_name}-{}", field.name));
+        if let Some(field) = field {
+            if args.len() != 1 {
+                return Err(anyhow!("{name} expects 1 argument but got {}", args.len()));
+            }
+            return Ok(Expr::Field {
+                name: struct_name.clone(),
+                field: field.name.clone(),
+                expr: Box::new(args.remove(0)),
+            });
         }
     }
+
+    Err(anyhow!("Unknown function {name}"))
 }
 
 #[cfg(test)]
@@ -148,19 +575,25 @@ mod tests {
     fn simple() {
         let defs = vec![prev::Definition::Test {
             name: "test".to_owned(),
+            directive: None,
+            fails: false,
+            budget: None,
             stmts: vec![
                 prev::Statement::Let {
                     variable_name: "x".to_owned(),
                     expr: prev::Expr::LitBool(true),
                 },
                 prev::Statement::Assert {
+                    origin: Origin::default(),
                     expr: prev::Expr::Variable("x".to_owned()),
                 },
             ],
         }];
 
-        let program = uniquify(defs);
-        let Definition::Test { stmts, .. } = &program.defs[0];
+        let program = uniquify(defs).unwrap();
+        let Definition::Test { stmts, .. } = &program.defs[0] else {
+            panic!("Expected test definition");
+        };
 
         let x1 = if let Statement::Let { var, expr: _ } = &stmts[0] {
             var
@@ -170,6 +603,7 @@ mod tests {
 
         if let Statement::Assert {
             expr: Expr::Variable(x2),
+            ..
         } = &stmts[1]
         {
             assert_eq!(x1, x2);
@@ -180,12 +614,16 @@ mod tests {
     fn shadowing() {
         let defs = vec![prev::Definition::Test {
             name: "test".to_owned(),
+            directive: None,
+            fails: false,
+            budget: None,
             stmts: vec![
                 prev::Statement::Let {
                     variable_name: "x".to_owned(),
                     expr: prev::Expr::LitBool(true),
                 },
                 prev::Statement::Assert {
+                    origin: Origin::default(),
                     expr: prev::Expr::Variable("x".to_owned()),
                 },
                 prev::Statement::Let {
@@ -193,13 +631,16 @@ mod tests {
                     expr: prev::Expr::LitBool(true),
                 },
                 prev::Statement::Assert {
+                    origin: Origin::default(),
                     expr: prev::Expr::Variable("x".to_owned()),
                 },
             ],
         }];
 
-        let program = uniquify(defs);
-        let Definition::Test { stmts, .. } = &program.defs[0];
+        let program = uniquify(defs).unwrap();
+        let Definition::Test { stmts, .. } = &program.defs[0] else {
+            panic!("Expected test definition");
+        };
 
         let x1 = if let Statement::Let { var, expr: _ } = &stmts[0] {
             var
@@ -209,6 +650,7 @@ mod tests {
 
         if let Statement::Assert {
             expr: Expr::Variable(x2),
+            ..
         } = &stmts[1]
         {
             assert_eq!(x1, x2);
@@ -222,10 +664,228 @@ mod tests {
 
         if let Statement::Assert {
             expr: Expr::Variable(x4),
+            ..
         } = &stmts[3]
         {
             assert_eq!(x3, x4);
             assert_ne!(x1, x3);
         }
     }
+
+    #[test]
+    fn struct_accessors() {
+        let defs = vec![
+            prev::Definition::Struct {
+                name: "point".to_owned(),
+                fields: vec![
+                    Field {
+                        name: "x".to_owned(),
+                        ty: Type::Named("int".to_owned()),
+                    },
+                    Field {
+                        name: "y".to_owned(),
+                        ty: Type::Named("int".to_owned()),
+                    },
+                ],
+            },
+            prev::Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![prev::Statement::AssertEq {
+                    origin: Origin::default(),
+                    left: prev::Expr::Apply {
+                        name: "point-y".to_owned(),
+                        args: vec![prev::Expr::Apply {
+                            name: "make-point".to_owned(),
+                            args: vec![prev::Expr::LitInt(1), prev::Expr::LitInt(2)],
+                        }],
+                    },
+                    right: prev::Expr::LitInt(2),
+                }],
+            },
+        ];
+
+        let program = uniquify(defs).unwrap();
+        let Definition::Test { stmts, .. } = &program.defs[1] else {
+            panic!("Expected test definition");
+        };
+
+        assert_eq!(
+            stmts[0],
+            Statement::AssertEq {
+                origin: Origin::default(),
+                left: Expr::Field {
+                    name: "point".to_owned(),
+                    field: "y".to_owned(),
+                    expr: Box::new(Expr::Construct {
+                        name: "point".to_owned(),
+                        args: vec![Expr::LitInt(1), Expr::LitInt(2)],
+                    }),
+                },
+                right: Expr::LitInt(2),
+            }
+        );
+    }
+
+    #[test]
+    fn duplicate_struct() {
+        let point = |fields: &[&str]| prev::Definition::Struct {
+            name: "point".to_owned(),
+            fields: fields
+                .iter()
+                .map(|name| Field {
+                    name: (*name).to_owned(),
+                    ty: Type::Named("int".to_owned()),
+                })
+                .collect(),
+        };
+        let defs = vec![point(&["x", "y"]), point(&["x"])];
+
+        let Err(error) = uniquify(defs) else {
+            panic!("Expected a duplicate struct to be rejected");
+        };
+        assert_eq!(error.to_string(), "Struct point is defined more than once");
+    }
+
+    #[test]
+    fn unknown_function() {
+        let defs = vec![prev::Definition::Test {
+            name: "test".to_owned(),
+            directive: None,
+            fails: false,
+            budget: None,
+            stmts: vec![prev::Statement::Assert {
+                origin: Origin::default(),
+                expr: prev::Expr::Apply {
+                    name: "make-point".to_owned(),
+                    args: vec![],
+                },
+            }],
+        }];
+
+        assert!(uniquify(defs).is_err());
+    }
+
+    #[test]
+    fn function_values() {
+        let int = || Type::Named("int".to_owned());
+        let defs = vec![
+            prev::Definition::Function {
+                name: "id".to_owned(),
+                params: vec![prev::Param {
+                    name: "x".to_owned(),
+                    ty: int(),
+                }],
+                ret: int(),
+                stmts: vec![],
+                result: prev::Expr::Variable("x".to_owned()),
+            },
+            prev::Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts: vec![
+                    prev::Statement::Let {
+                        variable_name: "f".to_owned(),
+                        expr: prev::Expr::Variable("id".to_owned()),
+                    },
+                    prev::Statement::AssertEq {
+                        origin: Origin::default(),
+                        left: prev::Expr::Apply {
+                            name: "f".to_owned(),
+                            args: vec![prev::Expr::LitInt(1)],
+                        },
+                        right: prev::Expr::Apply {
+                            name: "id".to_owned(),
+                            args: vec![prev::Expr::LitInt(1)],
+                        },
+                    },
+                ],
+            },
+        ];
+
+        let program = uniquify(defs).unwrap();
+        let Definition::Test { stmts, .. } = &program.defs[1] else {
+            panic!("Expected test definition");
+        };
+        let Statement::Let { var: f, expr: Expr::Function { id: 0 } } = &stmts[0] else {
+            panic!("Expected f to be bound to function 0");
+        };
+
+        assert_eq!(
+            stmts[1],
+            Statement::AssertEq {
+                origin: Origin::default(),
+                left: Expr::CallIndirect {
+                    function: Box::new(Expr::Variable(f.clone())),
+                    args: vec![Expr::LitInt(1)],
+                },
+                right: Expr::Call {
+                    id: 0,
+                    args: vec![Expr::LitInt(1)],
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn context_blocks_write_outer_variables() {
+        let test = |stmts| {
+            vec![prev::Definition::Test {
+                name: "test".to_owned(),
+                directive: None,
+                fails: false,
+                budget: None,
+                stmts,
+            }]
+        };
+        let let_int = |name: &str, value| prev::Statement::Let {
+            variable_name: name.to_owned(),
+            expr: prev::Expr::LitInt(value),
+        };
+        let execute = |stmts| prev::Statement::Execute {
+            context: Context::As("@e".to_owned()),
+            stmts,
+        };
+        let assert_var = |name: &str| prev::Statement::Assert {
+            origin: Origin::default(),
+            expr: prev::Expr::Variable(name.to_owned()),
+        };
+
+        let program = uniquify(test(vec![
+            let_int("x", 1),
+            execute(vec![let_int("x", 2)]),
+            assert_var("x"),
+        ]))
+        .unwrap();
+        let Definition::Test { stmts, .. } = &program.defs[0] else {
+            panic!("Expected test definition");
+        };
+        let Statement::Let { var: x, .. } = &stmts[0] else {
+            panic!("Expected let statement");
+        };
+        let Statement::Execute { stmts: inner, .. } = &stmts[1] else {
+            panic!("Expected execute statement");
+        };
+        assert_eq!(
+            inner[0],
+            Statement::Let {
+                var: x.clone(),
+                expr: Expr::LitInt(2)
+            }
+        );
+        assert_eq!(
+            stmts[2],
+            Statement::Assert {
+                origin: Origin::default(),
+                expr: Expr::Variable(x.clone())
+            }
+        );
+
+        // Names first bound inside the block go out of scope at its end.
+        assert!(uniquify(test(vec![execute(vec![let_int("y", 2)]), assert_var("y")])).is_err());
+    }
 }
@@ -1,6 +1,43 @@
-pub fn escape(s: &str) -> String {
-    let s = s.to_owned();
-    let s = s.replace("\\", "\\\\");
-    let s = s.replace("\"", "\\\"");
-    s
+/// Where a string is written, which decides what has to be escaped in it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quoting {
+    /// Between the quotes of an MCML string, when showing source back.
+    Source,
+    /// Between the quotes of a JSON string, like the text of a `tellraw`.
+    Json,
+    /// A command written as is. Line breaks would end it early, so they are written the way the
+    /// JSON and SNBT strings they can only appear in read them. Quotes are left to the author.
+    Command,
+}
+
+pub fn escape(s: &str, quoting: Quoting) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match (c, quoting) {
+            ('\\' | '"', Quoting::Source | Quoting::Json) => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            ('\n', _) => escaped.push_str("\\n"),
+            ('\t', _) => escaped.push_str("\\t"),
+            ('\r', _) => escaped.push_str("\\r"),
+            (c, Quoting::Source) if c.is_control() => escaped.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            (c, _) if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            (c, _) => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quoting() {
+        let s = "say \"hi\"\\\n\u{1}é";
+        assert_eq!(escape(s, Quoting::Source), r#"say \"hi\"\\\n\u{1}é"#);
+        assert_eq!(escape(s, Quoting::Json), r#"say \"hi\"\\\n\u0001é"#);
+        assert_eq!(escape(s, Quoting::Command), r#"say "hi"\\n\u0001é"#);
+    }
 }
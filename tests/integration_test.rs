@@ -1,10 +1,11 @@
 use anyhow::{anyhow, Context, Result};
 use std::process;
 use std::{ffi::OsStr, fs};
-use tap_parser::{TapParser, TapStatement};
+use tap_parser::{DirectiveKind, TapParser, TapStatement, TapTest};
 use tempdir::TempDir;
 
-fn mctest(path: &impl AsRef<OsStr>) -> Result<()> {
+/// Runs the datapack at `path` and returns the TAP document it reports.
+fn mctest_output(path: &impl AsRef<OsStr>) -> Result<String> {
     let output = process::Command::new("../mctest/target/release/mctest")
         .arg(path)
         .output()
@@ -17,15 +18,19 @@ fn mctest(path: &impl AsRef<OsStr>) -> Result<()> {
         ));
     }
 
-    let document = String::from_utf8(output.stdout)?;
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+fn mctest(path: &impl AsRef<OsStr>) -> Result<()> {
+    let document = mctest_output(path)?;
     let tap = TapParser::new()
         .parse(&document)
         .with_context(|| format!("Failed to parse mctest output:\n{}<EOF>", &document))?;
 
     for stmt in tap {
         match stmt {
-            TapStatement::TestPoint(t) => assert!(t.result),
-            TapStatement::Subtest(t) => assert!(t.ending.result),
+            TapStatement::TestPoint(t) => assert!(passed(&t)),
+            TapStatement::Subtest(t) => assert!(passed(&t.ending)),
             _ => {}
         }
     }
@@ -33,6 +38,11 @@ fn mctest(path: &impl AsRef<OsStr>) -> Result<()> {
     Ok(())
 }
 
+/// Tests that are still to do are allowed to fail, as TAP consumers treat them.
+fn passed(test: &TapTest) -> bool {
+    test.result || matches!(&test.directive, Some(d) if d.kind == DirectiveKind::Todo)
+}
+
 fn run_test(program: &str) -> Result<()> {
     let tempdir = TempDir::new("mcml_test")?;
     let pack_path = tempdir.path().join("pack.zip");
@@ -43,6 +53,15 @@ fn run_test(program: &str) -> Result<()> {
     Ok(())
 }
 
+/// Compiles and runs `program`, returning its TAP document without checking the results.
+fn run_test_output(program: &str) -> Result<String> {
+    let tempdir = TempDir::new("mcml_test")?;
+    let pack_path = tempdir.path().join("pack.zip");
+    let contents = mcml::compile(program)?.bytes();
+    fs::write(&pack_path, &contents?)?;
+    mctest_output(&pack_path)
+}
+
 #[test]
 fn literals() -> Result<()> {
     run_test(include_str!("literals.mcml"))
@@ -54,6 +73,18 @@ fn fail() {
     run_test(include_str!("fail.mcml")).unwrap();
 }
 
+#[test]
+fn bail() -> Result<()> {
+    let document = run_test_output(include_str!("bail.mcml"))?;
+    let lines: Vec<_> = document.lines().collect();
+    assert!(
+        lines.contains(&"Bail out! unbalances the stack left the stack unbalanced"),
+        "{document}"
+    );
+    assert!(!document.contains("never runs"), "{document}");
+    Ok(())
+}
+
 #[test]
 fn hello_world() -> Result<()> {
     run_test(include_str!("commands.mcml"))
@@ -74,8 +105,130 @@ fn cond() -> Result<()> {
     run_test(include_str!("cond.mcml"))
 }
 
+#[test]
+fn arrays() -> Result<()> {
+    run_test(include_str!("arrays.mcml"))
+}
+
+#[test]
+fn structs() -> Result<()> {
+    run_test(include_str!("structs.mcml"))
+}
+
+#[test]
+fn functions() -> Result<()> {
+    run_test(include_str!("functions.mcml"))
+}
+
+#[test]
+fn tail_calls() -> Result<()> {
+    run_test(include_str!("tail_calls.mcml"))
+}
+
+#[test]
+fn context() -> Result<()> {
+    run_test(include_str!("context.mcml"))
+}
+
+#[test]
+fn scores() -> Result<()> {
+    run_test(include_str!("scores.mcml"))
+}
+
+#[test]
+fn nbt() -> Result<()> {
+    run_test(include_str!("nbt.mcml"))
+}
+
+#[test]
+fn handlers() -> Result<()> {
+    run_test(include_str!("handlers.mcml"))
+}
+
+#[test]
+fn after() -> Result<()> {
+    run_test(include_str!("after.mcml"))
+}
+
+#[test]
+fn random() -> Result<()> {
+    run_test(include_str!("random.mcml"))
+}
+
+#[test]
+fn early_return() -> Result<()> {
+    run_test(include_str!("early_return.mcml"))
+}
+
+#[test]
+fn asm() -> Result<()> {
+    run_test(include_str!("asm.mcml"))
+}
+
+#[test]
+fn strings() -> Result<()> {
+    run_test(include_str!("strings.mcml"))
+}
+
+#[test]
+fn fixtures() -> Result<()> {
+    run_test(include_str!("fixtures.mcml"))
+}
+
+#[test]
+fn describe() -> Result<()> {
+    run_test(include_str!("describe.mcml"))
+}
+
+#[test]
+fn directives() -> Result<()> {
+    run_test(include_str!("directives.mcml"))
+}
+
+#[test]
+fn test_each() -> Result<()> {
+    run_test(include_str!("test_each.mcml"))
+}
+
+#[test]
+fn test_fails() -> Result<()> {
+    run_test(include_str!("test_fails.mcml"))
+}
+
+#[test]
+fn comparisons() -> Result<()> {
+    run_test(include_str!("comparisons.mcml"))
+}
+
 #[ignore]
 #[test]
 fn collatz() -> Result<()> {
     run_test(include_str!("collatz.mcml"))
 }
+
+#[test]
+fn budget() -> Result<()> {
+    run_test(include_str!("budget.mcml"))
+}
+
+#[test]
+fn budget_exceeded() -> Result<()> {
+    let document = run_test_output(include_str!("budget_exceeded.mcml"))?;
+    let lines: Vec<_> = document.lines().collect();
+    for expected in [
+        "not ok - runs away # exceeded budget",
+        "ok - nothing runs once out of budget but the teardown",
+        "    not ok - also runs away # TODO exceeded budget",
+        "ok - a group",
+        "ok - still runs",
+        "<EOF>",
+    ] {
+        assert!(lines.contains(&expected), "Expected {expected:?} in:\n{document}");
+    }
+    Ok(())
+}
+
+#[test]
+fn frames() -> Result<()> {
+    run_test(include_str!("frames.mcml"))
+}